@@ -0,0 +1,134 @@
+//! 帧渲染的基准测试
+//!
+//! 运行 `cargo bench --bench render`，由 criterion 统计每帧的耗时，并输出每帧的内存分配次数。
+//! 使用固定的主题、帧索引和 80x24 终端，结果只随代码变化。
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use nyancat::animation::{
+    ColorMode, DEFAULT_COUNTER_FORMAT, Flip, RenderOptions, RenderSize, Renderer, THEMES,
+    build_frame,
+};
+
+/// 统计分配次数的分配器
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// 终端大小
+const WIDTH: u16 = 80;
+const HEIGHT: u16 = 24;
+/// 渲染的帧索引
+const FRAME: usize = 3;
+
+/// 由 criterion 计时，同时统计 `run` 每次的平均分配次数，在计时结果之后输出
+fn bench(c: &mut Criterion, name: &str, mut run: impl FnMut()) {
+    let mut allocations = 0;
+    let mut iterations = 0;
+    c.bench_function(name, |b| {
+        b.iter_custom(|iters| {
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            let start = Instant::now();
+            for _ in 0..iters {
+                run();
+            }
+            let elapsed = start.elapsed();
+            allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+            iterations += iters;
+            elapsed
+        });
+    });
+    // 被命令行过滤掉的测试不会运行
+    if iterations > 0 {
+        println!(
+            "{name:<32} {:>8.1} allocs/frame",
+            allocations as f64 / iterations as f64
+        );
+    }
+}
+
+fn render(c: &mut Criterion) {
+    let theme = &THEMES[0];
+    let size = RenderSize::new((theme.width, theme.height), WIDTH, HEIGHT, Renderer::Block);
+    let start_time = tokio::time::Instant::now();
+
+    for color_mode in ColorMode::ALL {
+        let options = RenderOptions {
+            theme,
+            color_mode,
+            no_clear: false,
+            // 计数器的文本随时间变化，不计入
+            no_counter: true,
+            counter_format: DEFAULT_COUNTER_FORMAT,
+            flip: Flip::default(),
+            center_counter: false,
+            plain_counter: false,
+            color_phase: 0,
+            crlf: false,
+            renderer: Renderer::Block,
+        };
+
+        // 与 Telnet 和 WebSocket 一样在帧之间复用缓冲区
+        let mut frame_data = String::new();
+        bench(c, &format!("build_frame/{}", color_mode.name()), || {
+            frame_data.clear();
+            build_frame(
+                &mut frame_data,
+                black_box(&options),
+                WIDTH,
+                size,
+                FRAME,
+                start_time,
+            );
+            black_box(&frame_data);
+        });
+
+        // 只渲染可见范围内的单元格，与 build_frame 相同
+        let frame = theme.frames.get(FRAME);
+        let rows = frame
+            .rows()
+            .skip(size.min_row)
+            .take(size.max_row - size.min_row);
+        bench(c, &format!("render_color/{}", color_mode.name()), || {
+            let mut len = 0;
+            for row in rows.clone() {
+                for c in row
+                    .chars()
+                    .skip(size.min_col)
+                    .take(size.max_col - size.min_col)
+                {
+                    len += theme.render_color(black_box(c), color_mode).len();
+                }
+            }
+            black_box(len);
+        });
+    }
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
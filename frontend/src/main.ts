@@ -25,10 +25,19 @@ progressAddon.onChange((state: IProgressState) => {
     console.log(state);
 });
 
+// 将页面地址中的 theme、token 参数传给服务端
+const pageParams = new URLSearchParams(window.location.search);
+const wsParams = new URLSearchParams();
+for (const key of ["theme", "token"]) {
+    const value = pageParams.get(key);
+    if (value) {
+        wsParams.set(key, value);
+    }
+}
 const wsUrl = `${window.location.protocol === "https:" ? "wss" : "ws"}://${
     window.location.host
-}/ws`;
-const ws = new WebSocket(wsUrl);
+}/ws${wsParams.toString() ? `?${wsParams}` : ""}`;
+const ws = new WebSocket(wsUrl, ['nyancat.v1']);
 ws.onopen = () => {
     console.log("connected");
 };
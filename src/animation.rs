@@ -1,5 +1,17 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, Range},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
 use colored::{Color, Colorize};
 use tokio::time::Instant;
+use tracing::warn;
+
+use crate::palette::{CustomPalette, PaletteColor};
 
 const FRAME0: &[&str] = &[
     ",,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,,.,,,,,,,,,,,,,,,,,,,,,,,,,",
@@ -813,28 +825,1449 @@ pub const FRAMES: &[&[&str]] = &[
 pub const FRAME_WIDTH: usize = 64;
 pub const FRAME_HEIGHT: usize = 64;
 
-/// 渲染字符为对应的颜色代码
-pub fn render_color(character: char) -> &'static str {
+/// 主题的帧序列
+///
+/// 内置主题的帧是编译进程序的静态数据。从文件加载或由主题变换生成的帧按引用计数共享，
+/// 不再有主题使用时释放，`--frames-dir` 重新加载后旧的帧不会一直占用内存。
+#[derive(Clone)]
+pub enum Frames {
+    Static(&'static [&'static [&'static str]]),
+    Shared(Arc<[Box<[Box<str>]>]>),
+}
+
+impl Frames {
+    /// 由加载或生成的帧创建
+    pub fn new(frames: Vec<Vec<String>>) -> Self {
+        Self::Shared(
+            frames
+                .into_iter()
+                .map(|frame| frame.into_iter().map(String::into_boxed_str).collect())
+                .collect(),
+        )
+    }
+
+    /// 帧数
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Static(frames) => frames.len(),
+            Self::Shared(frames) => frames.len(),
+        }
+    }
+
+    /// 是否没有帧
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 第 `index` 帧，超出范围时 panic
+    pub fn get(&self, index: usize) -> Frame<'_> {
+        match self {
+            Self::Static(frames) => Frame::Static(frames[index]),
+            Self::Shared(frames) => Frame::Shared(&frames[index]),
+        }
+    }
+
+    /// 按顺序遍历所有帧
+    pub fn iter(&self) -> impl Iterator<Item = Frame<'_>> {
+        (0..self.len()).map(|index| self.get(index))
+    }
+}
+
+/// 一帧中的所有行
+#[derive(Clone, Copy)]
+pub enum Frame<'a> {
+    Static(&'static [&'static str]),
+    Shared(&'a [Box<str>]),
+}
+
+impl<'a> Frame<'a> {
+    /// 行数
+    pub fn len(self) -> usize {
+        match self {
+            Self::Static(rows) => rows.len(),
+            Self::Shared(rows) => rows.len(),
+        }
+    }
+
+    /// 是否没有行
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    /// 第 `index` 行，超出范围时 panic
+    pub fn row(self, index: usize) -> &'a str {
+        match self {
+            Self::Static(rows) => rows[index],
+            Self::Shared(rows) => &rows[index],
+        }
+    }
+
+    /// 按顺序遍历所有行
+    pub fn rows(self) -> FrameRows<'a> {
+        FrameRows {
+            frame: self,
+            range: 0..self.len(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Frame<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.rows()).finish()
+    }
+}
+
+impl<const N: usize> PartialEq<[&str; N]> for Frame<'_> {
+    fn eq(&self, other: &[&str; N]) -> bool {
+        self.len() == N && self.rows().eq(other.iter().copied())
+    }
+}
+
+/// [`Frame::rows`] 返回的迭代器
+#[derive(Clone)]
+pub struct FrameRows<'a> {
+    frame: Frame<'a>,
+    range: Range<usize>,
+}
+
+impl<'a> Iterator for FrameRows<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.range.next().map(|index| self.frame.row(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for FrameRows<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().map(|index| self.frame.row(index))
+    }
+}
+
+impl ExactSizeIterator for FrameRows<'_> {}
+
+/// 动画主题
+///
+/// 主题由帧序列和字符到颜色的映射组成，颜色为 xterm 256 色索引，
+/// 真彩色模式下可以为字符另外指定精确的 RGB 颜色。
+#[derive(Clone)]
+pub struct Theme {
+    /// 主题名称，用于 `--theme` 参数
+    pub name: &'static str,
+    /// 主题描述
+    pub description: &'static str,
+    /// 动画帧
+    pub frames: Frames,
+    /// 帧宽度（字符数）
+    pub width: usize,
+    /// 帧高度（行数）
+    pub height: usize,
+    /// 字符到颜色的映射
+    palette: &'static [(char, u8)],
+    /// 24 位真彩色模式下字符的精确颜色和单元格，未列出的字符按 256 色索引换算
+    truecolor: &'static [(char, (u8, u8, u8), &'static str)],
+    /// 从文件加载的调色板，设置后替代内置映射
+    custom: Option<Arc<CustomPalette>>,
+    /// 把输出中的非 ASCII 字符替换为近似的 ASCII 字符
+    ascii_only: bool,
+}
+
+impl std::fmt::Debug for Theme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Theme")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 播放中使用的主题
+///
+/// 内置主题和启动时加载的主题在程序运行期间一直存在。`--frames-dir` 重新加载的主题按引用计数共享，
+/// 所有播放都切换到更新的帧后释放。
+#[derive(Clone, Debug)]
+pub enum ThemeRef {
+    Static(&'static Theme),
+    Shared(Arc<Theme>),
+}
+
+impl ThemeRef {
+    /// 只输出 ASCII 字符的版本，静态主题的转换会被缓存
+    pub fn with_ascii_only(&self) -> ThemeRef {
+        match self {
+            Self::Static(theme) => Self::Static(theme.with_ascii_only()),
+            Self::Shared(theme) if theme.is_ascii_only() => self.clone(),
+            Self::Shared(theme) => Self::Shared(Arc::new(theme.to_ascii_only())),
+        }
+    }
+}
+
+impl Deref for ThemeRef {
+    type Target = Theme;
+
+    fn deref(&self) -> &Theme {
+        match self {
+            Self::Static(theme) => theme,
+            Self::Shared(theme) => theme,
+        }
+    }
+}
+
+impl From<&'static Theme> for ThemeRef {
+    fn from(theme: &'static Theme) -> Self {
+        Self::Static(theme)
+    }
+}
+
+/// 彩虹从上到下的字符，所有内置主题按这个顺序配色
+pub const RAINBOW: [char; 6] = ['>', '&', '+', '#', '=', ';'];
+
+/// 彩虹的字符按 `phase` 轮换后的字符，其他字符不变
+pub fn rainbow_glyph(character: char, phase: usize) -> char {
+    match RAINBOW.iter().position(|&glyph| glyph == character) {
+        Some(index) => RAINBOW[(index + phase) % RAINBOW.len()],
+        None => character,
+    }
+}
+
+/// 字符的 24 位颜色，单元格在编译时生成
+macro_rules! truecolor {
+    ($glyph:literal, $r:literal, $g:literal, $b:literal) => {
+        (
+            $glyph,
+            ($r, $g, $b),
+            concat!("\x1B[48;2;", $r, ";", $g, ";", $b, "m  \x1B[0m"),
+        )
+    };
+}
+
+/// 经典配色
+const CLASSIC: Theme = Theme {
+    name: "classic",
+    description: "The original nyancat colors",
+    frames: Frames::Static(FRAMES),
+    width: FRAME_WIDTH,
+    height: FRAME_HEIGHT,
+    custom: None,
+    ascii_only: false,
+    palette: &[
+        (',', 17),
+        ('.', 231),
+        ('\'', 16),
+        ('@', 230),
+        ('$', 175),
+        ('-', 162),
+        ('>', 196),
+        ('&', 214),
+        ('+', 226),
+        ('#', 118),
+        ('=', 33),
+        (';', 19),
+        ('*', 240),
+        ('%', 175),
+    ],
+    // 原版动画的颜色，256 色索引是它们的近似
+    truecolor: &[
+        truecolor!(',', 0, 51, 102),
+        truecolor!('.', 255, 255, 255),
+        truecolor!('\'', 0, 0, 0),
+        truecolor!('@', 255, 204, 153),
+        truecolor!('$', 255, 153, 255),
+        truecolor!('-', 255, 51, 153),
+        truecolor!('>', 255, 0, 0),
+        truecolor!('&', 255, 153, 0),
+        truecolor!('+', 255, 255, 0),
+        truecolor!('#', 51, 255, 0),
+        truecolor!('=', 0, 153, 255),
+        truecolor!(';', 102, 51, 255),
+        truecolor!('*', 153, 153, 153),
+        truecolor!('%', 255, 153, 153),
+    ],
+};
+
+/// 粉色馅饼配色
+const POPTART: Theme = Theme {
+    name: "poptart",
+    description: "Pastel pop-tart cat on a purple sky",
+    frames: Frames::Static(FRAMES),
+    width: FRAME_WIDTH,
+    height: FRAME_HEIGHT,
+    custom: None,
+    ascii_only: false,
+    palette: &[
+        (',', 53),
+        ('.', 231),
+        ('\'', 16),
+        ('@', 224),
+        ('$', 218),
+        ('-', 205),
+        ('>', 211),
+        ('&', 223),
+        ('+', 229),
+        ('#', 157),
+        ('=', 117),
+        (';', 183),
+        ('*', 245),
+        ('%', 218),
+    ],
+    truecolor: &[],
+};
+
+/// 单色配色
+const MONOCHROME: Theme = Theme {
+    name: "monochrome",
+    description: "Grayscale nyancat",
+    frames: Frames::Static(FRAMES),
+    width: FRAME_WIDTH,
+    height: FRAME_HEIGHT,
+    custom: None,
+    ascii_only: false,
+    palette: &[
+        (',', 232),
+        ('.', 255),
+        ('\'', 16),
+        ('@', 252),
+        ('$', 248),
+        ('-', 244),
+        ('>', 250),
+        ('&', 247),
+        ('+', 254),
+        ('#', 245),
+        ('=', 242),
+        (';', 239),
+        ('*', 240),
+        ('%', 248),
+    ],
+    truecolor: &[],
+};
+
+/// 所有内置主题，第一个为默认主题
+pub const THEMES: &[Theme] = &[CLASSIC, POPTART, MONOCHROME];
+
+/// 内置动画
+///
+/// 动画决定画面中的图案，主题决定颜色，二者可以任意组合。经典动画以外的动画都由主题的帧
+/// 替换字符、叠加图案和镜像得到，帧数和大小不变，见 [`Theme::with_animation`]。
+#[derive(Debug)]
+pub struct Animation {
+    /// 动画名称，用于 `--animation` 参数
+    pub name: &'static str,
+    /// 动画描述
+    pub description: &'static str,
+    /// 替换的字符，用来给猫换一个身体
+    replace: &'static [(char, char)],
+    /// 叠加在猫右耳上方的图案，空格透明，只覆盖天空和星星
+    hat: &'static [&'static str],
+    /// 左右镜像，猫向左飞
+    mirror: bool,
+    /// 动画用到的颜色，优先于主题中相同字符的颜色
+    palette: &'static [(char, u8)],
+}
+
+/// 经典动画
+const NYANCAT: Animation = Animation {
+    name: "nyancat",
+    description: "The original pop-tart cat",
+    replace: &[],
+    hat: &[],
+    mirror: false,
+    palette: &[],
+};
+
+/// 南瓜猫
+const PUMPKIN: Animation = Animation {
+    name: "pumpkin",
+    description: "A pumpkin-bodied cat for Halloween",
+    replace: &[('@', 'p'), ('$', 'O'), ('-', 'p')],
+    hat: &[],
+    mirror: false,
+    palette: &[('p', 130), ('O', 208)],
+};
+
+/// 圣诞猫
+const XMAS: Animation = Animation {
+    name: "xmas",
+    description: "A candy-striped cat wearing a Santa hat",
+    replace: &[('@', 'g'), ('$', 'r'), ('-', 'w')],
+    hat: &["     ww", "   rrrw", "  rrrr ", " rrrrr ", "wwwwwww"],
+    mirror: false,
+    palette: &[('g', 28), ('r', 160), ('w', 231)],
+};
+
+/// 反向飞行的华夫饼猫
+const TACNAYN: Animation = Animation {
+    name: "tacnayn",
+    description: "Nyancat's evil waffle twin, flying the other way",
+    replace: &[('@', 'b'), ('$', 'B'), ('-', 'b')],
+    hat: &[],
+    mirror: true,
+    palette: &[
+        ('b', 58),
+        ('B', 94),
+        ('>', 232),
+        ('&', 234),
+        ('+', 236),
+        ('#', 238),
+        ('=', 240),
+        (';', 242),
+    ],
+};
+
+/// 所有内置动画，第一个为默认动画
+pub const ANIMATIONS: &[Animation] = &[NYANCAT, PUMPKIN, XMAS, TACNAYN];
+
+impl Animation {
+    /// 根据名称查找动画
+    pub fn by_name(name: &str) -> Option<&'static Animation> {
+        ANIMATIONS
+            .iter()
+            .find(|animation| animation.name.eq_ignore_ascii_case(name))
+    }
+
+    /// 是否与原来的帧相同
+    fn is_original(&self) -> bool {
+        self.replace.is_empty() && self.hat.is_empty() && !self.mirror
+    }
+
+    /// 变换一帧，先叠加图案再镜像，保证图案位于猫头上方
+    fn transform(&self, frame: Frame) -> Vec<String> {
+        let mut rows = frame
+            .rows()
+            .map(|row| {
+                row.chars()
+                    .map(|c| match self.replace.iter().find(|(from, _)| *from == c) {
+                        Some(&(_, to)) => to,
+                        None => c,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // 最上面一行 `*` 是猫耳，最右边的是右耳尖，耳朵轮廓占一行，图案放在轮廓之上
+        let ear = rows
+            .iter()
+            .enumerate()
+            .find_map(|(y, row)| row.iter().rposition(|&c| c == '*').map(|x| (y, x)));
+        if let Some((ear_row, ear_col)) = ear
+            && !self.hat.is_empty()
+        {
+            let bottom = ear_row.checked_sub(2);
+            for (dy, line) in self.hat.iter().rev().enumerate() {
+                let Some(y) = bottom.and_then(|bottom| bottom.checked_sub(dy)) else {
+                    break;
+                };
+                for (dx, c) in line.chars().enumerate() {
+                    let x = (ear_col + dx).checked_sub(3);
+                    if let Some(cell) = x.and_then(|x| rows[y].get_mut(x))
+                        && c != ' '
+                        && matches!(*cell, ',' | '.')
+                    {
+                        *cell = c;
+                    }
+                }
+            }
+        }
+
+        rows.into_iter()
+            .map(|mut row| {
+                if self.mirror {
+                    row.reverse();
+                }
+                row.into_iter().collect()
+            })
+            .collect()
+    }
+}
+
+/// 主题预览中每个主题至少占用的单元格数，终端放不下两个时纵向排列
+const PREVIEW_MIN_CELLS: usize = 24;
+/// 主题预览中相邻两列之间的空白列数
+const PREVIEW_GAP: usize = 2;
+
+/// 把各主题的第一帧排成带名称的网格，用于比较主题
+///
+/// 列数由终端宽度决定，帧比格子大时居中裁剪为正方形区域。
+pub fn theme_preview(themes: &[Theme], terminal_width: u16, mode: ColorMode) -> Vec<String> {
+    let width = usize::from(terminal_width);
+    let columns = ((width + PREVIEW_GAP) / (PREVIEW_MIN_CELLS * 2 + PREVIEW_GAP))
+        .clamp(1, themes.len().max(1));
+    // 每个单元格占两列
+    let tile_cells = (((width + PREVIEW_GAP) / columns).saturating_sub(PREVIEW_GAP) / 2).max(1);
+    let gap = " ".repeat(PREVIEW_GAP);
+
+    let mut lines = Vec::new();
+    for (index, row) in themes.chunks(columns).enumerate() {
+        if index > 0 {
+            lines.push(String::new());
+        }
+        let tiles: Vec<Vec<String>> = row
+            .iter()
+            .map(|theme| theme.preview_tile(tile_cells, mode))
+            .collect();
+        let height = tiles.iter().map(Vec::len).max().unwrap_or(0);
+        // 较矮的格子用空白补齐，保证后面的列对齐
+        let blank = " ".repeat(tile_cells * 2);
+        for line in 0..height {
+            let parts: Vec<&str> = tiles
+                .iter()
+                .map(|tile| tile.get(line).map_or(blank.as_str(), String::as_str))
+                .collect();
+            lines.push(parts.join(&gap).trim_end().to_string());
+        }
+    }
+    lines
+}
+
+impl Theme {
+    /// 主题预览中的一格：第一行为名称，之后是第一帧居中裁剪后的内容，每行占 `cells * 2` 列
+    fn preview_tile(&self, cells: usize, mode: ColorMode) -> Vec<String> {
+        let cols = cells.min(self.width);
+        let rows = cols.min(self.height);
+        let min_col = (self.width - cols) / 2;
+        let min_row = (self.height - rows) / 2;
+        let pad = "  ".repeat(cells - cols);
+
+        let name: String = self.name.chars().take(cells * 2).collect();
+        let mut tile = vec![format!("{name:<0$}", cells * 2)];
+        for line in self.frames.get(0).rows().skip(min_row).take(rows) {
+            let mut chars = line.chars().skip(min_col);
+            let mut out = String::new();
+            for _ in 0..cols {
+                // 比帧宽短的行用空格补齐
+                out.push_str(self.render_color(chars.next().unwrap_or(' '), mode));
+            }
+            out.push_str(&pad);
+            tile.push(out);
+        }
+        tile
+    }
+
+    /// 根据名称查找主题
+    pub fn by_name(name: &str) -> Option<&'static Theme> {
+        THEMES
+            .iter()
+            .find(|theme| theme.name.eq_ignore_ascii_case(name))
+    }
+
+    /// 调色板预览
+    ///
+    /// 每行对应一个字符，依次输出其在各颜色模式下的色块，用于检查终端的颜色支持。
+    pub fn palette_preview(&self) -> Vec<String> {
+        let mut header = String::from("glyph    color");
+        for mode in ColorMode::ALL {
+            header.push_str(&format!("  {:<9}", mode.name()));
+        }
+
+        let entries: Vec<(char, String)> = match &self.custom {
+            Some(custom) => custom
+                .entries()
+                .map(|(glyph, value)| (glyph, value.to_string()))
+                .collect(),
+            None => self
+                .palette
+                .iter()
+                .map(|&(glyph, color)| (glyph, color.to_string()))
+                .collect(),
+        };
+        let rows = entries.into_iter().map(|(glyph, color)| {
+            let mut row = format!("{glyph:^5}  {color:>7}");
+            for mode in ColorMode::ALL {
+                // 色块占两列，补齐到与表头相同的宽度
+                row.push_str(&format!("  {}{:7}", self.render_color(glyph, mode), ""));
+            }
+            row
+        });
+
+        std::iter::once(header.trim_end().to_string())
+            .chain(rows)
+            .collect()
+    }
+
+    /// 帧中用到的所有字符，按第一次出现的顺序
+    pub fn glyphs(&self) -> Vec<char> {
+        let mut glyphs = Vec::new();
+        for row in self.frames.iter().flat_map(Frame::rows) {
+            for glyph in row.chars() {
+                if !glyphs.contains(&glyph) {
+                    glyphs.push(glyph);
+                }
+            }
+        }
+        glyphs
+    }
+
+    /// 字符自检
+    ///
+    /// 每行输出帧中用到的一个字符、它的 Unicode 码位和渲染后的单元格，
+    /// 用于确认终端和字体能正确显示动画。
+    pub fn glyph_test(&self, mode: ColorMode) -> Vec<String> {
+        let header = format!("glyph  codepoint  {}", mode.name());
+        let rows = self.glyphs().into_iter().map(|glyph| {
+            let cell = self.render_color(glyph, mode);
+            format!("{glyph:^5}  U+{:04X}     {cell}", u32::from(glyph))
+        });
+        std::iter::once(header).chain(rows).collect()
+    }
+
+    /// 字符对应的 RGB 颜色，用于渲染到图片
+    pub fn rgb(&self, character: char) -> Option<(u8, u8, u8)> {
+        match &self.custom {
+            Some(custom) => custom.rgb(character),
+            None => match self
+                .truecolor
+                .iter()
+                .find(|(glyph, ..)| *glyph == character)
+            {
+                Some(&(_, rgb, _)) => Some(rgb),
+                None => self
+                    .palette
+                    .iter()
+                    .find(|(glyph, _)| *glyph == character)
+                    .map(|&(_, color)| xterm_to_rgb(color)),
+            },
+        }
+    }
+
+    /// 使用自定义调色板替换内置映射，返回新的主题
+    pub fn with_palette(&self, palette: Arc<CustomPalette>) -> Theme {
+        Theme {
+            custom: Some(palette),
+            ..self.clone()
+        }
+    }
+
+    /// 按比例调整所有颜色的亮度，返回新的主题
+    ///
+    /// 颜色先换算为 RGB 再缩放，256 色和 16 色模式会落到最接近的更暗或更亮的索引，
+    /// 无颜色模式的输出不变。
+    pub fn brightened(&self, factor: f32) -> Theme {
+        let entries: Vec<(char, String)> = match &self.custom {
+            Some(custom) => custom
+                .entries()
+                .map(|(glyph, label)| (glyph, label.to_string()))
+                .collect(),
+            None => self
+                .palette
+                .iter()
+                .map(|&(glyph, color)| (glyph, color.to_string()))
+                .collect(),
+        };
+
+        let mut palette = CustomPalette::default();
+        for (glyph, label) in entries {
+            let Some(rgb) = self.rgb(glyph) else {
+                continue;
+            };
+            let (r, g, b) = scale_rgb(rgb, factor);
+            let color = PaletteColor::Rgb(r, g, b);
+            let plain = self.render_color(glyph, ColorMode::None);
+            palette.insert(glyph, label, color, |mode| match mode {
+                ColorMode::None => plain.to_string(),
+                _ => color.render(glyph, mode),
+            });
+        }
+        self.with_palette(Arc::new(palette))
+    }
+
+    /// 静态主题按比例调整亮度后的主题，见 [`Theme::brightened`]
+    ///
+    /// 每个主题和倍数的组合只创建一次。
+    pub fn with_brightness(&'static self, factor: f32) -> &'static Theme {
+        static THEMES: OnceLock<Mutex<HashMap<(usize, u32), &'static Theme>>> = OnceLock::new();
+        let mut themes = THEMES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        themes
+            .entry((self as *const Theme as usize, factor.to_bits()))
+            .or_insert_with(|| self.brightened(factor).into_static())
+    }
+
+    /// 把动画应用到主题的帧上，动画的颜色优先于主题的颜色
+    ///
+    /// 每个主题和动画的组合只创建一次。
+    pub fn with_animation(&'static self, animation: &'static Animation) -> &'static Theme {
+        static THEMES: OnceLock<Mutex<HashMap<(usize, usize), &'static Theme>>> = OnceLock::new();
+        if animation.is_original() {
+            return self;
+        }
+        let mut themes = THEMES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let key = (
+            self as *const Theme as usize,
+            animation as *const Animation as usize,
+        );
+        themes.entry(key).or_insert_with(|| {
+            let frames = self
+                .frames
+                .iter()
+                .map(|frame| animation.transform(frame))
+                .collect();
+            let palette = animation
+                .palette
+                .iter()
+                .chain(self.palette)
+                .copied()
+                .collect::<Vec<_>>();
+            // 动画改了颜色的字符不再使用主题的精确颜色
+            let truecolor = self
+                .truecolor
+                .iter()
+                .filter(|(glyph, ..)| !animation.palette.iter().any(|(own, _)| own == glyph))
+                .copied()
+                .collect::<Vec<_>>();
+            Theme {
+                frames: Frames::new(frames),
+                palette: Box::leak(palette.into_boxed_slice()),
+                truecolor: Box::leak(truecolor.into_boxed_slice()),
+                ..self.clone()
+            }
+            .into_static()
+        })
+    }
+
+    /// 把每个单元格横向和纵向各复制 `scale` 次，用于很大的终端窗口
+    pub fn scaled(&self, scale: usize) -> Theme {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                frame
+                    .rows()
+                    .flat_map(|row| {
+                        let row = row
+                            .chars()
+                            .flat_map(|c| std::iter::repeat_n(c, scale))
+                            .collect::<String>();
+                        std::iter::repeat_n(row, scale)
+                    })
+                    .collect()
+            })
+            .collect();
+        Theme {
+            frames: Frames::new(frames),
+            width: self.width * scale,
+            height: self.height * scale,
+            ..self.clone()
+        }
+    }
+
+    /// 静态主题放大后的主题，见 [`Theme::scaled`]
+    ///
+    /// 每个主题和倍数的组合只创建一次。
+    pub fn with_scale(&'static self, scale: usize) -> &'static Theme {
+        static THEMES: OnceLock<Mutex<HashMap<(usize, usize), &'static Theme>>> = OnceLock::new();
+        if scale <= 1 {
+            return self;
+        }
+        let mut themes = THEMES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        themes
+            .entry((self as *const Theme as usize, scale))
+            .or_insert_with(|| self.scaled(scale).into_static())
+    }
+
+    /// 是否只输出 ASCII 字符
+    pub fn is_ascii_only(&self) -> bool {
+        self.ascii_only
+    }
+
+    /// 只输出 ASCII 字符的主题，用于无法显示方块和制表符的终端和字体
+    pub fn to_ascii_only(&self) -> Theme {
+        Theme {
+            ascii_only: true,
+            ..self.clone()
+        }
+    }
+
+    /// 静态主题只输出 ASCII 字符的版本，见 [`Theme::to_ascii_only`]
+    ///
+    /// 每个主题只创建一次，WebSocket 客户端每次连接时请求也不会泄漏更多内存。
+    pub fn with_ascii_only(&'static self) -> &'static Theme {
+        static THEMES: OnceLock<Mutex<HashMap<usize, &'static Theme>>> = OnceLock::new();
+        if self.ascii_only {
+            return self;
+        }
+        let mut themes = THEMES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        // 静态主题不会释放，地址可以作为标识
+        themes
+            .entry(self as *const Theme as usize)
+            .or_insert_with(|| self.to_ascii_only().into_static())
+    }
+
+    /// 在程序运行期间一直使用的主题，如启动时加载的帧
+    pub fn into_static(self) -> &'static Theme {
+        Box::leak(Box::new(self))
+    }
+
+    /// 从外部加载的帧和调色板创建主题
+    ///
+    /// 帧宽高取所有帧中最长的行和最多的行数，帧应当先经过 [`normalize_frames`] 补齐。
+    pub fn from_frames(name: &'static str, frames: Frames, palette: Arc<CustomPalette>) -> Theme {
+        let width = frames
+            .iter()
+            .flat_map(Frame::rows)
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let height = frames.iter().map(Frame::len).max().unwrap_or(0);
+        Theme {
+            name,
+            description: "Frames loaded from file",
+            frames,
+            width,
+            height,
+            palette: &[],
+            truecolor: &[],
+            custom: Some(palette),
+            ascii_only: false,
+        }
+    }
+
+    /// 渲染字符为对应的颜色代码
+    ///
+    /// 调色板中没有的字符（包括空格）不带颜色，按原样重复两次输出，
+    /// 与带颜色的单元格一样占两列，保证裁剪和对齐不受影响。
+    /// 开启 ASCII 模式时，单元格中的非 ASCII 字符会被替换，见 [`ascii_glyph`]。
+    pub fn render_color(&self, character: char, mode: ColorMode) -> &'static str {
+        let cell = self.render_cell(character, mode);
+        if self.ascii_only {
+            ascii_only_cell(cell)
+        } else {
+            cell
+        }
+    }
+
+    /// 彩虹的颜色按 `phase` 轮换后渲染字符，其他字符不受影响
+    ///
+    /// 相位与帧索引无关，彩虹可以按不同于动画的速度变换颜色。
+    pub fn render_color_at(&self, character: char, mode: ColorMode, phase: usize) -> &'static str {
+        self.render_color(rainbow_glyph(character, phase), mode)
+    }
+
+    /// 半块模式下把上下两个字符合并为一个单元格，占一列
+    ///
+    /// 颜色取自两个字符在块模式下的单元格，上半用 `▀` 的前景色、下半用背景色，
+    /// 一半没有颜色时只画有颜色的半块。不带颜色的字符没法拆成两半，按原样输出一个，
+    /// 优先取上面的字符，另一半的颜色作为它的背景。
+    pub fn render_half_block(
+        &self,
+        top: char,
+        bottom: char,
+        mode: ColorMode,
+        phase: usize,
+    ) -> &'static str {
+        let top = self.render_color_at(top, mode, phase);
+        let bottom = self.render_color_at(bottom, mode, phase);
+        let cell = half_block_cell(top, bottom);
+        if self.ascii_only {
+            ascii_only_cell(cell)
+        } else {
+            cell
+        }
+    }
+
+    /// 盲文模式下把 2x4 个字符合并为一个盲文字符，占一列
+    ///
+    /// `pixels` 按行排列，每行两个字符。一个单元格只有前景和背景两种颜色：
+    /// 最多的颜色作为背景，其次的作为点的颜色，其余颜色的字符也画成点。
+    /// 都不带颜色时（如无颜色模式）非空格的字符画成点。
+    pub fn render_braille(
+        &self,
+        pixels: [char; MAX_ROWS_PER_LINE * 2],
+        mode: ColorMode,
+        phase: usize,
+    ) -> &'static str {
+        let cells = pixels.map(|pixel| self.render_color_at(pixel, mode, phase));
+        let cell = braille_cell(cells);
+        if self.ascii_only {
+            ascii_only_cell(cell)
+        } else {
+            cell
+        }
+    }
+
+    fn render_cell(&self, character: char, mode: ColorMode) -> &'static str {
+        // 自定义调色板中没有的字符按原样输出
+        if let Some(custom) = &self.custom {
+            return custom
+                .get(character, mode)
+                .unwrap_or_else(|| plain_cell(character));
+        }
+        if mode == ColorMode::TrueColor
+            && let Some(&(.., cell)) = self
+                .truecolor
+                .iter()
+                .find(|(glyph, ..)| *glyph == character)
+        {
+            return cell;
+        }
+        let Some(&(_, color)) = self.palette.iter().find(|(glyph, _)| *glyph == character) else {
+            return plain_cell(character);
+        };
+        match mode {
+            ColorMode::TrueColor => cell(&TRUECOLOR_CELLS, color, |color| {
+                let (r, g, b) = xterm_to_rgb(color);
+                format!("\x1B[48;2;{r};{g};{b}m  \x1B[0m")
+            }),
+            ColorMode::Color256 => cell(&COLOR256_CELLS, color, |color| {
+                format!("\x1B[48;5;{color}m  \x1B[0m")
+            }),
+            ColorMode::Color16 => cell(&COLOR16_CELLS, color, |color| {
+                let ansi = nearest_ansi16(xterm_to_rgb(color));
+                let sgr = if ansi < 8 { 40 + ansi } else { 100 + ansi - 8 };
+                format!("\x1B[{sgr}m  \x1B[0m")
+            }),
+            ColorMode::Color8 => cell(&COLOR8_CELLS, color, |color| {
+                let ansi = nearest_ansi8(xterm_to_rgb(color));
+                format!("\x1B[{}m  \x1B[0m", 40 + ansi)
+            }),
+            ColorMode::None => ascii_cell(character),
+        }
+    }
+}
+
+/// 颜色输出模式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorMode {
+    /// 24 位真彩色
+    TrueColor,
+    /// xterm 256 色
+    #[default]
+    Color256,
+    /// 传统 16 色
+    Color16,
+    /// 基本 8 色，只使用 40–47 背景色，用于不支持亮色背景的老终端
+    Color8,
+    /// 无颜色，使用 ASCII 字符表示
+    None,
+}
+
+impl ColorMode {
+    /// 所有颜色模式，从高到低排列
+    pub const ALL: [ColorMode; 5] = [
+        ColorMode::TrueColor,
+        ColorMode::Color256,
+        ColorMode::Color16,
+        ColorMode::Color8,
+        ColorMode::None,
+    ];
+
+    /// 颜色模式的显示名称
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorMode::TrueColor => "truecolor",
+            ColorMode::Color256 => "256",
+            ColorMode::Color16 => "16",
+            ColorMode::Color8 => "8",
+            ColorMode::None => "none",
+        }
+    }
+
+    /// 不超过 `limit` 的颜色模式，用于按服务端设置降低客户端的颜色模式
+    pub fn at_most(self, limit: Self) -> Self {
+        let rank = |mode| Self::ALL.iter().position(|m| *m == mode);
+        if rank(self) < rank(limit) {
+            limit
+        } else {
+            self
+        }
+    }
+
+    /// 根据名称解析颜色模式，名称见 [`ColorMode::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+
+    /// 根据当前进程的 `COLORTERM` 和 `TERM` 环境变量检测终端支持的颜色模式
+    ///
+    /// `TERM` 未设置或为 `dumb` 时不输出颜色，无法识别时回退到 256 色。
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).ok();
+        Self::detect_from(var("COLORTERM").as_deref(), var("TERM").as_deref()).0
+    }
+
+    /// 根据 `COLORTERM` 和 `TERM` 的值选择颜色模式，同时返回选择的原因
+    pub fn detect_from(colorterm: Option<&str>, term: Option<&str>) -> (Self, String) {
+        if let Some(colorterm) = colorterm.filter(|value| {
+            value.eq_ignore_ascii_case("truecolor") || value.eq_ignore_ascii_case("24bit")
+        }) {
+            return (Self::TrueColor, format!("COLORTERM is `{colorterm}`"));
+        }
+        let Some(term) = term.filter(|term| !term.is_empty()) else {
+            return (Self::None, "TERM is not set".to_string());
+        };
+        match Self::from_term(term) {
+            Some(mode) => (mode, format!("TERM is `{term}`")),
+            None => (
+                Self::default(),
+                format!("TERM `{term}` is not recognized, falling back to the default"),
+            ),
+        }
+    }
+
+    /// 根据终端类型（如 telnet TTYPE 或 `TERM`）推断颜色模式
+    ///
+    /// 无法识别的终端类型返回 `None`，由调用方决定回退方式。
+    pub fn from_term(term: &str) -> Option<Self> {
+        let term = term.to_ascii_lowercase();
+        if term.contains("truecolor") || term.contains("24bit") || term.contains("direct") {
+            Some(Self::TrueColor)
+        } else if term.contains("256color") || term.starts_with("xterm") {
+            Some(Self::Color256)
+        } else if ["linux", "rxvt", "screen", "cygwin", "vt102", "putty"]
+            .iter()
+            .any(|prefix| term.starts_with(prefix))
+        {
+            Some(Self::Color16)
+        } else if term.starts_with("ansi") {
+            // ANSI.SYS 和 BBS 终端没有亮色背景
+            Some(Self::Color8)
+        } else if ["dumb", "vt100", "vt220", "unknown"]
+            .iter()
+            .any(|prefix| term.starts_with(prefix))
+        {
+            Some(Self::None)
+        } else {
+            None
+        }
+    }
+}
+
+static TRUECOLOR_CELLS: OnceLock<Vec<String>> = OnceLock::new();
+static COLOR256_CELLS: OnceLock<Vec<String>> = OnceLock::new();
+static COLOR16_CELLS: OnceLock<Vec<String>> = OnceLock::new();
+static COLOR8_CELLS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// 按 xterm 256 色索引缓存的背景单元格，每个单元格占两个字符宽度
+fn cell(
+    cells: &'static OnceLock<Vec<String>>,
+    color: u8,
+    render: impl Fn(u8) -> String,
+) -> &'static str {
+    let cells = cells.get_or_init(|| (0..=u8::MAX).map(render).collect());
+    &cells[color as usize]
+}
+
+/// 不带颜色的单元格，字符重复两次占满两列
+fn plain_cell(character: char) -> &'static str {
+    static CELLS: OnceLock<Mutex<HashMap<char, &'static str>>> = OnceLock::new();
+    let mut cells = CELLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    // 字符种类有限，泄漏的内存有上限
+    cells
+        .entry(character)
+        .or_insert_with(|| Box::leak(format!("{character}{character}").into_boxed_str()))
+}
+
+/// 块模式单元格的背景色参数，如 `48;5;17`，不带颜色时返回 `None`
+fn cell_background(cell: &str) -> Option<&str> {
+    cell.strip_prefix("\x1B[")?.strip_suffix("m  \x1B[0m")
+}
+
+/// 把背景色参数换成对应的前景色参数
+fn foreground(background: &str) -> String {
+    if let Some(rest) = background.strip_prefix("48;") {
+        return format!("38;{rest}");
+    }
+    // 40–47 对应 30–37，亮色 100–107 对应 90–97
+    match background.parse::<u8>() {
+        Ok(code) if code >= 40 => (code - 10).to_string(),
+        _ => background.to_string(),
+    }
+}
+
+/// 按块模式的两个单元格合并出半块单元格，见 [`Theme::render_half_block`]
+fn half_block_cell(top: &'static str, bottom: &'static str) -> &'static str {
+    static CELLS: OnceLock<Mutex<HashMap<(&'static str, &'static str), &'static str>>> =
+        OnceLock::new();
+    let mut cells = CELLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    // 组合数受调色板大小限制，泄漏的内存有上限
+    cells.entry((top, bottom)).or_insert_with(|| {
+        // 不带颜色的单元格是同一个字符重复两次
+        let glyph = |cell: &str| cell.chars().next().unwrap_or(' ');
+        let cell = match (cell_background(top), cell_background(bottom)) {
+            (Some(top), Some(bottom)) if top == bottom => format!("\x1B[{top}m \x1B[0m"),
+            (Some(top), Some(bottom)) => {
+                format!("\x1B[{};{bottom}m▀\x1B[0m", foreground(top))
+            }
+            (Some(top), None) if glyph(bottom) == ' ' => {
+                format!("\x1B[{}m▀\x1B[0m", foreground(top))
+            }
+            (None, Some(bottom)) if glyph(top) == ' ' => {
+                format!("\x1B[{}m▄\x1B[0m", foreground(bottom))
+            }
+            (Some(background), None) => format!("\x1B[{background}m{}\x1B[0m", glyph(bottom)),
+            (None, Some(background)) => format!("\x1B[{background}m{}\x1B[0m", glyph(top)),
+            (None, None) => match glyph(top) {
+                ' ' => glyph(bottom).to_string(),
+                top => top.to_string(),
+            },
+        };
+        Box::leak(cell.into_boxed_str())
+    })
+}
+
+/// 盲文字符中每个点对应的位，按 [`Theme::render_braille`] 的像素顺序排列
+const BRAILLE_DOTS: [u32; MAX_ROWS_PER_LINE * 2] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+/// 按块模式的 2x4 个单元格合并出盲文单元格，见 [`Theme::render_braille`]
+fn braille_cell(cells: [&'static str; MAX_ROWS_PER_LINE * 2]) -> &'static str {
+    type Cells = [&'static str; MAX_ROWS_PER_LINE * 2];
+    static CELLS: OnceLock<Mutex<HashMap<Cells, &'static str>>> = OnceLock::new();
+    let mut cache = CELLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    // 点阵的种类受帧内容限制，泄漏的内存有上限
+    cache.entry(cells).or_insert_with(|| {
+        let dots = |is_dot: &dyn Fn(&str) -> bool| {
+            cells
+                .iter()
+                .zip(BRAILLE_DOTS)
+                .filter(|(cell, _)| is_dot(cell))
+                .fold(0, |bits, (_, dot)| bits | dot)
+        };
+        let braille = |bits| char::from_u32(0x2800 + bits).unwrap_or(' ');
+
+        let cell = if cells.iter().all(|cell| cell_background(cell).is_none()) {
+            match dots(&|cell| !cell.starts_with(' ')) {
+                0 => " ".to_string(),
+                bits => braille(bits).to_string(),
+            }
+        } else {
+            // 按出现次数从多到少排列颜色，次数相同时先出现的在前
+            let mut counts: Vec<(&str, usize)> = Vec::new();
+            for cell in cells {
+                match counts.iter_mut().find(|(existing, _)| *existing == cell) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((cell, 1)),
+                }
+            }
+            counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+            let background = counts[0].0;
+            let mut params = Vec::new();
+            if let Some(&(dot, _)) = counts.get(1)
+                && let Some(color) = cell_background(dot)
+            {
+                params.push(foreground(color));
+            }
+            if let Some(color) = cell_background(background) {
+                params.push(color.to_string());
+            }
+            let glyph = match dots(&|cell| cell != background) {
+                0 => ' ',
+                bits => braille(bits),
+            };
+            if params.is_empty() {
+                glyph.to_string()
+            } else {
+                format!("\x1B[{}m{glyph}\x1B[0m", params.join(";"))
+            }
+        };
+        Box::leak(cell.into_boxed_str())
+    })
+}
+
+/// 替换单元格中的非 ASCII 字符
+fn ascii_only_cell(cell: &'static str) -> &'static str {
+    if cell.is_ascii() {
+        return cell;
+    }
+    static CELLS: OnceLock<Mutex<HashMap<&'static str, &'static str>>> = OnceLock::new();
+    let mut cells = CELLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    cells.entry(cell).or_insert_with(|| {
+        let ascii: String = cell.chars().map(ascii_glyph).collect();
+        Box::leak(ascii.into_boxed_str())
+    })
+}
+
+/// 非 ASCII 字符的 ASCII 近似，一个字符替换为一个字符，保持列宽
+///
+/// 方块按填充程度替换，制表符按方向替换，无法近似的字符替换为 `?`。
+fn ascii_glyph(ch: char) -> char {
+    match ch {
+        ch if ch.is_ascii() => ch,
+        '█' | '▓' | '▇' | '▆' | '▉' | '▊' | '■' | '◼' => '#',
+        '▒' | '▅' | '▋' | '▍' | '▎' | '▏' => '=',
+        '░' => ':',
+        '▀' | '▔' => '"',
+        '▄' | '▃' | '▂' | '▁' => '_',
+        '▌' | '▐' => '|',
+        '●' | '◉' | '⬤' | '◆' => '@',
+        '○' | '◯' | '◦' | '◇' => 'o',
+        '•' | '·' | '∙' => '.',
+        '★' | '☆' | '✦' | '✧' | '✶' | '✳' => '*',
+        '─' | '━' | '┄' | '┅' | '┈' | '┉' | '╌' | '╍' | '═' | '╴' | '╶' | '╸' | '╺' => {
+            '-'
+        }
+        '│' | '┃' | '┆' | '┇' | '┊' | '┋' | '╎' | '╏' | '║' | '╵' | '╷' | '╹' | '╻' => {
+            '|'
+        }
+        '╱' => '/',
+        '╲' => '\\',
+        '╳' => 'X',
+        // 盲文按点数近似
+        '\u{2800}' => ' ',
+        '\u{2801}'..='\u{28FF}' => match (ch as u32 - 0x2800).count_ones() {
+            1..=2 => '.',
+            3..=5 => ':',
+            _ => '#',
+        },
+        // 其余制表符都是拐角和交叉
+        '\u{2500}'..='\u{257F}' => '+',
+        // 其余方块
+        '\u{2580}'..='\u{259F}' => '#',
+        _ => '?',
+    }
+}
+
+/// 无颜色模式下字符对应的 ASCII 表示，与原版 nyancat 一致
+fn ascii_cell(character: char) -> &'static str {
     match character {
-        ',' => "\x1B[48;5;17m  \x1B[0m",
-        '.' => "\x1B[48;5;231m  \x1B[0m",
-        '\'' => "\x1B[48;5;16m  \x1B[0m",
-        '@' => "\x1B[48;5;230m  \x1B[0m",
-        '$' => "\x1B[48;5;175m  \x1B[0m",
-        '-' => "\x1B[48;5;162m  \x1B[0m",
-        '>' => "\x1B[48;5;196m  \x1B[0m",
-        '&' => "\x1B[48;5;214m  \x1B[0m",
-        '+' => "\x1B[48;5;226m  \x1B[0m",
-        '#' => "\x1B[48;5;118m  \x1B[0m",
-        '=' => "\x1B[48;5;33m  \x1B[0m",
-        ';' => "\x1B[48;5;19m  \x1B[0m",
-        '*' => "\x1B[48;5;240m  \x1B[0m",
-        '%' => "\x1B[48;5;175m  \x1B[0m",
-        _ => todo!(),
+        ',' => "  ",
+        '.' => "**",
+        '\'' => "##",
+        '@' => "##",
+        '$' => "??",
+        '-' => "<>",
+        '>' => "##",
+        '&' => "==",
+        '+' => "--",
+        '#' => "++",
+        '=' => "~~",
+        ';' => "$$",
+        '*' => ";;",
+        '%' => "()",
+        _ => plain_cell(character),
+    }
+}
+
+/// xterm 默认的 16 色 RGB 值
+const ANSI16_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// xterm 256 色索引转换为 RGB
+pub fn xterm_to_rgb(color: u8) -> (u8, u8, u8) {
+    match color {
+        0..=15 => ANSI16_RGB[color as usize],
+        16..=231 => {
+            const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+            let index = color - 16;
+            (
+                LEVELS[(index / 36) as usize],
+                LEVELS[(index / 6 % 6) as usize],
+                LEVELS[(index % 6) as usize],
+            )
+        }
+        232..=255 => {
+            let gray = 8 + (color - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// 按比例缩放 RGB，结果限制在 `0..=255`
+pub fn scale_rgb((r, g, b): (u8, u8, u8), factor: f32) -> (u8, u8, u8) {
+    let scale = |channel: u8| (f32::from(channel) * factor).round().clamp(0.0, 255.0) as u8;
+    (scale(r), scale(g), scale(b))
+}
+
+/// 找到与 RGB 最接近的 xterm 256 色索引，只在 6x6x6 色块和灰阶中查找
+pub fn rgb_to_xterm((r, g, b): (u8, u8, u8)) -> u8 {
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let dr = i32::from(r) - i32::from(cr);
+        let dg = i32::from(g) - i32::from(cg);
+        let db = i32::from(b) - i32::from(cb);
+        dr * dr + dg * dg + db * db
+    };
+    (16..=255u8)
+        .min_by_key(|&color| distance(xterm_to_rgb(color)))
+        .unwrap_or(16)
+}
+
+/// 找到与 RGB 最接近的 16 色索引
+pub fn nearest_ansi16(rgb: (u8, u8, u8)) -> u8 {
+    nearest_ansi(rgb, 16)
+}
+
+/// 找到与 RGB 最接近的基本 8 色索引
+pub fn nearest_ansi8(rgb: (u8, u8, u8)) -> u8 {
+    nearest_ansi(rgb, 8)
+}
+
+fn nearest_ansi((r, g, b): (u8, u8, u8), count: u8) -> u8 {
+    let distance = |&(cr, cg, cb): &(u8, u8, u8)| {
+        let dr = i32::from(r) - i32::from(cr);
+        let dg = i32::from(g) - i32::from(cg);
+        let db = i32::from(b) - i32::from(cb);
+        dr * dr + dg * dg + db * db
+    };
+    (0..count)
+        .min_by_key(|&index| distance(&ANSI16_RGB[index as usize]))
+        .unwrap_or(0)
+}
+
+/// 计算下一帧的索引
+///
+/// 正向播放时从最后一帧回到第一帧，倒放时从第一帧回到最后一帧。
+pub fn advance_frame(frame_idx: usize, frame_count: usize, reverse: bool) -> usize {
+    if reverse {
+        frame_idx.checked_sub(1).unwrap_or(frame_count - 1)
+    } else {
+        (frame_idx + 1) % frame_count
+    }
+}
+
+/// 帧的翻转方式
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Flip {
+    /// 上下翻转，行倒序
+    pub vertical: bool,
+    /// 左右翻转，每行字符倒序
+    pub horizontal: bool,
+}
+
+impl Flip {
+    /// 按翻转后的顺序遍历帧的行
+    ///
+    /// 返回的下标是翻转后的位置，可以直接用于 [`RenderSize`] 的裁剪。
+    pub fn rows(self, frame: Frame<'_>) -> Box<dyn Iterator<Item = &str> + '_> {
+        if self.vertical {
+            Box::new(frame.rows().rev())
+        } else {
+            Box::new(frame.rows())
+        }
+    }
+
+    /// 按翻转后的顺序遍历一行的字符
+    pub fn chars(self, row: &str) -> Box<dyn Iterator<Item = char> + '_> {
+        if self.horizontal {
+            Box::new(row.chars().rev())
+        } else {
+            Box::new(row.chars())
+        }
+    }
+}
+
+/// 帧的绘制方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    /// 每个字符画成两个带背景色的空格，占两列一行
+    #[default]
+    Block,
+    /// 每列用 `▀` 的前景色和背景色画上下两个字符，纵向分辨率翻倍
+    HalfBlock,
+    /// 每列用盲文点阵画 2x4 个字符，用于很小的终端窗口
+    Braille,
+    /// 通过 kitty 图片协议把帧作为图片显示，只用于独立模式，其他模式按块绘制
+    Kitty,
+    /// 通过 iTerm2 内联图片协议把帧作为图片显示，同样只用于独立模式
+    ITerm2,
+}
+
+/// 屏幕上一行最多包含的帧行数，见 [`Renderer::rows_per_line`]
+pub const MAX_ROWS_PER_LINE: usize = 4;
+
+impl Renderer {
+    /// 所有绘制方式
+    pub const ALL: [Renderer; 5] = [
+        Renderer::Block,
+        Renderer::HalfBlock,
+        Renderer::Braille,
+        Renderer::Kitty,
+        Renderer::ITerm2,
+    ];
+
+    /// 绘制方式的名称，用于 `--render` 参数
+    pub fn name(self) -> &'static str {
+        match self {
+            Renderer::Block => "block",
+            Renderer::HalfBlock => "half-block",
+            Renderer::Braille => "braille",
+            Renderer::Kitty => "kitty",
+            Renderer::ITerm2 => "iterm2",
+        }
+    }
+
+    /// 根据名称解析绘制方式，名称见 [`Renderer::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|renderer| renderer.name().eq_ignore_ascii_case(name))
+    }
+
+    /// 每个单元格占的终端列数
+    pub fn cell_width(self) -> usize {
+        match self {
+            Renderer::Block | Renderer::Kitty | Renderer::ITerm2 => 2,
+            Renderer::HalfBlock | Renderer::Braille => 1,
+        }
+    }
+
+    /// 每个单元格包含的帧列数
+    pub fn cell_cols(self) -> usize {
+        match self {
+            Renderer::Block | Renderer::HalfBlock | Renderer::Kitty | Renderer::ITerm2 => 1,
+            Renderer::Braille => 2,
+        }
+    }
+
+    /// 每个终端行包含的帧行数，不超过 [`MAX_ROWS_PER_LINE`]
+    pub fn rows_per_line(self) -> usize {
+        match self {
+            Renderer::Block | Renderer::Kitty | Renderer::ITerm2 => 1,
+            Renderer::HalfBlock => 2,
+            Renderer::Braille => 4,
+        }
+    }
+
+    /// 是否把帧作为图片输出，图片的裁剪范围与块模式相同
+    pub fn is_image(self) -> bool {
+        matches!(self, Renderer::Kitty | Renderer::ITerm2)
     }
 }
 
 /// 渲染大小
+///
+/// 行列都是翻转后的帧坐标，不是终端坐标。
+#[derive(Clone, Copy)]
 pub struct RenderSize {
     pub min_col: usize,
     pub max_col: usize,
@@ -842,14 +2275,68 @@ pub struct RenderSize {
     pub max_row: usize,
 }
 
+/// 外部加载的帧的最大宽高，超过时多半是选错了文件
+pub const MAX_FRAME_SIZE: usize = 1000;
+
+/// 检查外部加载的帧的大小，帧应当已经由 [`normalize_frames`] 补齐
+pub fn validate_frames(frames: &[Vec<String>]) -> anyhow::Result<()> {
+    let Some(frame) = frames.first() else {
+        anyhow::bail!("animation contains no frames");
+    };
+    let width = frame.first().map_or(0, |row| row.chars().count());
+    let height = frame.len();
+    if width == 0 || height == 0 {
+        anyhow::bail!("animation frames are empty");
+    }
+    if width > MAX_FRAME_SIZE || height > MAX_FRAME_SIZE {
+        anyhow::bail!(
+            "animation frames are {width}x{height}, larger than the limit of {MAX_FRAME_SIZE}x{MAX_FRAME_SIZE}"
+        );
+    }
+    Ok(())
+}
+
+/// 把参差不齐的帧补齐为相同的宽高，返回补齐后的宽高
+///
+/// 渲染、翻转和裁剪都按字符的位置计算列号，短行在末尾补空格、行数不足的帧在末尾补空行后，
+/// 每个字符的列号才与它在帧文件中的位置一致，增量输出也会覆盖上一帧的所有单元格。
+pub fn normalize_frames(frames: &mut [Vec<String>]) -> (usize, usize) {
+    let width = frames
+        .iter()
+        .flatten()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    let height = frames.iter().map(Vec::len).max().unwrap_or(0);
+
+    for frame in frames.iter_mut() {
+        frame.resize_with(height, String::new);
+        for row in frame.iter_mut() {
+            let missing = width - row.chars().count();
+            row.extend(std::iter::repeat_n(' ', missing));
+        }
+    }
+    (width, height)
+}
+
 impl RenderSize {
-    pub fn new(terminal_width: u16, terminal_height: u16) -> Self {
-        let term_half_width = (terminal_width / 2) as usize;
-        let min_col = (FRAME_WIDTH.saturating_sub(term_half_width)).saturating_div(2);
-        let max_col = min_col + term_half_width;
-        let min_row = (FRAME_HEIGHT.saturating_sub(terminal_height as usize)).saturating_div(2);
+    /// 计算大小为 `frame_width` x `frame_height` 的帧在终端中居中显示时的裁剪范围
+    ///
+    /// 半块模式下终端的一列对应帧的一列、一行对应帧的两行，盲文模式下分别为两列和四行。
+    pub fn new(
+        (frame_width, frame_height): (usize, usize),
+        terminal_width: u16,
+        terminal_height: u16,
+        renderer: Renderer,
+    ) -> Self {
+        let visible_cols = terminal_width as usize / renderer.cell_width() * renderer.cell_cols();
+        let rows = renderer.rows_per_line();
+        let min_col = (frame_width.saturating_sub(visible_cols)).saturating_div(2);
+        let max_col = min_col + visible_cols;
+        let min_row =
+            (frame_height.saturating_sub(terminal_height as usize * rows)).saturating_div(2);
         // 减去终端高度减去1，因为终端坐标系从 0 开始
-        let max_row = min_row + (terminal_height - 1) as usize;
+        let max_row = min_row + terminal_height.saturating_sub(1) as usize * rows;
 
         Self {
             min_col,
@@ -858,8 +2345,51 @@ impl RenderSize {
             max_row,
         }
     }
+    /// 水平平移裁剪窗口，到达边缘后反向
+    ///
+    /// `offset` 为累计平移的列数，帧宽度不超过终端宽度时不平移。
+    pub fn panned(self, offset: usize, frame_width: usize) -> Self {
+        let visible = self.max_col - self.min_col;
+        let range = frame_width.saturating_sub(visible);
+        if range == 0 {
+            return self;
+        }
+
+        // 来回一趟为 2 * range，前半程向右，后半程向左
+        let position = offset % (2 * range);
+        let min_col = if position <= range {
+            position
+        } else {
+            2 * range - position
+        };
+        Self {
+            min_col,
+            max_col: min_col + visible,
+            ..self
+        }
+    }
+}
+
+/// 宽或高为 0 时视为终端大小不可用，回退到 `fallback`
+///
+/// 只在第一次回退时输出警告，避免每帧重复。
+pub fn usable_size(size: (u16, u16), fallback: (u16, u16)) -> (u16, u16) {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if size.0 > 0 && size.1 > 0 {
+        return size;
+    }
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "Terminal size {}x{} is unusable, falling back to {}x{}",
+            size.0, size.1, fallback.0, fallback.1
+        );
+    }
+    fallback
 }
 
+/// 默认计数器格式
+pub const DEFAULT_COUNTER_FORMAT: &str = "You have nyaned for {seconds} seconds!";
+
 pub struct NyanedTime {
     // 计数器宽度
     // pub width: usize,
@@ -877,6 +2407,8 @@ pub struct NyanedTime {
     pub nyaned: String,
     /// 添加背景的计数文本
     pub counter_text: String,
+    /// 只有文本背景色、两侧不填充的计数文本
+    pub styled: String,
 }
 
 impl NyanedTime {
@@ -886,11 +2418,20 @@ impl NyanedTime {
     ///
     /// * `start_time`: 开始时间
     /// * `terminal_width`: 终端宽度
+    /// * `theme`: 动画主题，用于计数器两侧的背景色
+    /// * `mode`: 颜色输出模式
+    /// * `format`: 计数器格式，见 [`NyanedTime::format`]
     ///
     /// ## Returns
     ///
     /// * `(counter_text, counter_width)`: 计数器文本和计数器宽度
-    pub fn new(start_time: Instant, terminal_width: u16) -> Self {
+    pub fn new(
+        start_time: Instant,
+        terminal_width: u16,
+        theme: &Theme,
+        mode: ColorMode,
+        format: &str,
+    ) -> Self {
         // 计数器显式长度，终端与单个帧的长度
         let counter_width = if usize::from(terminal_width) < FRAME_WIDTH {
             terminal_width as usize
@@ -899,15 +2440,16 @@ impl NyanedTime {
         };
 
         let elapsed = start_time.elapsed().as_secs();
-        let nyaned = format!("You have nyaned for {:.1} seconds!", elapsed);
-        let text_len = nyaned.len();
-        let bg = Color::TrueColor { r: 0, g: 0, b: 91 };
-        let padding = (counter_width - text_len) / 2 + 7;
+        let nyaned = Self::format(format, elapsed);
+        let text_len = Self::text_len(&nyaned);
+        let padding = counter_width.saturating_sub(text_len) / 2 + 7;
+        let background = theme.render_color(',', mode);
+        let styled = Self::style(&nyaned, mode);
         let counter_text = format!(
             "{}{}{}",
-            "\x1B[48;5;17m  \x1B[0m".repeat(padding + 1),
-            nyaned.on_color(bg),
-            "\x1B[48;5;17m  \x1B[0m".repeat(padding),
+            background.repeat(padding + 1),
+            styled,
+            background.repeat(padding),
         );
         Self {
             // width: text_len,
@@ -918,6 +2460,836 @@ impl NyanedTime {
             // padding,
             nyaned,
             counter_text,
+            styled,
+        }
+    }
+
+    /// 按格式生成计数文本
+    ///
+    /// 支持的占位符：
+    ///
+    /// * `{seconds}`: 已运行的秒数
+    /// * `{hms}`: 已运行时间，格式为 `HH:MM:SS`
+    pub fn format(format: &str, elapsed: u64) -> String {
+        let hms = format!(
+            "{:02}:{:02}:{:02}",
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60
+        );
+        format
+            .replace("{seconds}", &elapsed.to_string())
+            .replace("{hms}", &hms)
+    }
+
+    /// 给计数文本加上背景色，无颜色模式下原样返回
+    pub fn style(text: &str, mode: ColorMode) -> String {
+        if mode == ColorMode::None {
+            text.normal().to_string()
+        } else {
+            text.on_color(COUNTER_BG).to_string()
+        }
+    }
+
+    /// 计数文本在终端中占用的列数
+    ///
+    /// 跳过 CSI 转义序列，组合字符和零宽字符不占列，东亚宽字符和 emoji 占两列。
+    pub fn text_len(text: &str) -> usize {
+        visible_chars(text).map(|(_, width)| width).sum()
+    }
+
+    /// 从第 `offset` 列开始截取 `width` 列的计数文本，文本首尾相接循环滚动
+    ///
+    /// 结果总是恰好占 `width` 列：被窗口边缘切开的宽字符用空格代替，
+    /// 转义序列会被去掉。
+    pub fn scrolled(&self, width: usize, offset: usize) -> String {
+        let gap = std::iter::repeat_n((' ', 1), SCROLL_GAP);
+        let cells: Vec<_> = visible_chars(&self.nyaned).chain(gap).collect();
+        let cycle = self.text_len + SCROLL_GAP;
+        let start = offset % cycle;
+
+        let mut out = String::new();
+        let mut filled = 0;
+        let mut col = 0;
+        for &(ch, ch_width) in cells.iter().cycle() {
+            let next = col + ch_width;
+            if ch_width == 0 {
+                // 组合字符跟随已经输出的字符
+                if col >= start && filled > 0 && filled < width {
+                    out.push(ch);
+                }
+                continue;
+            }
+            if next <= start {
+                col = next;
+                continue;
+            }
+            // 宽字符的左半边在窗口之外，或右半边超出窗口
+            let visible = if col < start { next - start } else { ch_width };
+            if visible < ch_width || filled + ch_width > width {
+                let pad = visible.min(width - filled);
+                out.extend(std::iter::repeat_n(' ', pad));
+                filled += pad;
+            } else {
+                out.push(ch);
+                filled += ch_width;
+            }
+            col = next;
+            if filled >= width {
+                break;
+            }
+        }
+        out
+    }
+
+    /// 计数器一行的输出，`plain` 时只有文本，否则带两侧的背景
+    pub fn line(&self, plain: bool) -> &str {
+        if plain {
+            &self.nyaned
+        } else {
+            &self.counter_text
+        }
+    }
+}
+
+/// 计数器文本的背景色
+const COUNTER_BG: Color = Color::TrueColor { r: 0, g: 0, b: 91 };
+
+/// 滚动的计数器首尾之间的空白列数
+const SCROLL_GAP: usize = 3;
+
+/// 文本中的可见字符及其列数，跳过 CSI 转义序列
+pub fn visible_chars(text: &str) -> impl Iterator<Item = (char, usize)> + '_ {
+    let mut chars = text.chars();
+    std::iter::from_fn(move || {
+        loop {
+            let ch = chars.next()?;
+            if ch == '\x1B' {
+                // CSI 序列以 0x40..=0x7E 范围内的字符结束
+                if chars.next() == Some('[') {
+                    for ch in chars.by_ref() {
+                        if ('\x40'..='\x7E').contains(&ch) {
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            return Some((ch, char_width(ch)));
+        }
+    })
+}
+
+/// 字符在终端中占用的列数
+fn char_width(ch: char) -> usize {
+    match u32::from(ch) {
+        // 控制字符
+        0..=0x1F | 0x7F..=0x9F => 0,
+        // 组合字符、零宽字符和变体选择符
+        0x0300..=0x036F | 0x200B..=0x200F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F => 0,
+        // 东亚宽字符、全角字符和 emoji
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// 构建一帧所需的渲染选项，与命令行参数解耦
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions<'a> {
+    /// 动画主题
+    pub theme: &'a Theme,
+    /// 颜色输出模式
+    pub color_mode: ColorMode,
+    /// 不在帧之前清屏
+    pub no_clear: bool,
+    /// 不显示计数器
+    pub no_counter: bool,
+    /// 计数器格式，见 [`NyanedTime::format`]
+    pub counter_format: &'a str,
+    /// 帧的翻转方式
+    pub flip: Flip,
+    /// 计数器水平居中
+    pub center_counter: bool,
+    /// 计数器只输出文本，不包含转义序列
+    pub plain_counter: bool,
+    /// 彩虹颜色的相位，见 [`Theme::render_color_at`]
+    pub color_phase: usize,
+    /// 行尾使用 `\r\n`，否则使用 `\n`
+    pub crlf: bool,
+    /// 帧的绘制方式
+    pub renderer: Renderer,
+}
+
+/// 翻转并裁剪后屏幕上的每一行
+///
+/// 每行包含 [`Renderer::rows_per_line`] 行帧，帧的行数不够时其余的行为空。
+pub fn screen_lines<'a>(
+    frame: Frame<'a>,
+    size: RenderSize,
+    options: &RenderOptions,
+) -> impl Iterator<Item = [&'a str; MAX_ROWS_PER_LINE]> + 'a {
+    let rows_per_line = options.renderer.rows_per_line();
+    // 翻转后再裁剪，保证猫仍在屏幕中间
+    let mut rows = options
+        .flip
+        .rows(frame)
+        .skip(size.min_row)
+        .take(size.max_row.saturating_sub(size.min_row));
+    std::iter::from_fn(move || {
+        let mut line = [""; MAX_ROWS_PER_LINE];
+        line[0] = rows.next()?;
+        for row in &mut line[1..rows_per_line] {
+            *row = rows.next().unwrap_or("");
+        }
+        Some(line)
+    })
+}
+
+/// 屏幕上一行中可见的单元格，按从左到右的顺序
+pub fn screen_cells<'a>(
+    line: [&'a str; MAX_ROWS_PER_LINE],
+    size: RenderSize,
+    options: &RenderOptions<'a>,
+) -> impl Iterator<Item = &'static str> + 'a {
+    let RenderOptions {
+        theme,
+        color_mode,
+        flip,
+        color_phase,
+        renderer,
+        ..
+    } = *options;
+    let cell_cols = renderer.cell_cols();
+    let rows_per_line = renderer.rows_per_line();
+    let mut rows: [_; MAX_ROWS_PER_LINE] = std::array::from_fn(|y| {
+        (y < rows_per_line).then(|| flip.chars(line[y]).skip(size.min_col))
+    });
+    let mut remaining = size.max_col.saturating_sub(size.min_col);
+
+    std::iter::from_fn(move || {
+        let width = cell_cols.min(remaining);
+        if width == 0 {
+            return None;
+        }
+        remaining -= width;
+
+        // 单元格覆盖的字符，按行排列，缺少的字符视为空格
+        let mut pixels = [' '; MAX_ROWS_PER_LINE * 2];
+        for (y, row) in rows.iter_mut().flatten().enumerate() {
+            for x in 0..width {
+                match row.next() {
+                    Some(c) => pixels[y * cell_cols + x] = c,
+                    // 第一行结束即到达帧的右边缘
+                    None if y == 0 && x == 0 => return None,
+                    None => {}
+                }
+            }
+        }
+
+        Some(match renderer {
+            // 图片只在独立模式中输出，其他模式按块绘制
+            Renderer::Block | Renderer::Kitty | Renderer::ITerm2 => {
+                theme.render_color_at(pixels[0], color_mode, color_phase)
+            }
+            Renderer::HalfBlock => {
+                theme.render_half_block(pixels[0], pixels[1], color_mode, color_phase)
+            }
+            Renderer::Braille => theme.render_braille(pixels, color_mode, color_phase),
+        })
+    })
+}
+
+/// 构建完整的一帧，包括清屏、帧内容和计数器，追加到 `frame_data`
+///
+/// ## Arguments
+///
+/// * `frame_data`: 输出缓冲区，调用方可以在帧之间复用
+/// * `options`: 渲染选项
+/// * `width`: 终端宽度，用于计数器
+/// * `size`: 帧在终端中的裁剪范围
+/// * `frame_idx`: 主题中的帧索引
+/// * `start_time`: 动画开始时间，用于计数器
+pub fn build_frame(
+    frame_data: &mut String,
+    options: &RenderOptions,
+    width: u16,
+    size: RenderSize,
+    frame_idx: usize,
+    start_time: Instant,
+) {
+    let RenderOptions {
+        theme, color_mode, ..
+    } = *options;
+
+    // 渲染帧到缓冲区
+    if !options.no_clear {
+        frame_data.push_str("\x1B[2J\x1B[1;1H"); // 清屏
+    }
+
+    // 构建帧内容
+    for line in screen_lines(theme.frames.get(frame_idx), size, options) {
+        for cell in screen_cells(line, size, options) {
+            frame_data.push_str(cell);
+        }
+        frame_data.push_str(if options.crlf { "\r\n" } else { "\n" });
+    }
+
+    // 显示计数器
+    if !options.no_counter {
+        let nyaned_time =
+            NyanedTime::new(start_time, width, theme, color_mode, options.counter_format);
+        let plain = options.plain_counter;
+        if nyaned_time.text_len >= width.into() {
+            frame_data.push_str(&nyaned_time.nyaned);
+        } else if options.center_counter {
+            let (col, _) = counter_position((width, 1), nyaned_time.text_len, true);
+            frame_data.push_str(&" ".repeat(col.into()));
+            frame_data.push_str(if plain {
+                &nyaned_time.nyaned
+            } else {
+                &nyaned_time.styled
+            });
+        } else {
+            frame_data.push_str(nyaned_time.line(plain));
+        }
+    }
+}
+
+/// 计数器在终端中的位置 `(列, 行)`
+///
+/// 计数器总是位于最后一行，动画只使用之上的行（见 [`RenderSize::new`]）。
+/// 终端只有一行时没有动画行，只显示计数器。
+pub fn counter_position(
+    (terminal_width, terminal_height): (u16, u16),
+    text_len: usize,
+    center: bool,
+) -> (u16, u16) {
+    let row = terminal_height.saturating_sub(1);
+    let col = if center {
+        let text_len = u16::try_from(text_len).unwrap_or(u16::MAX);
+        terminal_width.saturating_sub(text_len) / 2
+    } else {
+        0
+    };
+    (col, row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_color_keeps_space_uncolored() {
+        for mode in ColorMode::ALL {
+            assert_eq!(CLASSIC.render_color(' ', mode), "  ");
+        }
+    }
+
+    #[test]
+    fn render_color_known_glyph() {
+        assert_eq!(
+            CLASSIC.render_color(',', ColorMode::TrueColor),
+            "\x1B[48;2;0;51;102m  \x1B[0m"
+        );
+        assert_eq!(
+            CLASSIC.render_color(',', ColorMode::Color256),
+            "\x1B[48;5;17m  \x1B[0m"
+        );
+        assert_eq!(
+            CLASSIC.render_color(',', ColorMode::Color16),
+            "\x1B[40m  \x1B[0m"
+        );
+        assert_eq!(CLASSIC.render_color('.', ColorMode::None), "**");
+    }
+
+    #[test]
+    fn render_size_with_zero_terminal() {
+        let size = RenderSize::new((FRAME_WIDTH, FRAME_HEIGHT), 0, 0, Renderer::Block);
+        assert!(size.min_col <= size.max_col);
+        assert!(size.min_row <= size.max_row);
+
+        let options = RenderOptions {
+            theme: &CLASSIC,
+            color_mode: ColorMode::None,
+            no_clear: true,
+            no_counter: false,
+            counter_format: DEFAULT_COUNTER_FORMAT,
+            flip: Flip::default(),
+            center_counter: false,
+            plain_counter: false,
+            color_phase: 0,
+            crlf: false,
+            renderer: Renderer::Block,
+        };
+        let mut frame = String::new();
+        build_frame(&mut frame, &options, 0, size, 0, Instant::now());
+    }
+
+    #[test]
+    fn scale_replicates_cells() {
+        let frames: &'static [&'static [&'static str]] = Box::leak(Box::new([&["ab", "cd"][..]]));
+        let theme =
+            Theme::from_frames("sample", Frames::Static(frames), Arc::default()).into_static();
+        assert!(std::ptr::eq(theme.with_scale(1), theme));
+
+        let scaled = theme.with_scale(3);
+        assert_eq!((scaled.width, scaled.height), (6, 6));
+        assert_eq!(
+            scaled.frames.get(0),
+            ["aaabbb", "aaabbb", "aaabbb", "cccddd", "cccddd", "cccddd"]
+        );
+        assert!(std::ptr::eq(theme.with_scale(3), scaled));
+    }
+
+    #[test]
+    fn ascii_only_leaks_no_multibyte_glyphs() {
+        let frames: &'static [&'static [&'static str]] =
+            Box::leak(Box::new([&["█▓▒░", "┌─┐│", "★é,."][..]]));
+        let theme =
+            Theme::from_frames("sample", Frames::Static(frames), Arc::default()).to_ascii_only();
+        let size = RenderSize::new((theme.width, theme.height), 80, 24, Renderer::Block);
+        for color_mode in ColorMode::ALL {
+            let options = RenderOptions {
+                theme: &theme,
+                color_mode,
+                no_clear: true,
+                no_counter: true,
+                counter_format: DEFAULT_COUNTER_FORMAT,
+                flip: Flip::default(),
+                center_counter: false,
+                plain_counter: false,
+                color_phase: 0,
+                crlf: false,
+                renderer: Renderer::Block,
+            };
+            let mut frame = String::new();
+            build_frame(&mut frame, &options, 80, size, 0, Instant::now());
+            assert!(frame.is_ascii(), "{frame:?}");
+        }
+
+        assert_eq!(theme.render_color('█', ColorMode::None), "##");
+        assert_eq!(theme.render_color('┌', ColorMode::None), "++");
+        assert_eq!(theme.render_color('é', ColorMode::None), "??");
+        assert_eq!(CLASSIC.render_color('█', ColorMode::None), "██");
+    }
+
+    #[test]
+    fn color_phase_rotates_rainbow_only() {
+        let mode = ColorMode::Color256;
+        assert_eq!(
+            CLASSIC.render_color_at('>', mode, 0),
+            CLASSIC.render_color('>', mode)
+        );
+        assert_eq!(
+            CLASSIC.render_color_at('>', mode, 1),
+            CLASSIC.render_color('&', mode)
+        );
+        assert_eq!(
+            CLASSIC.render_color_at(';', mode, 1),
+            CLASSIC.render_color('>', mode)
+        );
+        assert_eq!(
+            CLASSIC.render_color_at('>', mode, RAINBOW.len()),
+            CLASSIC.render_color('>', mode)
+        );
+        assert_eq!(
+            CLASSIC.render_color_at('@', mode, 3),
+            CLASSIC.render_color('@', mode)
+        );
+    }
+
+    fn counter(text: &str) -> NyanedTime {
+        NyanedTime {
+            text_len: NyanedTime::text_len(text),
+            nyaned: text.to_string(),
+            counter_text: String::new(),
+            styled: String::new(),
+        }
+    }
+
+    #[test]
+    fn scrolled_counter_wraps_around() {
+        let time = counter("nyan");
+        assert_eq!(time.scrolled(4, 0), "nyan");
+        assert_eq!(time.scrolled(4, 2), "an  ");
+        assert_eq!(time.scrolled(4, 5), "  ny");
+        // 一个周期为文本加上空白
+        assert_eq!(time.scrolled(4, 7), time.scrolled(4, 0));
+        assert_eq!(time.scrolled(10, 1), "yan   nyan");
+    }
+
+    #[test]
+    fn scrolled_counter_fits_narrow_terminals() {
+        let time = counter("喵了 5 秒 e\u{301}!");
+        for width in 0..=5 {
+            for offset in 0..40 {
+                let text = time.scrolled(width, offset);
+                assert_eq!(NyanedTime::text_len(&text), width, "{offset}: {text:?}");
+            }
+        }
+        // 被切开的宽字符用空格代替
+        assert_eq!(time.scrolled(3, 1), " 了");
+        assert_eq!(time.scrolled(3, 0), "喵 ");
+        assert_eq!(time.scrolled(3, 10), "e\u{301}! ");
+    }
+
+    #[test]
+    fn theme_preview_stacks_on_narrow_terminals() {
+        let wide = theme_preview(THEMES, 240, ColorMode::None);
+        for theme in THEMES {
+            assert!(wide[0].contains(theme.name), "{:?}", wide[0]);
+        }
+
+        for width in [1, 10, 40, 100] {
+            let lines = theme_preview(THEMES, width, ColorMode::None);
+            // 名称按格子宽度截断
+            let label =
+                |theme: &Theme| &theme.name[..theme.name.len().min(usize::from(width).max(2))];
+            let labels: usize = lines
+                .iter()
+                .map(|line| {
+                    THEMES
+                        .iter()
+                        .filter(|theme| line.contains(label(theme)))
+                        .count()
+                })
+                .sum();
+            assert_eq!(labels, THEMES.len(), "width {width}");
+            for line in &lines {
+                // 终端宽度为 1 时每个单元格仍然占两列
+                assert!(
+                    NyanedTime::text_len(line) <= usize::from(width).max(2),
+                    "width {width}: {line:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn counter_text_len_counts_columns() {
+        assert_eq!(NyanedTime::text_len("You have nyaned for 5 seconds!"), 30);
+        assert_eq!(NyanedTime::text_len("喵了 5 秒"), 9);
+        assert_eq!(NyanedTime::text_len("🐱 5s"), 5);
+        assert_eq!(NyanedTime::text_len("e\u{301}"), 1);
+        assert_eq!(NyanedTime::text_len("\x1B[48;5;17m5s\x1B[0m"), 2);
+    }
+
+    #[test]
+    fn plain_counter_has_no_escapes() {
+        let mut options = RenderOptions {
+            theme: &CLASSIC,
+            color_mode: ColorMode::TrueColor,
+            no_clear: true,
+            no_counter: false,
+            counter_format: "{hms}",
+            flip: Flip::default(),
+            center_counter: false,
+            plain_counter: true,
+            color_phase: 0,
+            crlf: false,
+            renderer: Renderer::Block,
+        };
+        let size = RenderSize::new((FRAME_WIDTH, FRAME_HEIGHT), 80, 24, Renderer::Block);
+        for center_counter in [false, true] {
+            options.center_counter = center_counter;
+            let mut frame = String::new();
+            build_frame(&mut frame, &options, 80, size, 0, Instant::now());
+            let counter = frame.rsplit('\n').next().unwrap();
+            assert_eq!(counter.trim_start(), "00:00:00");
+        }
+    }
+
+    #[test]
+    fn counter_on_last_visible_row() {
+        assert_eq!(counter_position((80, 24), 10, false), (0, 23));
+        assert_eq!(counter_position((80, 24), 10, true), (35, 23));
+        assert_eq!(counter_position((80, 2), 10, false), (0, 1));
+        // 只有一行时计数器占据该行，动画没有可用的行
+        assert_eq!(counter_position((80, 1), 10, false), (0, 0));
+        let size = RenderSize::new((FRAME_WIDTH, FRAME_HEIGHT), 80, 1, Renderer::Block);
+        assert_eq!(size.max_row - size.min_row, 0);
+        // 文本比终端宽时从第一列开始
+        assert_eq!(counter_position((4, 1), 10, true), (0, 0));
+    }
+
+    #[test]
+    fn scale_rgb_clamps() {
+        assert_eq!(scale_rgb((100, 200, 50), 1.0), (100, 200, 50));
+        assert_eq!(scale_rgb((100, 200, 50), 0.5), (50, 100, 25));
+        assert_eq!(scale_rgb((100, 200, 50), 2.0), (200, 255, 100));
+        assert_eq!(scale_rgb((255, 255, 255), 0.1), (26, 26, 26));
+    }
+
+    #[test]
+    fn brightness_keeps_plain_mode() {
+        let dim = CLASSIC.with_brightness(0.5);
+        assert_eq!(dim.render_color('.', ColorMode::None), "**");
+        assert_eq!(
+            dim.render_color('.', ColorMode::TrueColor),
+            "\x1B[48;2;128;128;128m  \x1B[0m"
+        );
+        // 不在调色板中的字符不受影响
+        assert_eq!(dim.render_color('é', ColorMode::TrueColor), "éé");
+    }
+
+    #[test]
+    fn usable_size_falls_back_on_zero() {
+        assert_eq!(usable_size((0, 0), (80, 24)), (80, 24));
+        assert_eq!(usable_size((100, 0), (80, 24)), (80, 24));
+        assert_eq!(usable_size((100, 40), (80, 24)), (100, 40));
+    }
+
+    #[test]
+    fn render_color_unknown_unicode() {
+        for mode in ColorMode::ALL {
+            assert_eq!(CLASSIC.render_color('é', mode), "éé");
+            assert_eq!(MONOCHROME.render_color('é', mode), "éé");
+        }
+    }
+
+    #[test]
+    fn detection_explains_choice() {
+        let cases = [
+            (
+                Some("truecolor"),
+                Some("xterm"),
+                ColorMode::TrueColor,
+                "COLORTERM",
+            ),
+            (
+                Some("yes"),
+                Some("xterm-256color"),
+                ColorMode::Color256,
+                "TERM",
+            ),
+            (None, Some("linux"), ColorMode::Color16, "TERM"),
+            (None, Some("dumb"), ColorMode::None, "TERM"),
+            (None, Some("foo"), ColorMode::Color256, "not recognized"),
+            (None, Some(""), ColorMode::None, "not set"),
+            (None, None, ColorMode::None, "not set"),
+        ];
+        for (colorterm, term, mode, reason) in cases {
+            let (detected, why) = ColorMode::detect_from(colorterm, term);
+            assert_eq!(detected, mode, "{colorterm:?} {term:?}");
+            assert!(why.contains(reason), "{why}");
+        }
+    }
+
+    #[test]
+    fn ragged_frames_are_padded() {
+        let mut frames = vec![
+            vec!["ab".to_string(), "abcd".to_string(), String::new()],
+            vec!["█".to_string()],
+        ];
+        assert_eq!(normalize_frames(&mut frames), (4, 3));
+        assert_eq!(frames[0], ["ab  ", "abcd", "    "]);
+        assert_eq!(frames[1], ["█   ", "    ", "    "]);
+    }
+
+    #[test]
+    fn ragged_frames_align_after_flip_and_crop() {
+        let mut frames = vec![vec!["ab".to_string(), "abcd".to_string()]];
+        normalize_frames(&mut frames);
+        let rows = frames[0].iter().map(String::as_str).collect::<Vec<_>>();
+
+        // 左右翻转后每行的 `a` 都在最后一列
+        let flip = Flip {
+            vertical: false,
+            horizontal: true,
+        };
+        for row in &rows {
+            assert_eq!(flip.chars(row).position(|c| c == 'a'), Some(3), "{row:?}");
+        }
+
+        // 终端只能显示两列时裁掉两侧各一列，每行看到的都是帧中间的两列
+        let size = RenderSize::new((4, 2), 4, 10, Renderer::Block);
+        let visible = rows
+            .iter()
+            .map(|row| {
+                row.chars()
+                    .skip(size.min_col)
+                    .take(size.max_col - size.min_col)
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(visible, ["b ", "bc"]);
+    }
+
+    #[test]
+    fn animations_keep_frame_size() {
+        for animation in ANIMATIONS {
+            let theme = THEMES[0].with_animation(animation);
+            assert!(std::ptr::eq(theme, THEMES[0].with_animation(animation)));
+            assert_eq!(theme.frames.len(), FRAMES.len(), "{}", animation.name);
+            for frame in theme.frames.iter() {
+                assert_eq!(frame.len(), FRAME_HEIGHT, "{}", animation.name);
+                assert!(frame.rows().all(|row| row.chars().count() == FRAME_WIDTH));
+            }
+        }
+
+        // 圣诞帽在右耳上方，镜像后猫头朝左
+        let xmas = THEMES[0].with_animation(Animation::by_name("xmas").unwrap());
+        let ear_row = xmas.frames.get(0).rows().position(|row| row.contains('*'));
+        let hat_row = xmas.frames.get(0).rows().position(|row| row.contains('r'));
+        assert!(hat_row < ear_row, "{hat_row:?} {ear_row:?}");
+        assert_eq!(
+            xmas.render_color('r', ColorMode::Color256),
+            "\x1B[48;5;160m  \x1B[0m"
+        );
+
+        let tacnayn = THEMES[0].with_animation(Animation::by_name("tacnayn").unwrap());
+        let row = FRAMES[0][30].chars().rev().collect::<String>();
+        assert_eq!(
+            tacnayn.frames.get(0).row(30).replace(['b', 'B'], "@"),
+            row.replace('$', "@")
+        );
+    }
+
+    #[test]
+    fn truecolor_uses_exact_colors() {
+        let classic = &THEMES[0];
+        assert_eq!(
+            classic.render_color('>', ColorMode::TrueColor),
+            "\x1B[48;2;255;0;0m  \x1B[0m"
+        );
+        assert_eq!(classic.rgb('='), Some((0, 153, 255)));
+        // 其他模式仍使用 256 色索引
+        assert_eq!(
+            classic.render_color('>', ColorMode::Color256),
+            "\x1B[48;5;196m  \x1B[0m"
+        );
+        for (glyph, ..) in classic.truecolor {
+            assert!(
+                classic.palette.iter().any(|(own, _)| own == glyph),
+                "{glyph}"
+            );
+        }
+
+        // 动画换掉的颜色不使用主题的精确颜色
+        let tacnayn = classic.with_animation(Animation::by_name("tacnayn").unwrap());
+        assert_eq!(tacnayn.rgb('>'), Some(xterm_to_rgb(232)));
+        assert_eq!(tacnayn.rgb(','), Some((0, 51, 102)));
+    }
+
+    #[test]
+    fn eight_colors_use_basic_backgrounds() {
+        for theme in THEMES {
+            for &(glyph, _) in theme.palette {
+                let cell = theme.render_color(glyph, ColorMode::Color8);
+                let sgr = cell
+                    .strip_prefix("\x1B[")
+                    .and_then(|rest| rest.split_once('m'))
+                    .and_then(|(sgr, _)| sgr.parse::<u8>().ok());
+                assert!(sgr.is_some_and(|sgr| (40..=47).contains(&sgr)), "{cell:?}");
+            }
+        }
+        assert_eq!(ColorMode::from_term("ansi"), Some(ColorMode::Color8));
+
+        // 服务端限制为 16 色时高于 16 色的客户端降级，更低的保持不变
+        assert_eq!(
+            ColorMode::TrueColor.at_most(ColorMode::Color16),
+            ColorMode::Color16
+        );
+        assert_eq!(ColorMode::None.at_most(ColorMode::Color16), ColorMode::None);
+        assert_eq!(
+            ColorMode::Color8.at_most(ColorMode::Color16),
+            ColorMode::Color8
+        );
+    }
+
+    #[test]
+    fn half_block_packs_two_rows_per_line() {
+        let half = |top, bottom, mode| CLASSIC.render_half_block(top, bottom, mode, 0);
+        assert_eq!(
+            half(',', '.', ColorMode::Color256),
+            "\x1B[38;5;17;48;5;231m▀\x1B[0m"
+        );
+        assert_eq!(half(',', ',', ColorMode::Color256), "\x1B[48;5;17m \x1B[0m");
+        assert_eq!(half(' ', ',', ColorMode::Color256), "\x1B[38;5;17m▄\x1B[0m");
+        assert_eq!(
+            half('>', ',', ColorMode::TrueColor),
+            "\x1B[38;2;255;0;0;48;2;0;51;102m▀\x1B[0m"
+        );
+        assert_eq!(half(',', '.', ColorMode::Color16), "\x1B[30;107m▀\x1B[0m");
+        assert_eq!(half('.', ',', ColorMode::None), "*");
+        assert_eq!(half(' ', ' ', ColorMode::Color256), " ");
+
+        // 64 行的帧在 24 行的终端中显示 23 行，每行 64 列
+        let options = RenderOptions {
+            theme: &CLASSIC,
+            color_mode: ColorMode::Color256,
+            no_clear: true,
+            no_counter: true,
+            counter_format: DEFAULT_COUNTER_FORMAT,
+            flip: Flip::default(),
+            center_counter: false,
+            plain_counter: false,
+            color_phase: 0,
+            crlf: false,
+            renderer: Renderer::HalfBlock,
+        };
+        let size = RenderSize::new((FRAME_WIDTH, FRAME_HEIGHT), 80, 24, Renderer::HalfBlock);
+        assert_eq!(size.max_row - size.min_row, 46);
+        let mut frame = String::new();
+        build_frame(&mut frame, &options, 80, size, 0, Instant::now());
+        let lines = frame.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 23);
+        for line in lines {
+            assert_eq!(NyanedTime::text_len(line), FRAME_WIDTH, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn braille_packs_two_by_four_pixels() {
+        let braille = |pixels, mode| CLASSIC.render_braille(pixels, mode, 0);
+        assert_eq!(
+            braille([','; 8], ColorMode::Color256),
+            "\x1B[48;5;17m \x1B[0m"
+        );
+        let mut pixels = [','; 8];
+        pixels[0] = '.';
+        pixels[7] = '.';
+        assert_eq!(
+            braille(pixels, ColorMode::Color256),
+            "\x1B[38;5;231;48;5;17m⢁\x1B[0m"
+        );
+        assert_eq!(braille(pixels, ColorMode::None), "⢁");
+        assert_eq!(braille([' '; 8], ColorMode::None), " ");
+
+        // 64x64 的帧在 40x14 的终端中显示 13 行，每行 32 列
+        let options = RenderOptions {
+            theme: &CLASSIC,
+            color_mode: ColorMode::Color256,
+            no_clear: true,
+            no_counter: true,
+            counter_format: DEFAULT_COUNTER_FORMAT,
+            flip: Flip::default(),
+            center_counter: false,
+            plain_counter: false,
+            color_phase: 0,
+            crlf: false,
+            renderer: Renderer::Braille,
+        };
+        let size = RenderSize::new((FRAME_WIDTH, FRAME_HEIGHT), 40, 14, Renderer::Braille);
+        let mut frame = String::new();
+        build_frame(&mut frame, &options, 40, size, 0, Instant::now());
+        let lines = frame.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 13);
+        for line in lines {
+            assert_eq!(NyanedTime::text_len(line), FRAME_WIDTH / 2, "{line:?}");
         }
     }
 }
@@ -0,0 +1,88 @@
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::{Context, bail};
+
+use crate::{
+    animation::{Frames, Theme, normalize_frames, validate_frames},
+    palette::CustomPalette,
+};
+
+/// 分隔调色板与帧、帧与帧的行
+const SEPARATOR: &str = "---";
+
+/// 读取动画文件并创建主题
+///
+/// 文件开头是与 `--palette` 格式相同的调色板，之后每个 `---` 行开始新的一帧。
+/// 调色板中的字符按对应的颜色渲染，其他字符按原样输出。
+pub fn load(path: &Path) -> anyhow::Result<Theme> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("read animation file {} failed", path.display()))?;
+    let (palette, frames) = parse(&content, path)
+        .with_context(|| format!("invalid animation file {}", path.display()))?;
+    Ok(Theme::from_frames(
+        "animation",
+        Frames::new(frames),
+        Arc::new(palette),
+    ))
+}
+
+/// 解析动画文件，返回调色板和补齐后的帧，空帧会被忽略
+fn parse(content: &str, path: &Path) -> anyhow::Result<(CustomPalette, Vec<Vec<String>>)> {
+    let mut sections = content.split_inclusive('\n');
+    let palette = sections
+        .by_ref()
+        .take_while(|line| line.trim_end() != SEPARATOR)
+        .collect::<String>();
+    if palette.len() == content.len() {
+        bail!("missing a `{SEPARATOR}` line between the palette and the frames");
+    }
+
+    let mut frames = Vec::new();
+    let mut frame = Vec::new();
+    for line in sections {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim_end() == SEPARATOR {
+            if !frame.is_empty() {
+                frames.push(std::mem::take(&mut frame));
+            }
+            continue;
+        }
+        frame.push(line.to_string());
+    }
+    if !frame.is_empty() {
+        frames.push(frame);
+    }
+
+    normalize_frames(&mut frames);
+    validate_frames(&frames)?;
+    Ok((CustomPalette::parse(&palette, path), frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::ColorMode;
+
+    #[test]
+    fn palette_and_frames_are_split() {
+        let content = "\
+# 注释
+* = #ffcc00
+---
+ *
+***
+---
+---
+*
+";
+        let (palette, frames) = parse(content, Path::new("test.txt")).unwrap();
+        assert_eq!(frames, [[" * ", "***"], ["*  ", "   "]]);
+        assert_eq!(palette.rgb('*'), Some((0xff, 0xcc, 0x00)));
+        assert!(palette.get(' ', ColorMode::TrueColor).is_none());
+
+        let err = parse("* = 1\n *\n", Path::new("test.txt")).unwrap_err();
+        assert!(err.to_string().contains("---"), "{err:#}");
+        let err = parse("---\n---\n", Path::new("test.txt")).unwrap_err();
+        assert!(err.to_string().contains("no frames"), "{err:#}");
+    }
+}
@@ -0,0 +1,630 @@
+use std::{ops::Range, time::Duration};
+
+use tokio::{
+    sync::watch,
+    time::{Instant, sleep_until},
+};
+
+use crate::{
+    animation::{
+        ColorMode, Frame, RenderOptions, RenderSize, Renderer, ThemeRef, advance_frame, build_frame,
+    },
+    cli::Args,
+};
+
+/// 动画播放器
+///
+/// 负责帧索引、计时、播放方向和各种播放限制，各模式只需把帧输出到自己的终端或连接。
+pub struct Animator {
+    theme: ThemeRef,
+    frame_idx: usize,
+    start_frame: usize,
+    // 循环播放的帧范围
+    range: Range<usize>,
+    // 命令行指定的帧范围，切换主题后重新计算实际的播放范围
+    requested_range: Option<Range<usize>>,
+    reverse: bool,
+    start_time: Instant,
+    delay: Duration,
+    // 下一帧的时间，tick 被取消时保留，重新调用后继续等待同一时间
+    next_frame: Option<Instant>,
+    // 帧率上限对应的最短帧间隔，所有帧间隔来源都会被限制
+    min_delay: Duration,
+    // 已渲染的帧数，不受 frame_idx 循环取模影响
+    rendered: usize,
+    // 已完整播放的次数
+    cycles: usize,
+    frame_limit: Option<usize>,
+    loop_limit: Option<usize>,
+    duration: Option<Duration>,
+    // 帧间隔的更新，配置重新加载后在下一帧生效
+    delay_updates: Option<watch::Receiver<Duration>>,
+    // `--frames-dir` 重新加载的帧，在下一帧生效
+    theme_updates: Option<watch::Receiver<ThemeRef>>,
+    // 每帧水平平移的列数，None 表示不平移
+    pan_speed: Option<usize>,
+    // 彩虹颜色每秒轮换的次数，None 表示彩虹只随帧变化
+    color_speed: Option<f64>,
+    renderer: Renderer,
+}
+
+impl Animator {
+    /// 根据命令行参数创建播放器，只应用时间限制
+    pub fn new(args: &Args) -> Self {
+        let frame_count = args.theme.frames.len();
+        let start_frame = args.first_frame(frame_count);
+        Self {
+            theme: args.theme.into(),
+            frame_idx: start_frame,
+            start_frame,
+            range: args.frame_range(frame_count),
+            requested_range: args.frame_range.clone(),
+            reverse: args.reverse,
+            start_time: Instant::now(),
+            delay: args.frame_delay().max(args.min_frame_delay()),
+            next_frame: None,
+            min_delay: args.min_frame_delay(),
+            rendered: 0,
+            cycles: 0,
+            frame_limit: None,
+            loop_limit: None,
+            duration: args.duration.map(Duration::from_secs),
+            delay_updates: None,
+            theme_updates: None,
+            pan_speed: args.pan.then_some(args.pan_speed.into()),
+            color_speed: args.color_speed,
+            renderer: args.renderer(),
+        }
+    }
+
+    /// 使用指定主题代替命令行参数中的主题，帧范围超出该主题的帧数时播放全部帧
+    #[cfg(feature = "http")]
+    pub fn with_theme(mut self, theme: ThemeRef) -> Self {
+        self.set_theme(theme);
+        self.frame_idx = self.start_frame;
+        self
+    }
+
+    /// 播放过程中切换主题，下一帧生效
+    ///
+    /// 帧范围超出新主题的帧数时改为播放全部帧，当前帧不在新的范围内时按范围长度回绕。
+    pub fn set_theme(&mut self, theme: ThemeRef) {
+        let frame_count = theme.frames.len();
+        self.range = self
+            .requested_range
+            .clone()
+            .filter(|range| range.end <= frame_count)
+            .unwrap_or(0..frame_count);
+        let wrap = |frame: usize, range: &Range<usize>| {
+            if range.contains(&frame) {
+                frame
+            } else {
+                range.start + frame % range.len()
+            }
+        };
+        self.start_frame = wrap(self.start_frame, &self.range);
+        self.frame_idx = wrap(self.frame_idx, &self.range);
+        self.theme = theme;
+    }
+
+    /// 取消 `--duration` 的时间限制，用于多个连接共享的动画
+    #[cfg(feature = "http")]
+    pub fn without_time_limit(mut self) -> Self {
+        self.duration = None;
+        self
+    }
+
+    /// 设置帧数和循环次数限制，循环次数为 0 表示无限循环
+    pub fn with_frame_limits(mut self, frames: Option<usize>, loop_count: Option<usize>) -> Self {
+        self.frame_limit = frames;
+        self.loop_limit = loop_count.filter(|&count| count > 0);
+        self
+    }
+
+    /// 跟随共享的帧间隔，见 [`crate::reload::watch_frame_delay`]
+    pub fn with_delay_updates(mut self, updates: watch::Receiver<Duration>) -> Self {
+        self.delay = updates.borrow().max(self.min_delay);
+        self.delay_updates = Some(updates);
+        self
+    }
+
+    /// 跟随 `--frames-dir` 重新加载的帧，见 [`crate::reload::watch_frames_dir`]
+    ///
+    /// 新的帧在 [`Animator::tick`] 之后生效，播放器使用 ASCII 主题时新的帧同样转换为 ASCII。
+    pub fn with_theme_updates(mut self, updates: Option<watch::Receiver<ThemeRef>>) -> Self {
+        self.theme_updates = updates;
+        self
+    }
+
+    /// 设置帧间隔，下一次 [`Animator::tick`] 生效，超过帧率上限时被限制
+    #[cfg(feature = "http")]
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay.max(self.min_delay);
+    }
+
+    /// 当前主题
+    pub fn theme(&self) -> &ThemeRef {
+        &self.theme
+    }
+
+    /// 当前帧在主题中的索引
+    #[cfg(feature = "graphics")]
+    pub fn frame_index(&self) -> usize {
+        self.frame_idx
+    }
+
+    /// 当前帧
+    pub fn frame(&self) -> Frame<'_> {
+        self.theme.frames.get(self.frame_idx)
+    }
+
+    /// 动画开始时间
+    pub fn start_time(&self) -> Instant {
+        self.start_time
+    }
+
+    /// 已渲染的帧数
+    pub fn rendered(&self) -> usize {
+        self.rendered
+    }
+
+    /// 当前帧在终端中的裁剪范围，开启平移时随帧数移动
+    pub fn render_size(&self, width: u16, height: u16) -> RenderSize {
+        let size = RenderSize::new(
+            (self.theme.width, self.theme.height),
+            width,
+            height,
+            self.renderer,
+        );
+        match self.pan_speed {
+            Some(speed) => size.panned(self.rendered * speed, self.theme.width),
+            None => size,
+        }
+    }
+
+    /// 彩虹颜色的当前相位，按动画开始后的时间计算，与帧率无关
+    pub fn color_phase(&self) -> usize {
+        self.color_speed.map_or(0, |speed| {
+            (self.start_time.elapsed().as_secs_f64() * speed) as usize
+        })
+    }
+
+    /// 当前帧的渲染选项，使用播放器的主题和彩虹相位
+    pub fn render_options<'a>(
+        &'a self,
+        args: &'a Args,
+        color_mode: ColorMode,
+    ) -> RenderOptions<'a> {
+        RenderOptions {
+            theme: &self.theme,
+            color_phase: self.color_phase(),
+            ..render_options(args, color_mode)
+        }
+    }
+
+    /// 构建当前帧的完整输出，用于 WebSocket 消息
+    ///
+    /// 浏览器中的终端不会把 `\n` 转换为 `\r\n`，行尾固定使用 `\r\n`。
+    #[cfg(feature = "http")]
+    pub fn next_frame_string(
+        &self,
+        width: u16,
+        height: u16,
+        args: &Args,
+        color_mode: ColorMode,
+    ) -> String {
+        let options = RenderOptions {
+            crlf: true,
+            ..render_options(args, color_mode)
+        };
+        let mut frame_data = String::new();
+        self.write_frame_with(&mut frame_data, width, height, options);
+        frame_data
+    }
+
+    /// 将当前帧的完整输出追加到缓冲区，调用方可以在帧之间复用缓冲区
+    pub fn write_frame(
+        &self,
+        out: &mut String,
+        width: u16,
+        height: u16,
+        args: &Args,
+        color_mode: ColorMode,
+    ) {
+        self.write_frame_with(out, width, height, render_options(args, color_mode))
+    }
+
+    /// 使用指定的渲染选项输出当前帧，主题和彩虹相位始终使用播放器的
+    pub fn write_frame_with(
+        &self,
+        out: &mut String,
+        width: u16,
+        height: u16,
+        options: RenderOptions,
+    ) {
+        let options = RenderOptions {
+            theme: &self.theme,
+            color_phase: self.color_phase(),
+            ..options
+        };
+        build_frame(
+            out,
+            &options,
+            width,
+            self.render_size(width, height),
+            self.frame_idx,
+            self.start_time,
+        )
+    }
+
+    /// 当前帧输出完成后调用
+    ///
+    /// 依次检查帧数和循环次数限制、等待帧间隔、检查时间限制，然后前进到下一帧。
+    /// 返回 `false` 表示动画应当结束。
+    ///
+    /// 在等待帧间隔时被取消不会改变播放状态，再次调用时等待的仍是同一帧的时间，
+    /// 可以与重绘等其他事件一起 `select!`，不会因为频繁被打断而停在一帧上。
+    pub async fn tick(&mut self) -> bool {
+        let rendered = self.rendered + 1;
+
+        // 检查帧限制
+        if let Some(limit) = self.frame_limit
+            && rendered >= limit
+        {
+            self.rendered = rendered;
+            return false;
+        }
+
+        // 检查循环次数，回到起始帧之前的一帧渲染完成即为一次完整播放
+        let Range { start, end } = self.range;
+        let next_idx = start + advance_frame(self.frame_idx - start, end - start, self.reverse);
+        let cycles = self.cycles + usize::from(next_idx == self.start_frame);
+        if let Some(count) = self.loop_limit
+            && cycles >= count
+        {
+            self.rendered = rendered;
+            self.cycles = cycles;
+            return false;
+        }
+
+        // 控制帧率
+        if let Some(updates) = self.delay_updates.as_mut()
+            && updates.has_changed().unwrap_or(false)
+        {
+            self.delay = updates.borrow_and_update().max(self.min_delay);
+        }
+        let next_frame = *self
+            .next_frame
+            .get_or_insert_with(|| Instant::now() + self.delay);
+        sleep_until(next_frame).await;
+        self.next_frame = None;
+        self.rendered = rendered;
+        self.cycles = cycles;
+
+        // 检查时间限制
+        if let Some(duration) = self.duration
+            && self.start_time.elapsed() >= duration
+        {
+            return false;
+        }
+
+        // 下一帧
+        self.frame_idx = next_idx;
+        if let Some(updates) = self.theme_updates.as_mut()
+            && updates.has_changed().unwrap_or(false)
+        {
+            let theme = updates.borrow_and_update().clone();
+            let theme = if self.theme.is_ascii_only() {
+                theme.with_ascii_only()
+            } else {
+                theme
+            };
+            self.set_theme(theme);
+        }
+        true
+    }
+}
+
+/// 从命令行参数生成渲染选项
+pub fn render_options(args: &Args, color_mode: ColorMode) -> RenderOptions<'_> {
+    RenderOptions {
+        theme: args.theme,
+        color_mode,
+        no_clear: args.no_clear,
+        no_counter: args.no_counter,
+        counter_format: &args.counter_format,
+        flip: args.flip(),
+        center_counter: args.center_counter,
+        plain_counter: args.plain_counter(color_mode, true),
+        color_phase: 0,
+        crlf: false,
+        renderer: args.renderer(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::animation::{FRAMES, Frames, Theme};
+
+    /// 按命令行参数播放到结束，返回依次渲染的帧
+    async fn play(argv: &[&str]) -> Vec<usize> {
+        let args = Args::parse_from(std::iter::once("nyancat").chain(argv.iter().copied()));
+        let mut animator = Animator::new(&args).with_frame_limits(args.frames, args.loop_count);
+        let mut played = vec![animator.frame_idx];
+        while animator.tick().await {
+            played.push(animator.frame_idx);
+        }
+        played
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frames_limit_renders_exact_count() {
+        assert_eq!(play(&["--frames", "5"]).await, [0, 1, 2, 3, 4]);
+        assert_eq!(play(&["--frames", "1"]).await, [0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn loop_count_renders_whole_cycles() {
+        let frame_count = FRAMES.len();
+        assert_eq!(play(&["--loop", "2"]).await.len(), 2 * frame_count);
+
+        // 从起始帧开始计算完整播放，结束在起始帧之前的一帧
+        let played = play(&["--loop", "2", "--start-frame", "3"]).await;
+        assert_eq!(played.len(), 2 * frame_count);
+        assert_eq!((played[0], played[played.len() - 1]), (3, 2));
+
+        let played = play(&["--loop", "2", "--start-frame", "3", "--reverse"]).await;
+        assert_eq!(played.len(), 2 * frame_count);
+        assert_eq!((played[0], played[played.len() - 1]), (3, 4));
+
+        let played = play(&["--loop", "2", "--frame-range", "2:5", "--start-frame", "3"]).await;
+        assert_eq!(played, [3, 4, 2, 3, 4, 2]);
+
+        // 帧数限制先到时提前结束
+        assert_eq!(play(&["--loop", "2", "--frames", "5"]).await.len(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn duration_stops_after_time_limit() {
+        let start = Instant::now();
+        let played = play(&["--duration", "1", "--fps", "10"]).await;
+        assert_eq!(played.len(), 10);
+        let elapsed = start.elapsed();
+        assert!(
+            (Duration::from_millis(1000)..=Duration::from_millis(1010)).contains(&elapsed),
+            "{elapsed:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interrupted_tick_keeps_deadline() {
+        let args = Args::parse_from(["nyancat", "--fps", "10"]);
+        let mut animator = Animator::new(&args);
+        let start = Instant::now();
+        // 每 30ms 打断一次，相当于持续调整窗口大小
+        let mut advanced = false;
+        for _ in 0..10 {
+            tokio::select! {
+                more = animator.tick() => {
+                    advanced = more;
+                    break;
+                }
+                () = tokio::time::sleep(Duration::from_millis(30)) => {}
+            }
+        }
+        assert!(advanced, "frame did not advance while being interrupted");
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        assert_eq!((animator.rendered(), animator.frame_idx), (1, 1));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn only_websocket_frames_use_crlf() {
+        // 同时运行 Telnet 和 HTTP 时 Telnet 的输出不受影响
+        let args = Args::parse_from(["nyancat", "-t", "-H", "--no-counter"]);
+        let animator = Animator::new(&args);
+        let mut telnet = String::new();
+        animator.write_frame(&mut telnet, 80, 24, &args, ColorMode::None);
+        assert!(telnet.contains('\n') && !telnet.contains("\r\n"));
+
+        let websocket = animator.next_frame_string(80, 24, &args, ColorMode::None);
+        assert_eq!(websocket, telnet.replace('\n', "\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn start_frame_renders_first() {
+        let args = Args::parse_from(["nyancat", "--start-frame", "3"]);
+        let animator = Animator::new(&args);
+        assert!(animator.frame().rows().eq(FRAMES[3].iter().copied()));
+
+        let mut first = String::new();
+        animator.write_frame(&mut first, 80, 24, &args, ColorMode::None);
+        let mut expected = String::new();
+        build_frame(
+            &mut expected,
+            &render_options(&args, ColorMode::None),
+            80,
+            animator.render_size(80, 24),
+            3,
+            animator.start_time(),
+        );
+        assert_eq!(first, expected);
+
+        assert_eq!(
+            play(&["--start-frame", "3", "--frames", "3"]).await,
+            [3, 4, 5]
+        );
+        // 超出帧数时取模
+        assert_eq!(play(&["--start-frame", "15", "--frames", "1"]).await, [3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_fps_caps_requested_rate() {
+        let args = Args::parse_from(["nyancat", "--fps", "500", "--max-fps", "30"]);
+        let mut animator = Animator::new(&args);
+
+        let start = Instant::now();
+        for _ in 0..30 {
+            assert!(animator.tick().await);
+        }
+        // 30 帧约 1 秒，计时器精度为毫秒
+        let elapsed = start.elapsed();
+        assert!(
+            (Duration::from_millis(990)..=Duration::from_millis(1050)).contains(&elapsed),
+            "{elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn speed_scales_frame_delay() {
+        let delay = |argv: &[&str]| Args::parse_from(argv).frame_delay();
+        assert_eq!(
+            delay(&["nyancat", "--speed", "2"]),
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            delay(&["nyancat", "--frame-delay-ms", "300", "--speed", "0.5"]),
+            Duration::from_millis(600)
+        );
+        assert!(Args::try_parse_from(["nyancat", "--speed", "0"]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frame_delay_ms_sets_interval() {
+        let args = Args::parse_from(["nyancat", "--frame-delay-ms", "250"]);
+        let mut animator = Animator::new(&args);
+
+        let start = Instant::now();
+        for _ in 0..4 {
+            assert!(animator.tick().await);
+        }
+        let elapsed = start.elapsed();
+        assert!(
+            (Duration::from_millis(1000)..=Duration::from_millis(1010)).contains(&elapsed),
+            "{elapsed:?}"
+        );
+        assert!(
+            Args::try_parse_from(["nyancat", "--fps", "5", "--frame-delay-ms", "200"]).is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frame_range_wraps_at_end() {
+        let args = Args::parse_from(["nyancat", "--frame-range", "2:5", "--start-frame", "3"]);
+        let mut animator = Animator::new(&args);
+
+        let mut played = vec![animator.frame_idx];
+        for _ in 0..6 {
+            assert!(animator.tick().await);
+            played.push(animator.frame_idx);
+        }
+        assert_eq!(played, [3, 4, 2, 3, 4, 2, 3]);
+
+        let args = Args::parse_from(["nyancat", "--frame-range", "2:5", "--reverse"]);
+        let mut animator = Animator::new(&args);
+        let mut played = vec![animator.frame_idx];
+        for _ in 0..3 {
+            assert!(animator.tick().await);
+            played.push(animator.frame_idx);
+        }
+        assert_eq!(played, [2, 4, 3, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_theme_wraps_to_shorter_theme() {
+        let args = Args::parse_from(["nyancat", "--start-frame", "5"]);
+        let mut animator = Animator::new(&args);
+        assert_eq!(animator.frame_idx, 5);
+
+        let frames = Frames::new(vec![
+            vec!["ab".into()],
+            vec!["cd".into()],
+            vec!["ef".into()],
+        ]);
+        animator.set_theme(ThemeRef::Shared(Arc::new(Theme::from_frames(
+            "short",
+            frames,
+            Arc::default(),
+        ))));
+        assert_eq!(animator.frame_idx, 2);
+        assert_eq!(animator.frame(), ["ef"]);
+
+        let mut played = Vec::new();
+        for _ in 0..4 {
+            assert!(animator.tick().await);
+            played.push(animator.frame_idx);
+        }
+        assert_eq!(played, [0, 1, 2, 0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloaded_frames_apply_after_tick() {
+        let shared_theme = |frames: &[&str]| {
+            let frames = frames.iter().map(|row| vec![row.to_string()]).collect();
+            ThemeRef::Shared(Arc::new(Theme::from_frames(
+                "dir",
+                Frames::new(frames),
+                Arc::default(),
+            )))
+        };
+        let mut args = Args::parse_from(["nyancat"]);
+        args.theme = Theme::from_frames(
+            "dir",
+            Frames::new(["a", "b", "c", "d"].map(|row| vec![row.into()]).into()),
+            Arc::default(),
+        )
+        .into_static();
+        let (tx, rx) = watch::channel(args.theme.into());
+        let mut animator = Animator::new(&args).with_theme_updates(Some(rx));
+        for _ in 0..3 {
+            assert!(animator.tick().await);
+        }
+        assert_eq!(animator.frame(), ["d"]);
+
+        // 帧数减少时当前帧回绕
+        tx.send_replace(shared_theme(&["x", "y"]));
+        assert_eq!(animator.frame(), ["d"]);
+        assert!(animator.tick().await);
+        assert_eq!(animator.frame(), ["x"]);
+
+        // 帧数增加时重新播放全部帧
+        tx.send_replace(shared_theme(&["1", "2", "3"]));
+        let mut played = Vec::new();
+        for _ in 0..3 {
+            assert!(animator.tick().await);
+            played.push(animator.frame().row(0).to_string());
+        }
+        assert_eq!(played, ["2", "3", "1"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replaced_frames_are_freed() {
+        let shared = |row: &str| {
+            Arc::new(Theme::from_frames(
+                "dir",
+                Frames::new(vec![vec![row.into()]]),
+                Arc::default(),
+            ))
+        };
+        let args = Args::parse_from(["nyancat"]);
+        let (tx, rx) = watch::channel(args.theme.into());
+        let mut animator = Animator::new(&args).with_theme_updates(Some(rx));
+        let first = shared("a");
+        let freed = Arc::downgrade(&first);
+        tx.send_replace(ThemeRef::Shared(first));
+        assert!(animator.tick().await);
+        assert_eq!(animator.frame(), ["a"]);
+
+        // 通道和播放器都换到新的帧后，旧的帧被释放
+        tx.send_replace(ThemeRef::Shared(shared("b")));
+        assert!(freed.upgrade().is_some());
+        assert!(animator.tick().await);
+        assert_eq!(animator.frame(), ["b"]);
+        assert!(freed.upgrade().is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::extract::ws::Utf8Bytes;
+use tokio::sync::watch;
+use tracing::{debug, error};
+
+use crate::{
+    animation::{ColorMode, Theme, ThemeRef},
+    animator::Animator,
+    cli::Args,
+    http::frame_message,
+};
+
+/// 分组中所有连接共享的一帧
+pub struct SharedFrame {
+    /// 帧内容，用于转储
+    pub frame: String,
+    /// 序列化后的消息，克隆不会复制内容
+    pub message: Utf8Bytes,
+}
+
+/// 订阅分组得到的最新帧，分组结束时通道关闭
+pub type FrameReceiver = watch::Receiver<Option<Arc<SharedFrame>>>;
+
+type FrameSender = Arc<watch::Sender<Option<Arc<SharedFrame>>>>;
+
+/// 主题、颜色模式和大小都相同的连接看到的画面完全一致，可以共用一份动画
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct BucketKey {
+    /// 主题按地址区分，ASCII 版本与原主题属于不同的分组
+    theme: usize,
+    color_mode: ColorMode,
+    width: u16,
+    height: u16,
+}
+
+/// 共享的 WebSocket 动画
+///
+/// 每个分组只有一个任务渲染和序列化帧，通过 watch 通道广播给所有订阅的连接。
+/// 分组在第一个连接订阅时启动，最后一个连接退出后在下一帧结束。
+#[derive(Clone)]
+pub struct FrameHub {
+    args: Arc<Args>,
+    frame_delay: watch::Receiver<Duration>,
+    frames: Option<watch::Receiver<ThemeRef>>,
+    buckets: Arc<Mutex<HashMap<BucketKey, FrameSender>>>,
+}
+
+impl FrameHub {
+    pub fn new(
+        args: &Args,
+        frame_delay: watch::Receiver<Duration>,
+        frames: Option<watch::Receiver<ThemeRef>>,
+    ) -> Self {
+        Self {
+            args: Arc::new(args.clone()),
+            frame_delay,
+            frames,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// 订阅指定主题、颜色模式和大小的动画，对应的分组不存在时启动一个
+    ///
+    /// 加入已有分组时立即收到当前帧，不用等到下一帧。
+    pub fn subscribe(
+        &self,
+        theme: &'static Theme,
+        color_mode: ColorMode,
+        width: u16,
+        height: u16,
+    ) -> FrameReceiver {
+        let key = BucketKey {
+            theme: std::ptr::from_ref(theme) as usize,
+            color_mode,
+            width,
+            height,
+        };
+        // 订阅和分组退出都在锁内进行，不会订阅到正在退出的分组
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(sender) = buckets.get(&key) {
+            let mut frames = sender.subscribe();
+            frames.mark_changed();
+            return frames;
+        }
+
+        let (sender, frames) = watch::channel(None);
+        let sender = Arc::new(sender);
+        buckets.insert(key, sender.clone());
+        debug!(
+            "Starting shared animation {width}x{height}, {} running",
+            buckets.len()
+        );
+        tokio::spawn(self.clone().produce(key, theme, sender));
+        frames
+    }
+
+    /// 分组的动画任务，按全局帧间隔生成帧，不受单个连接的时间限制
+    ///
+    /// 分组按启动时的主题区分，默认主题及其 ASCII 版本的分组跟随重新加载的帧。
+    async fn produce(self, key: BucketKey, theme: &'static Theme, sender: FrameSender) {
+        let default_theme = self.args.theme;
+        let follows_reload = std::ptr::eq(theme, default_theme)
+            || std::ptr::eq(theme, default_theme.with_ascii_only());
+        let mut animator = Animator::new(&self.args)
+            .with_theme(theme.into())
+            .without_time_limit()
+            .with_delay_updates(self.frame_delay.clone())
+            .with_theme_updates(self.frames.clone().filter(|_| follows_reload));
+        loop {
+            let frame =
+                animator.next_frame_string(key.width, key.height, &self.args, key.color_mode);
+            let message = match frame_message(frame.clone()) {
+                Ok(message) => message,
+                Err(err) => {
+                    error!("Shared animation stopped: {err:#}");
+                    break;
+                }
+            };
+            sender.send_replace(Some(Arc::new(SharedFrame { frame, message })));
+
+            if self.retire_if_idle(key, &sender) || !animator.tick().await {
+                break;
+            }
+        }
+        self.retire(key, &sender);
+    }
+
+    /// 没有连接订阅时移除分组，返回 `true` 表示分组应当结束
+    fn retire_if_idle(&self, key: BucketKey, sender: &FrameSender) -> bool {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if sender.receiver_count() > 0 {
+            return false;
+        }
+        Self::remove(&mut buckets, key, sender);
+        true
+    }
+
+    fn retire(&self, key: BucketKey, sender: &FrameSender) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        Self::remove(&mut buckets, key, sender);
+    }
+
+    fn remove(buckets: &mut HashMap<BucketKey, FrameSender>, key: BucketKey, sender: &FrameSender) {
+        // 分组可能已经被移除并由新的任务接替
+        if buckets
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, sender))
+        {
+            buckets.remove(&key);
+            debug!(
+                "Stopping shared animation {}x{}, {} running",
+                key.width,
+                key.height,
+                buckets.len()
+            );
+        }
+    }
+}
@@ -1,7 +1,21 @@
+#[cfg(feature = "http")]
+use std::net::SocketAddr;
+use std::{ops::Range, path::PathBuf, time::Duration};
+
+use anyhow::Context;
 use clap::Parser;
+use serde::{Serialize, Serializer};
+
+use crate::animation::{
+    ANIMATIONS, Animation, ColorMode, DEFAULT_COUNTER_FORMAT, Flip, Renderer, THEMES, Theme,
+};
 
-#[derive(Parser, Debug, Clone)]
+/// 命令行参数
+///
+/// 序列化后的字段名与命令行长参数一致，用于 `--print-config`。
+#[derive(Parser, Serialize, Debug, Clone)]
 #[clap(author, version, about = "Nyancat !!!", long_about = None)]
+#[serde(rename_all = "kebab-case")]
 pub struct Args {
     /// telnet 模式
     #[arg(short, long)]
@@ -12,10 +26,150 @@ pub struct Args {
     #[arg(short = 'H', long)]
     pub http: bool,
 
+    #[cfg(feature = "http")]
+    /// HTTP 服务监听地址
+    #[arg(
+        long = "http-addr",
+        value_name = "ADDR",
+        default_value = "0.0.0.0:3000"
+    )]
+    pub http_addr: SocketAddr,
+
+    #[cfg(feature = "http")]
+    /// WebSocket 心跳间隔（秒）
+    #[arg(
+        long = "ws-ping-interval",
+        value_name = "SECONDS",
+        default_value_t = 15
+    )]
+    pub ws_ping_interval: u64,
+
+    #[cfg(feature = "http")]
+    /// WebSocket 超时时间（秒），超过该时间未收到客户端消息则断开
+    #[arg(long = "ws-timeout", value_name = "SECONDS", default_value_t = 45)]
+    pub ws_timeout: u64,
+
+    #[cfg(feature = "http")]
+    /// WebSocket 连接在该时间（秒）内未上报终端大小时发送错误消息并关闭
+    #[arg(
+        long = "ws-init-timeout",
+        value_name = "SECONDS",
+        default_value_t = 15,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub ws_init_timeout: u64,
+
+    #[cfg(feature = "http")]
+    /// 每个 WebSocket 连接中待处理的客户端消息数上限，大小调整和帧间隔不占用该队列
+    #[arg(
+        long = "ws-channel-capacity",
+        value_name = "COUNT",
+        default_value_t = 128,
+        value_parser = clap::value_parser!(u32).range(1..=65536)
+    )]
+    pub ws_channel_capacity: u32,
+
+    #[cfg(feature = "http")]
+    /// 主题、颜色模式和大小相同的 WebSocket 连接共用同一份动画，每帧只渲染一次
+    #[arg(long = "shared-animation")]
+    pub shared_animation: bool,
+
+    #[cfg(feature = "http")]
+    /// 单个 IP 同时允许的 WebSocket 连接数
+    #[arg(
+        long = "max-connections-per-ip",
+        value_name = "COUNT",
+        default_value_t = 32
+    )]
+    pub max_connections_per_ip: usize,
+
+    #[cfg(feature = "http")]
+    /// 单个 IP 在时间窗口内允许新建的 WebSocket 连接数
+    #[arg(long = "rate-limit", value_name = "COUNT", default_value_t = 120)]
+    pub rate_limit: usize,
+
+    #[cfg(feature = "http")]
+    /// 连接频率限制的时间窗口（秒）
+    #[arg(long = "rate-window", value_name = "SECONDS", default_value_t = 60)]
+    pub rate_window: u64,
+
+    #[cfg(feature = "http")]
+    /// 允许跨域访问的来源，多个来源用逗号分隔，`*` 表示允许所有来源，未设置时不启用 CORS
+    #[arg(long = "cors-origin", value_name = "ORIGINS", value_delimiter = ',')]
+    pub cors_origin: Vec<String>,
+
+    #[cfg(feature = "http")]
+    /// WebSocket 访问令牌，设置后客户端需通过 `?token=` 或 `Authorization` 头提供
+    #[arg(long = "http-token", value_name = "SECRET")]
+    #[serde(serialize_with = "serialize_redacted")]
+    pub http_token: Option<String>,
+
+    #[cfg(feature = "http")]
+    /// 访问 `/` 时重定向到该地址，用于托管在其他位置的前端，`/ws` 仍由本服务提供
+    #[arg(long = "client-url", value_name = "URL", value_parser = parse_client_url)]
+    pub client_url: Option<String>,
+
+    #[cfg(feature = "http")]
+    /// 监听 Unix 域套接字代替 TCP 端口（仅 Unix）
+    #[arg(long, value_name = "PATH")]
+    pub uds: Option<PathBuf>,
+
+    #[cfg(feature = "tls")]
+    /// TLS 证书文件（PEM），与 `--tls-key` 一起设置后 telnet 和 http 服务使用 TLS
+    #[arg(long = "tls-cert", value_name = "PATH", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    #[cfg(feature = "tls")]
+    /// TLS 私钥文件（PEM）
+    #[arg(long = "tls-key", value_name = "PATH", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// 只输出警告和错误日志
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// 输出更详细的日志，`-v` 为 debug，`-vv` 为 trace
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// 逐帧输出纯文本到标准输出，不清屏、不移动光标、不修改终端模式，
+    /// 标准输出不是终端时自动启用
+    #[arg(long)]
+    pub plain: bool,
+
     /// 不显示计数器
     #[arg(short = 'n', long = "no-counter")]
     pub no_counter: bool,
 
+    /// 计数器水平居中，只显示计数文本，不绘制两侧的背景
+    #[arg(long = "center-counter")]
+    pub center_counter: bool,
+
+    /// 计数文本比终端宽时每帧滚动一列，默认截断（独立模式和未指定 `--no-diff` 的 Telnet 模式）
+    #[arg(long = "scroll-counter")]
+    pub scroll_counter: bool,
+
+    /// 独立模式下不隐藏光标，适用于屏幕阅读器和部分录屏工具
+    #[arg(long = "no-hide-cursor")]
+    pub no_hide_cursor: bool,
+
+    /// 在左上角显示实际帧率和已渲染帧数（仅独立模式）
+    #[arg(long = "show-fps")]
+    pub show_fps: bool,
+
+    /// 退出并恢复终端后输出运行时间和已渲染帧数（仅独立模式）
+    #[arg(long)]
+    pub summary: bool,
+
+    /// 计数器格式，支持 `{seconds}` 和 `{hms}` 占位符
+    #[arg(long = "counter-format", default_value = DEFAULT_COUNTER_FORMAT)]
+    pub counter_format: String,
+
+    /// 计数器的输出格式：plain 只输出文本，styled 带背景色，
+    /// 未设置时在 `--color none` 或输出不是终端时使用 plain
+    #[arg(long = "output-format", value_name = "FORMAT", value_parser = parse_output_format)]
+    pub output_format: Option<OutputFormat>,
+
     /// 不清除屏幕
     #[arg(short = 'e', long = "no-clear")]
     pub no_clear: bool,
@@ -24,7 +178,637 @@ pub struct Args {
     #[arg(short, long)]
     pub frames: Option<usize>,
 
+    /// 从指定帧开始播放，超出帧数时取模，指定 `--frame-range` 时必须在范围内
+    #[arg(short = 's', long = "start-frame", value_name = "FRAME")]
+    pub start_frame: Option<usize>,
+
+    /// 只在 `START:END` 范围内的帧之间循环播放，包含 START，不包含 END
+    #[arg(long = "frame-range", value_name = "START:END", value_parser = parse_frame_range)]
+    #[serde(serialize_with = "serialize_frame_range")]
+    pub frame_range: Option<Range<usize>>,
+
+    /// 倒放动画
+    #[arg(short, long)]
+    pub reverse: bool,
+
+    /// 终端比帧窄时水平来回平移，显示完整的帧
+    #[arg(long)]
+    pub pan: bool,
+
+    /// 平移速度，每帧移动的列数
+    #[arg(
+        long = "pan-speed",
+        value_name = "COLUMNS",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub pan_speed: u16,
+
+    /// 上下翻转动画
+    #[arg(long = "flip-vertical")]
+    pub flip_vertical: bool,
+
+    /// 左右翻转动画
+    #[arg(long = "flip-horizontal")]
+    pub flip_horizontal: bool,
+
+    /// 把每个单元格横向和纵向各放大为 N 个，再按终端大小裁剪，用于很大的终端窗口
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=16)
+    )]
+    pub scale: u8,
+
+    /// 每秒帧数，服务模式下可以修改配置文件后发送 SIGHUP 生效
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..=1000)
+    )]
+    pub fps: u32,
+
+    /// 帧间隔的毫秒数，与 `--fps` 二选一，适合不是整数帧率的慢速动画
+    #[arg(
+        long = "frame-delay-ms",
+        value_name = "MS",
+        conflicts_with = "fps",
+        value_parser = clap::value_parser!(u32).range(1..=60_000)
+    )]
+    pub frame_delay_ms: Option<u32>,
+
+    /// 动画速度倍数，作用于 `--fps` 或 `--frame-delay-ms` 得到的帧间隔，小于 1 为慢动作
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = parse_speed)]
+    pub speed: f32,
+
+    /// 帧率上限，`--fps`、配置重新加载和 WebSocket 客户端请求的帧率都会被限制在此之内
+    #[arg(
+        long = "max-fps",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u32).range(1..=1000)
+    )]
+    pub max_fps: u32,
+
+    /// 运行指定秒数后退出
+    #[arg(short, long, value_name = "SECONDS")]
+    pub duration: Option<u64>,
+
+    /// 每个 Telnet 或 WebSocket 连接发送的字节数达到该值后关闭连接，包括转义序列
+    #[arg(long = "max-bytes", value_name = "BYTES")]
+    pub max_bytes: Option<u64>,
+
+    /// 完整播放指定次数后退出，0 表示无限循环
+    #[arg(short, long = "loop", visible_alias = "repeat", value_name = "COUNT")]
+    #[serde(rename = "loop")]
+    pub loop_count: Option<usize>,
+
     /// Telnet服务器端口
     #[arg(short = 'p', long, default_value_t = 23)]
     pub port: u16,
+
+    /// 客户端未上报窗口大小时使用的宽度
+    #[arg(
+        long = "default-width",
+        value_name = "COLUMNS",
+        default_value_t = 80,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub default_width: u16,
+
+    /// 客户端未上报窗口大小时使用的高度
+    #[arg(
+        long = "default-height",
+        value_name = "ROWS",
+        default_value_t = 24,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub default_height: u16,
+
+    /// 独立模式下固定的渲染宽度，代替终端的实际宽度，用于录制固定大小的输出
+    #[arg(
+        long,
+        value_name = "COLUMNS",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub width: Option<u16>,
+
+    /// 独立模式下固定的渲染高度，计数器显示在该高度的最后一行
+    #[arg(
+        long,
+        value_name = "ROWS",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub height: Option<u16>,
+
+    /// Telnet 握手时每次读取的字节数，超过该长度的协商数据会跨多次读取拼接
+    #[arg(
+        long = "telnet-read-size",
+        value_name = "BYTES",
+        default_value_t = 1024,
+        value_parser = clap::value_parser!(u32).range(16..=65536)
+    )]
+    pub telnet_read_size: u32,
+
+    /// 客户端连接时反向解析其主机名并记录到日志，超时或失败时只记录 IP
+    #[arg(long = "resolve-hostnames")]
+    pub resolve_hostnames: bool,
+
+    /// Telnet 服务不进行 Telnet 协商，直接发送动画，用于 `nc` 等原始 TCP 客户端，
+    /// 使用默认宽高和 `--color` 指定的颜色模式
+    #[arg(long = "raw-tcp", requires = "telnet")]
+    pub raw_tcp: bool,
+
+    /// Telnet 每帧清屏后完整重绘，默认只发送与上一帧不同的单元格
+    #[arg(long = "no-diff")]
+    pub no_diff: bool,
+
+    /// Telnet 客户端连接后、动画开始前显示的欢迎信息
+    #[arg(long, value_name = "TEXT", conflicts_with = "banner_file")]
+    pub banner: Option<String>,
+
+    /// 从文件读取 Telnet 欢迎信息
+    #[arg(long = "banner-file", value_name = "PATH")]
+    pub banner_file: Option<PathBuf>,
+
+    /// 欢迎信息显示的秒数，之后开始播放动画，为 0 时不等待
+    #[arg(long = "banner-duration", value_name = "SECONDS", default_value_t = 3)]
+    pub banner_duration: u64,
+
+    /// 录制为 asciinema v2 文件（仅独立模式）
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// 把发送到终端或连接的原始字节写入文件，用于排查渲染问题
+    #[arg(long = "dump-output", value_name = "PATH")]
+    pub dump_output: Option<PathBuf>,
+
+    /// 动画主题
+    #[arg(long, default_value = "classic", value_parser = parse_theme)]
+    #[serde(serialize_with = "serialize_theme")]
+    pub theme: &'static Theme,
+
+    /// 动画图案，与主题的颜色组合
+    #[arg(
+        long,
+        default_value = "nyancat",
+        value_parser = parse_animation,
+        conflicts_with_all = ["frames_file", "animation_file", "frames_dir"]
+    )]
+    #[serde(serialize_with = "serialize_animation")]
+    pub animation: &'static Animation,
+
+    /// 颜色亮度倍数，范围 `0.1..=2.0`，小于 1 变暗，大于 1 变亮
+    #[arg(long, value_name = "FACTOR", value_parser = parse_brightness, conflicts_with_all = ["dim", "bright"])]
+    pub brightness: Option<f32>,
+
+    /// 降低颜色亮度，相当于 `--brightness 0.6`
+    #[arg(long, conflicts_with = "bright")]
+    pub dim: bool,
+
+    /// 提高颜色亮度，相当于 `--brightness 1.4`
+    #[arg(long)]
+    pub bright: bool,
+
+    /// 自定义调色板文件，每行 `char = #RRGGBB` 或 `char = <xterm 256 色索引>`
+    #[arg(long, value_name = "PATH")]
+    pub palette: Option<PathBuf>,
+
+    /// 从文件加载带颜色标记的帧，帧之间用 `---` 分隔，代替 `--theme`
+    #[arg(long = "frames-file", value_name = "PATH", conflicts_with = "palette")]
+    pub frames_file: Option<PathBuf>,
+
+    /// 从动画文件加载帧和调色板，文件开头是调色板，之后每个 `---` 行开始新的一帧
+    #[arg(
+        long = "animation-file",
+        value_name = "PATH",
+        conflicts_with_all = ["palette", "frames_file", "frames_dir"]
+    )]
+    pub animation_file: Option<PathBuf>,
+
+    /// 从目录加载帧，每个文件是一帧，按文件名排序，文件变化时自动重新加载
+    #[arg(
+        long = "frames-dir",
+        value_name = "DIR",
+        conflicts_with_all = ["palette", "frames_file"]
+    )]
+    pub frames_dir: Option<PathBuf>,
+
+    #[cfg(feature = "gif")]
+    /// 将动画导出为 GIF 文件后退出
+    #[arg(long = "export-gif", value_name = "PATH")]
+    pub export_gif: Option<PathBuf>,
+
+    #[cfg(feature = "gif")]
+    /// 导出 GIF 时每个单元格的宽度（像素）
+    #[arg(
+        long = "gif-cell-width",
+        value_name = "PIXELS",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u16).range(1..=64)
+    )]
+    pub gif_cell_width: u16,
+
+    #[cfg(feature = "gif")]
+    /// 导出 GIF 时每个单元格的高度（像素）
+    #[arg(
+        long = "gif-cell-height",
+        value_name = "PIXELS",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u16).range(1..=64)
+    )]
+    pub gif_cell_height: u16,
+
+    /// 颜色模式：truecolor、256、16、8 或 none
+    ///
+    /// 独立模式下未设置时根据 `TERM` 和 `COLORTERM` 检测，输出到管道时不使用颜色，Telnet 客户端的颜色模式不会超过该设置。
+    #[arg(
+        long,
+        visible_alias = "colors",
+        value_name = "MODE",
+        value_parser = parse_color_mode
+    )]
+    #[serde(serialize_with = "serialize_color_mode")]
+    pub color: Option<ColorMode>,
+
+    /// 使用 24 位真彩色，相当于 `--color truecolor`
+    #[arg(long, conflicts_with = "color")]
+    pub truecolor: bool,
+
+    /// 帧的绘制方式：block、half-block、braille、kitty 或 iterm2，默认为 block
+    ///
+    /// half-block 用 `▀` 的前景色和背景色在一个字符中画上下两行，猫更小更清晰，
+    /// 需要终端支持前景色和 Unicode 方块字符。braille 用盲文点阵在一个字符中画 2x4 个单元格，
+    /// 适合很小的终端窗口。kitty 和 iterm2 通过对应终端的图片协议显示图片，需要 `graphics` 特性，
+    /// 只用于独立模式，未设置时在 `TERM=xterm-kitty` 或 `TERM_PROGRAM=iTerm.app` 的终端中自动使用。
+    #[arg(long, value_name = "RENDERER", value_parser = parse_renderer)]
+    #[serde(serialize_with = "serialize_renderer")]
+    pub render: Option<Renderer>,
+
+    /// 不输出颜色，用 ASCII 字符画出动画，相当于 `--color none`
+    #[arg(long = "no-color", conflicts_with_all = ["color", "truecolor"])]
+    pub no_color: bool,
+
+    /// 彩虹颜色每秒轮换的次数，与帧率无关，未设置时彩虹只随帧变化
+    #[arg(long = "color-speed", value_name = "STEPS", value_parser = parse_color_speed)]
+    pub color_speed: Option<f64>,
+
+    /// 把方块、制表符等非 ASCII 字符替换为近似的 ASCII 字符，用于字体不完整的终端
+    #[arg(long = "ascii-only", visible_alias = "ascii")]
+    pub ascii_only: bool,
+
+    /// 列出所有内置主题
+    #[arg(long = "list-themes")]
+    pub list_themes: bool,
+
+    /// 列出所有内置动画
+    #[arg(long = "list-animations")]
+    pub list_animations: bool,
+
+    /// 输出颜色模式的检测过程和结果后退出
+    #[arg(long = "detect-color")]
+    pub detect_color: bool,
+
+    /// 按终端宽度把所有内置主题的第一帧排成网格后退出，用于比较主题
+    #[arg(long = "preview-themes")]
+    pub preview_themes: bool,
+
+    /// 预览当前主题在各颜色模式下的调色板后退出
+    #[arg(long = "preview-palette", visible_alias = "list-colors")]
+    pub preview_palette: bool,
+
+    /// 输出动画中用到的所有字符及其码位和颜色后退出，用于检查字体
+    #[arg(long = "glyph-test", visible_alias = "palette-test")]
+    pub glyph_test: bool,
+
+    /// 配置文件路径，默认为 `~/.config/nyancat/config.toml`
+    #[arg(short, long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// 以 TOML 格式输出合并配置文件后实际生效的参数后退出，访问令牌会被隐藏
+    #[arg(long = "print-config")]
+    #[serde(skip)]
+    pub print_config: bool,
+}
+
+impl Args {
+    /// 帧间隔，`--frame-delay-ms` 优先，否则根据帧率计算，再按 `--speed` 缩放
+    pub fn frame_delay(&self) -> Duration {
+        let delay = match self.frame_delay_ms {
+            Some(ms) => Duration::from_millis(ms.into()),
+            None => Duration::from_secs(1) / self.fps,
+        };
+        delay.div_f64(self.speed.into())
+    }
+
+    /// 帧率上限对应的最短帧间隔
+    pub fn min_frame_delay(&self) -> Duration {
+        Duration::from_secs(1) / self.max_fps
+    }
+
+    /// 颜色亮度倍数，未指定时为 1
+    pub fn brightness(&self) -> f32 {
+        match (self.brightness, self.dim, self.bright) {
+            (Some(factor), _, _) => factor,
+            (None, true, _) => 0.6,
+            (None, _, true) => 1.4,
+            _ => 1.0,
+        }
+    }
+
+    /// 应用缩放、亮度和 ASCII 设置，用于启动后才加载或切换的主题
+    pub fn adjust_theme(&self, theme: &'static Theme) -> &'static Theme {
+        let theme = theme.with_scale(self.scale.into());
+        let theme = if self.brightness() != 1.0 {
+            theme.with_brightness(self.brightness())
+        } else {
+            theme
+        };
+        if self.ascii_only {
+            theme.with_ascii_only()
+        } else {
+            theme
+        }
+    }
+
+    /// 应用缩放、亮度和 ASCII 设置，用于重新加载的主题
+    ///
+    /// 与 [`Args::adjust_theme`] 不同，转换结果不会被缓存，主题不再使用时会被释放。
+    pub fn adjust_reloaded(&self, theme: Theme) -> Theme {
+        let theme = match self.scale {
+            0 | 1 => theme,
+            scale => theme.scaled(scale.into()),
+        };
+        let theme = if self.brightness() != 1.0 {
+            theme.brightened(self.brightness())
+        } else {
+            theme
+        };
+        if self.ascii_only {
+            theme.to_ascii_only()
+        } else {
+            theme
+        }
+    }
+
+    /// 字符输出使用的绘制方式，图片绘制方式按块绘制
+    pub fn renderer(&self) -> Renderer {
+        self.render.unwrap_or_default()
+    }
+
+    /// 计数器是否只输出文本，`is_terminal` 表示输出目标是否为终端
+    pub fn plain_counter(&self, color_mode: ColorMode, is_terminal: bool) -> bool {
+        match self.output_format {
+            Some(format) => format == OutputFormat::Plain,
+            None => color_mode == ColorMode::None || !is_terminal,
+        }
+    }
+
+    /// 播放的帧范围，未指定或超出 `frame_count` 时为全部帧
+    pub fn frame_range(&self, frame_count: usize) -> Range<usize> {
+        self.frame_range
+            .clone()
+            .filter(|range| range.end <= frame_count)
+            .unwrap_or(0..frame_count)
+    }
+
+    /// 第一帧的索引，未指定时为播放范围的起点
+    pub fn first_frame(&self, frame_count: usize) -> usize {
+        let range = self.frame_range(frame_count);
+        match self.start_frame {
+            Some(frame) if self.frame_range.is_none() => frame % frame_count,
+            Some(frame) if range.contains(&frame) => frame,
+            _ => range.start,
+        }
+    }
+
+    /// 检查帧范围是否在主题的帧数内，起始帧是否在范围内
+    pub fn check_frame_range(&self) -> anyhow::Result<()> {
+        let Some(range) = &self.frame_range else {
+            return Ok(());
+        };
+        let frame_count = self.theme.frames.len();
+        if range.end > frame_count {
+            anyhow::bail!(
+                "--frame-range {}:{} is out of bounds, theme `{}` has {frame_count} frames",
+                range.start,
+                range.end,
+                self.theme.name
+            );
+        }
+        if let Some(frame) = self.start_frame
+            && !range.contains(&frame)
+        {
+            anyhow::bail!(
+                "--start-frame {frame} is outside --frame-range {}:{}",
+                range.start,
+                range.end
+            );
+        }
+        Ok(())
+    }
+
+    /// 命令行参数指定的翻转方式
+    pub fn flip(&self) -> Flip {
+        Flip {
+            vertical: self.flip_vertical,
+            horizontal: self.flip_horizontal,
+        }
+    }
+}
+
+/// 计数器的输出格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// 只有文本，不包含转义序列
+    Plain,
+    /// 带背景色
+    Styled,
+}
+
+/// 解析计数器的输出格式
+pub fn parse_output_format(name: &str) -> Result<OutputFormat, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "plain" => Ok(OutputFormat::Plain),
+        "styled" => Ok(OutputFormat::Styled),
+        _ => Err(format!(
+            "unknown output format `{name}`, available: plain, styled"
+        )),
+    }
+}
+
+/// 解析 `START:END` 格式的帧范围
+pub fn parse_frame_range(value: &str) -> Result<Range<usize>, String> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or_else(|| format!("frame range must be START:END, got `{value}`"))?;
+    let parse = |bound: &str| {
+        bound
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid frame index `{bound}` in frame range `{value}`"))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start >= end {
+        return Err(format!(
+            "frame range start must be less than end, got {start}:{end}"
+        ));
+    }
+    Ok(start..end)
+}
+
+/// 主题序列化为名称
+fn serialize_theme<S: Serializer>(
+    theme: &&'static Theme,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(theme.name)
+}
+
+fn serialize_animation<S: Serializer>(
+    animation: &&'static Animation,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(animation.name)
+}
+
+/// 颜色模式序列化为名称，见 [`ColorMode::name`]
+fn serialize_color_mode<S: Serializer>(
+    mode: &Option<ColorMode>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match mode {
+        Some(mode) => serializer.serialize_str(mode.name()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// 绘制方式序列化为名称
+fn serialize_renderer<S: Serializer>(
+    renderer: &Option<Renderer>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match renderer {
+        Some(renderer) => serializer.serialize_str(renderer.name()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// 帧范围序列化为与命令行相同的 `START:END`
+fn serialize_frame_range<S: Serializer>(
+    range: &Option<Range<usize>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match range {
+        Some(range) => serializer.serialize_str(&format!("{}:{}", range.start, range.end)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// 敏感参数只输出是否设置，不输出内容
+#[cfg(feature = "http")]
+fn serialize_redacted<S: Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_str("<redacted>"),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// 以 TOML 格式输出实际生效的参数
+pub fn print_config(args: &Args) -> anyhow::Result<()> {
+    let config = toml::to_string(args).with_context(|| "serialize config failed")?;
+    print!("{config}");
+    Ok(())
+}
+
+/// 根据名称解析主题
+pub fn parse_theme(name: &str) -> Result<&'static Theme, String> {
+    Theme::by_name(name).ok_or_else(|| {
+        let names = THEMES.iter().map(|theme| theme.name).collect::<Vec<_>>();
+        format!("unknown theme `{name}`, available: {}", names.join(", "))
+    })
+}
+
+pub fn parse_animation(name: &str) -> Result<&'static Animation, String> {
+    Animation::by_name(name).ok_or_else(|| {
+        let names = ANIMATIONS
+            .iter()
+            .map(|animation| animation.name)
+            .collect::<Vec<_>>();
+        format!(
+            "unknown animation `{name}`, available: {}",
+            names.join(", ")
+        )
+    })
+}
+
+/// 校验前端地址，必须是带主机名的 http 或 https 绝对地址
+#[cfg(feature = "http")]
+pub fn parse_client_url(url: &str) -> Result<String, String> {
+    let uri: axum::http::Uri = url
+        .parse()
+        .map_err(|e| format!("invalid URL `{url}`: {e}"))?;
+    let scheme_ok = matches!(uri.scheme_str(), Some("http" | "https"));
+    if !scheme_ok || uri.host().is_none_or(str::is_empty) {
+        return Err(format!(
+            "client URL must be an absolute http(s) URL with a host, got `{url}`"
+        ));
+    }
+    Ok(url.to_string())
+}
+
+/// 解析亮度倍数
+pub fn parse_brightness(value: &str) -> Result<f32, String> {
+    let factor: f32 = value
+        .parse()
+        .map_err(|_| format!("invalid brightness `{value}`"))?;
+    if !(0.1..=2.0).contains(&factor) {
+        return Err(format!("brightness must be in 0.1..=2.0, got {factor}"));
+    }
+    Ok(factor)
+}
+
+/// 解析动画速度倍数
+pub fn parse_speed(value: &str) -> Result<f32, String> {
+    let speed: f32 = value
+        .parse()
+        .map_err(|_| format!("invalid speed `{value}`"))?;
+    if !(0.1..=10.0).contains(&speed) {
+        return Err(format!("speed must be in 0.1..=10, got {speed}"));
+    }
+    Ok(speed)
+}
+
+/// 解析彩虹颜色的轮换速度
+pub fn parse_color_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid color speed `{value}`"))?;
+    if !(0.0..=100.0).contains(&speed) {
+        return Err(format!("color speed must be in 0..=100, got {speed}"));
+    }
+    Ok(speed)
+}
+
+/// 根据名称解析颜色模式
+pub fn parse_color_mode(name: &str) -> Result<ColorMode, String> {
+    ColorMode::from_name(name).ok_or_else(|| {
+        let names = ColorMode::ALL.map(ColorMode::name);
+        format!(
+            "unknown color mode `{name}`, available: {}",
+            names.join(", ")
+        )
+    })
+}
+
+/// 根据名称解析绘制方式
+pub fn parse_renderer(name: &str) -> Result<Renderer, String> {
+    Renderer::from_name(name).ok_or_else(|| {
+        let names = Renderer::ALL.map(Renderer::name);
+        format!("unknown renderer `{name}`, available: {}", names.join(", "))
+    })
 }
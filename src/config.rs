@@ -0,0 +1,237 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, anyhow, bail};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, parser::ValueSource};
+use serde::Deserialize;
+
+use crate::cli::{Args, parse_animation, parse_color_mode, parse_theme};
+
+/// 配置文件
+///
+/// 字段名与命令行长参数一致，未设置的字段使用命令行或内置默认值。
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub color: Option<String>,
+    pub theme: Option<String>,
+    pub animation: Option<String>,
+    pub no_counter: Option<bool>,
+    pub counter_format: Option<String>,
+    pub no_clear: Option<bool>,
+    pub frames: Option<usize>,
+    pub duration: Option<u64>,
+    #[serde(rename = "loop")]
+    pub loop_count: Option<usize>,
+    pub port: Option<u16>,
+    pub fps: Option<u32>,
+    pub frame_delay_ms: Option<u32>,
+}
+
+impl Config {
+    /// 默认配置文件路径 `$XDG_CONFIG_HOME/nyancat/config.toml`
+    /// 或 `~/.config/nyancat/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("nyancat").join("config.toml"))
+    }
+
+    /// 读取并解析配置文件
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("read config file {} failed", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("parse config file {} failed", path.display()))
+    }
+
+    /// 将配置合并到命令行参数
+    ///
+    /// 优先级：命令行显式传入的参数 > 配置文件 > 内置默认值。
+    /// 是否显式传入由 `matches` 中参数的来源判断。
+    pub fn merge_into(self, args: &mut Args, matches: &ArgMatches) -> anyhow::Result<()> {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        if !from_cli("color")
+            && let Some(name) = self.color
+        {
+            let color = parse_color_mode(&name).map_err(|e| anyhow!("{e} in config file"))?;
+            args.color = Some(color);
+        }
+        if !from_cli("theme")
+            && let Some(name) = self.theme
+        {
+            args.theme = parse_theme(&name).map_err(|e| anyhow!("{e} in config file"))?;
+        }
+        if !from_cli("animation")
+            && let Some(name) = self.animation
+        {
+            args.animation = parse_animation(&name).map_err(|e| anyhow!("{e} in config file"))?;
+        }
+        if !from_cli("no_counter")
+            && let Some(no_counter) = self.no_counter
+        {
+            args.no_counter = no_counter;
+        }
+        if !from_cli("counter_format")
+            && let Some(counter_format) = self.counter_format
+        {
+            args.counter_format = counter_format;
+        }
+        if !from_cli("no_clear")
+            && let Some(no_clear) = self.no_clear
+        {
+            args.no_clear = no_clear;
+        }
+        if !from_cli("frames") && self.frames.is_some() {
+            args.frames = self.frames;
+        }
+        if !from_cli("duration") && self.duration.is_some() {
+            args.duration = self.duration;
+        }
+        if !from_cli("loop_count") && self.loop_count.is_some() {
+            args.loop_count = self.loop_count;
+        }
+        if !from_cli("port")
+            && let Some(port) = self.port
+        {
+            args.port = port;
+        }
+        // 帧率和帧间隔是同一个设置，命令行传入其中一个时忽略配置文件中的两个
+        if !from_cli("fps") && !from_cli("frame_delay_ms") {
+            if self.fps.is_some() && self.frame_delay_ms.is_some() {
+                bail!("fps and frame-delay-ms can't both be set in config file");
+            }
+            if let Some(fps) = self.fps {
+                if !(1..=1000).contains(&fps) {
+                    bail!("fps must be in 1..=1000 in config file, got {fps}");
+                }
+                args.fps = fps;
+            }
+            if let Some(ms) = self.frame_delay_ms {
+                if !(1..=60_000).contains(&ms) {
+                    bail!("frame-delay-ms must be in 1..=60000 in config file, got {ms}");
+                }
+                args.frame_delay_ms = Some(ms);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 解析命令行参数并合并配置文件
+///
+/// 通过 `--config` 指定的配置文件必须存在，默认路径下的配置文件不存在时忽略。
+pub fn load_args() -> anyhow::Result<Args> {
+    load_args_from(env::args_os())
+}
+
+/// 解析指定的命令行参数并合并配置文件，见 [`load_args`]
+pub fn load_args_from<I, T>(argv: I) -> anyhow::Result<Args>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let matches = Args::command().get_matches_from(argv);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let config = match &args.config {
+        Some(path) => Some(Config::load(path)?),
+        None => match Config::default_path() {
+            Some(path) if path.exists() => Some(Config::load(&path)?),
+            _ => None,
+        },
+    };
+
+    if let Some(config) = config {
+        config.merge_into(&mut args, &matches)?;
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::ColorMode;
+
+    /// 解析命令行参数并合并配置文件内容
+    fn merged(argv: &[&str], config: &str) -> anyhow::Result<Args> {
+        let matches = Args::command()
+            .try_get_matches_from(std::iter::once("nyancat").chain(argv.iter().copied()))
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        toml::from_str::<Config>(config)?.merge_into(&mut args, &matches)?;
+        Ok(args)
+    }
+
+    #[test]
+    fn cli_overrides_file_overrides_default() {
+        let config = "color = \"16\"\ntheme = \"poptart\"\nfps = 20\nframes = 7\nloop = 2\n";
+
+        let args = merged(&[], config).unwrap();
+        assert_eq!(args.color, Some(ColorMode::Color16));
+        assert_eq!(args.theme.name, "poptart");
+        assert_eq!(
+            (args.fps, args.frames, args.loop_count),
+            (20, Some(7), Some(2))
+        );
+
+        let args = merged(
+            &[
+                "--color",
+                "truecolor",
+                "--theme",
+                "classic",
+                "--fps",
+                "30",
+                "--frames",
+                "3",
+            ],
+            config,
+        )
+        .unwrap();
+        assert_eq!(args.color, Some(ColorMode::TrueColor));
+        assert_eq!(args.theme.name, "classic");
+        assert_eq!(
+            (args.fps, args.frames, args.loop_count),
+            (30, Some(3), Some(2))
+        );
+
+        // 配置文件中没有的设置保持内置默认值
+        let default = merged(&[], "").unwrap();
+        let args = merged(&[], "frames = 7\n").unwrap();
+        assert_eq!((args.fps, args.duration), (default.fps, default.duration));
+        assert_eq!(args.color, None);
+
+        // 与命令行使用相同的名称
+        let err = merged(&[], "color = \"sepia\"\n").unwrap_err();
+        assert!(err.to_string().contains("unknown color mode"), "{err:#}");
+    }
+
+    #[test]
+    fn fps_and_frame_delay_conflict_in_file() {
+        let config = "fps = 20\nframe-delay-ms = 50\n";
+        let err = merged(&[], config).unwrap_err();
+        assert!(err.to_string().contains("both"), "{err:#}");
+
+        // 命令行传入其中一个时忽略配置文件中的两个
+        let args = merged(&["--frame-delay-ms", "250"], config).unwrap();
+        assert_eq!(args.frame_delay_ms, Some(250));
+        assert_eq!(args.fps, merged(&[], "").unwrap().fps);
+
+        let args = merged(&[], "frame-delay-ms = 50\n").unwrap();
+        assert_eq!(args.frame_delay_ms, Some(50));
+        assert!(merged(&[], "fps = 0\n").is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let err = toml::from_str::<Config>("colour = true\n").unwrap_err();
+        assert!(err.to_string().contains("colour"), "{err}");
+        assert!(toml::from_str::<Config>("no-counter = true\n").is_ok());
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::{animation::ColorMode, metrics::Transport};
+
+/// 生命周期事件的 target，日志消费方可以按它过滤，如 `NYANCAT_LOG=nyancat::conn=info`
+const TARGET: &str = "nyancat::conn";
+
+/// 单个连接的生命周期事件
+///
+/// 依次发出 `connect`、`size`、`first_frame` 和 `disconnect` 事件，字段都是结构化的。
+/// `disconnect` 在 drop 时发出，连接任务出错或被中止时也不会遗漏。
+pub struct ConnectionEvents {
+    transport: Transport,
+    peer: SocketAddr,
+    started: Instant,
+    frames: AtomicU64,
+    bytes: AtomicU64,
+    /// 连接结束的原因，未设置时说明任务被中止
+    reason: OnceLock<&'static str>,
+}
+
+impl ConnectionEvents {
+    /// 记录新连接并发出 `connect` 事件
+    pub fn connect(transport: Transport, peer: SocketAddr) -> Self {
+        info!(
+            target: TARGET,
+            event = "connect",
+            transport = transport.label(),
+            %peer,
+        );
+        Self {
+            transport,
+            peer,
+            started: Instant::now(),
+            frames: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            reason: OnceLock::new(),
+        }
+    }
+
+    /// 确定终端大小和颜色模式后发出 `size` 事件
+    pub fn size_detected(
+        &self,
+        width: u16,
+        height: u16,
+        term: Option<&str>,
+        color_mode: ColorMode,
+    ) {
+        info!(
+            target: TARGET,
+            event = "size",
+            transport = self.transport.label(),
+            peer = %self.peer,
+            width,
+            height,
+            term,
+            color_mode = color_mode.name(),
+        );
+    }
+
+    /// 记录一帧发送完成，第一帧发出 `first_frame` 事件
+    pub fn frame_sent(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        if self.frames.fetch_add(1, Ordering::Relaxed) == 0 {
+            info!(
+                target: TARGET,
+                event = "first_frame",
+                transport = self.transport.label(),
+                peer = %self.peer,
+                elapsed_ms = self.started.elapsed().as_millis() as u64,
+            );
+        }
+    }
+
+    /// 已发送的字节数，包括转义序列
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// 记录连接结束的原因，只有第一次设置生效
+    pub fn close(&self, reason: &'static str) {
+        let _ = self.reason.set(reason);
+    }
+}
+
+impl Drop for ConnectionEvents {
+    fn drop(&mut self) {
+        info!(
+            target: TARGET,
+            event = "disconnect",
+            transport = self.transport.label(),
+            peer = %self.peer,
+            duration_ms = self.started.elapsed().as_millis() as u64,
+            frames = self.frames.load(Ordering::Relaxed),
+            bytes = self.bytes.load(Ordering::Relaxed),
+            reason = self.reason.get().copied().unwrap_or("aborted"),
+        );
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        fmt::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        Event, Level, Subscriber,
+        field::{Field, Visit},
+        span,
+        subscriber::DefaultGuard,
+    };
+    use tracing_subscriber::{Layer, layer::Context, prelude::*};
+
+    use super::*;
+
+    /// 记录事件和 span 的级别、目标和字段，其他模块的测试也用它检查日志
+    #[derive(Clone, Default)]
+    pub(crate) struct Recorder(Arc<Mutex<Vec<(Level, String, String)>>>);
+
+    impl Recorder {
+        /// 在当前线程开始记录，返回的守卫释放后停止
+        pub(crate) fn install(&self) -> DefaultGuard {
+            tracing_subscriber::registry()
+                .with(self.clone())
+                .set_default()
+        }
+
+        /// 目标为 `target` 的事件的字段
+        pub(crate) fn fields(&self, target: &str) -> Vec<String> {
+            let events = self.0.lock().unwrap();
+            events
+                .iter()
+                .filter(|(_, event_target, _)| event_target == target)
+                .map(|(.., fields)| fields.clone())
+                .collect()
+        }
+
+        /// 本程序记录的所有事件和 span 的字段，不含依赖库的日志
+        #[cfg(feature = "http")]
+        pub(crate) fn own(&self) -> Vec<String> {
+            let events = self.0.lock().unwrap();
+            events
+                .iter()
+                .filter(|(_, target, _)| target.starts_with(env!("CARGO_CRATE_NAME")))
+                .map(|(.., fields)| fields.clone())
+                .collect()
+        }
+
+        /// 级别不低于 `level` 的事件的字段
+        pub(crate) fn at_least(&self, level: Level) -> Vec<String> {
+            let events = self.0.lock().unwrap();
+            events
+                .iter()
+                .filter(|(event_level, ..)| *event_level <= level)
+                .map(|(.., fields)| fields.clone())
+                .collect()
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, _id: &span::Id, _ctx: Context<'_, S>) {
+            let metadata = attrs.metadata();
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            self.0.lock().unwrap().push((
+                *metadata.level(),
+                metadata.target().to_string(),
+                fields.0,
+            ));
+        }
+
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.0.lock().unwrap().push((
+                *metadata.level(),
+                metadata.target().to_string(),
+                fields.0,
+            ));
+        }
+    }
+
+    #[derive(Default)]
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let _ = write!(self.0, "{}={value:?} ", field.name());
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnect_fires_when_task_is_aborted() {
+        let recorder = Recorder::default();
+        let _guard = recorder.install();
+
+        let task = tokio::spawn(async {
+            let events = ConnectionEvents::connect(Transport::Telnet, ([127, 0, 0, 1], 23).into());
+            events.size_detected(80, 24, Some("xterm"), ColorMode::Color256);
+            events.frame_sent(100);
+            events.frame_sent(50);
+            std::future::pending::<()>().await;
+        });
+        tokio::task::yield_now().await;
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+
+        let events = recorder.fields(TARGET);
+        let kinds = events
+            .iter()
+            .map(|fields| fields.split(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                "event=\"connect\"",
+                "event=\"size\"",
+                "event=\"first_frame\"",
+                "event=\"disconnect\""
+            ]
+        );
+        let disconnect = &events[3];
+        for field in [
+            "frames=2 ",
+            "bytes=150 ",
+            "reason=\"aborted\" ",
+            "peer=127.0.0.1:23 ",
+        ] {
+            assert!(disconnect.contains(field), "{disconnect}");
+        }
+    }
+}
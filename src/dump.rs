@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use anyhow::Context as _;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+/// 原始输出的转储文件，用于排查渲染问题
+///
+/// 写入的字节与发送到终端或连接的完全一致，可以直接 `cat` 回放。
+/// 所有连接共享同一个文件，多个客户端同时连接时输出会交错。
+#[derive(Clone)]
+pub struct OutputDump {
+    file: Arc<Mutex<File>>,
+    /// 写入失败只警告一次，之后不再写入
+    failed: Arc<AtomicBool>,
+}
+
+impl OutputDump {
+    /// 创建转储文件，已存在时清空
+    ///
+    /// 每个进程只应打开一次，同时运行的多个服务共享同一个实例。
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("create dump file {} failed", path.display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// 根据命令行参数创建，未指定 `--dump-output` 时返回 `None`
+    pub fn from_path(path: Option<&Path>) -> anyhow::Result<Option<Self>> {
+        path.map(Self::create).transpose()
+    }
+
+    /// 追加一段输出，失败时不影响动画
+    pub fn write(&self, data: &[u8]) {
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(data) {
+            warn!("Writing dump file failed, disabling it: {e}");
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 把写入的数据同时复制到转储文件
+///
+/// 同步写入用于独立模式的标准输出，异步写入用于 Telnet 连接。
+pub struct Tee<W> {
+    inner: W,
+    dump: Option<OutputDump>,
+}
+
+impl<W> Tee<W> {
+    pub fn new(inner: W, dump: Option<OutputDump>) -> Self {
+        Self { inner, dump }
+    }
+
+    fn copy(&self, data: &[u8]) {
+        if let Some(dump) = &self.dump {
+            dump.write(data);
+        }
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.copy(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Tee<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        // 只记录实际写出的部分
+        if let Poll::Ready(Ok(n)) = poll {
+            self.copy(&buf[..n]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<W: AsyncRead + Unpin> AsyncRead for Tee<W> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
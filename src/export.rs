@@ -0,0 +1,51 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::Context;
+use gif::{Encoder, Frame, Repeat};
+use tracing::info;
+
+use crate::{animation::advance_frame, cli::Args, raster::Rasterizer};
+
+/// 将主题的动画帧导出为循环播放的 GIF
+pub fn export_gif(args: &Args, path: &Path) -> anyhow::Result<()> {
+    let theme = args.theme;
+    let rasterizer = Rasterizer::new(
+        theme.into(),
+        args.gif_cell_width.into(),
+        args.gif_cell_height.into(),
+    )?;
+    let (cols, rows) = (0..theme.width, 0..theme.height);
+    let (width, height) = rasterizer.image_size(&cols, &rows);
+    let gif_width = u16::try_from(width).context("GIF width too large")?;
+    let gif_height = u16::try_from(height).context("GIF height too large")?;
+
+    let file =
+        File::create(path).with_context(|| format!("create GIF file {} failed", path.display()))?;
+    let mut encoder = Encoder::new(
+        BufWriter::new(file),
+        gif_width,
+        gif_height,
+        &rasterizer.palette(),
+    )?;
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    // GIF 帧间隔单位为 10ms
+    let delay = (args.frame_delay().as_millis() / 10).max(1) as u16;
+    let range = args.frame_range(theme.frames.len());
+    let frame_count = range.len();
+    let mut frame_idx = args.first_frame(theme.frames.len());
+    let mut pixels = vec![0; width * height];
+
+    for _ in 0..frame_count {
+        rasterizer.render(frame_idx, 0, &cols, &rows, &mut pixels);
+
+        let mut frame = Frame::from_indexed_pixels(gif_width, gif_height, pixels.as_slice(), None);
+        frame.delay = delay;
+        encoder.write_frame(&frame)?;
+
+        frame_idx = range.start + advance_frame(frame_idx - range.start, frame_count, args.reverse);
+    }
+
+    info!("Exported {frame_count} frames to {}", path.display());
+    Ok(())
+}
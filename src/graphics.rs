@@ -0,0 +1,442 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::{self, Write},
+    ops::Range,
+};
+
+use crossterm::{cursor, queue};
+
+use crate::{
+    animation::{Flip, RAINBOW, RenderSize, Renderer, Theme, ThemeRef},
+    png,
+    raster::Rasterizer,
+};
+
+/// kitty 图片协议每个数据块的最大长度
+const KITTY_CHUNK: usize = 4096;
+
+/// 终端没有报告像素大小时，帧中每个字符的像素数
+const DEFAULT_CELL_PIXELS: usize = 8;
+
+/// 每个字符的最大像素数，避免高分屏上的图片过大
+const MAX_CELL_PIXELS: usize = 32;
+
+/// 根据环境变量检测终端支持的图片协议，不支持时返回 `None`
+pub fn detect_renderer() -> Option<Renderer> {
+    let term = std::env::var("TERM").ok();
+    let term_program = std::env::var("TERM_PROGRAM").ok();
+    detect_renderer_from(term.as_deref(), term_program.as_deref())
+}
+
+fn detect_renderer_from(term: Option<&str>, term_program: Option<&str>) -> Option<Renderer> {
+    if term == Some("xterm-kitty") {
+        Some(Renderer::Kitty)
+    } else if term_program == Some("iTerm.app") {
+        Some(Renderer::ITerm2)
+    } else {
+        None
+    }
+}
+
+/// 帧中每个字符的像素数，与终端的行高一致，使图片显示时不需要缩放
+fn cell_pixels() -> usize {
+    crossterm::terminal::window_size()
+        .ok()
+        .filter(|size| size.rows > 0 && size.height > 0)
+        .map_or(DEFAULT_CELL_PIXELS, |size| {
+            usize::from(size.height / size.rows)
+        })
+        .clamp(1, MAX_CELL_PIXELS)
+}
+
+/// 缓存图片时区分帧的键：主题地址、帧索引和彩虹相位
+type FrameKey = (usize, usize, usize);
+
+fn frame_key(theme: &Theme, frame_idx: usize, phase: usize) -> FrameKey {
+    (theme_addr(theme), frame_idx, phase % RAINBOW.len())
+}
+
+/// 主题的地址，缓存持有主题，主题在缓存中时地址不会被复用
+fn theme_addr(theme: &Theme) -> usize {
+    std::ptr::from_ref(theme) as usize
+}
+
+/// 终端左上角显示的图片，裁剪范围与块模式相同
+fn visible_area(theme: &Theme, size: &RenderSize) -> Option<(Range<usize>, Range<usize>)> {
+    // 终端大于帧时裁剪范围会超出帧
+    let cols = size.min_col..size.max_col.min(theme.width);
+    let rows = size.min_row..size.max_row.min(theme.height);
+    (!cols.is_empty() && !rows.is_empty()).then_some((cols, rows))
+}
+
+/// 按终端的字符大小把帧编码为 PNG
+struct FrameEncoder {
+    cell_pixels: usize,
+    flip: Flip,
+    /// 按主题地址缓存的栅格化器
+    rasterizers: HashMap<usize, Rasterizer>,
+}
+
+impl FrameEncoder {
+    fn new(flip: Flip) -> Self {
+        Self {
+            cell_pixels: cell_pixels(),
+            flip,
+            rasterizers: HashMap::new(),
+        }
+    }
+
+    /// 切换到另一个重新加载的主题时丢弃旧主题的栅格化器，返回被丢弃的主题地址
+    ///
+    /// 重新加载的主题只保留最新的一个，旧主题在这之后才会释放。
+    fn evict_replaced(&mut self, theme: &ThemeRef) -> Vec<usize> {
+        if !matches!(theme, ThemeRef::Shared(_)) {
+            return Vec::new();
+        }
+        let current = theme_addr(theme);
+        self.rasterizers
+            .extract_if(|&addr, raster| {
+                addr != current && matches!(raster.theme(), ThemeRef::Shared(_))
+            })
+            .map(|(addr, _)| addr)
+            .collect()
+    }
+
+    /// 编码帧的 `cols` x `rows` 部分
+    fn encode(
+        &mut self,
+        theme: &ThemeRef,
+        frame_idx: usize,
+        phase: usize,
+        cols: &Range<usize>,
+        rows: &Range<usize>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let raster = match self.rasterizers.entry(theme_addr(theme)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                Rasterizer::new(theme.clone(), self.cell_pixels, self.cell_pixels)?
+                    .with_flip(self.flip),
+            ),
+        };
+        let (width, height) = raster.image_size(cols, rows);
+        let mut pixels = vec![0; width * height];
+        raster.render(frame_idx, phase, cols, rows, &mut pixels);
+        png::encode_indexed(width as u32, height as u32, &raster.palette(), &pixels)
+    }
+}
+
+/// 按终端支持的图片协议显示帧
+pub enum Images {
+    Kitty(KittyImages),
+    ITerm2(ITerm2Images),
+}
+
+impl Images {
+    /// 渲染方式不是图片时返回 `None`
+    pub fn new(renderer: Renderer, flip: Flip) -> Option<Self> {
+        match renderer {
+            Renderer::Kitty => Some(Self::Kitty(KittyImages::new(flip))),
+            Renderer::ITerm2 => Some(Self::ITerm2(ITerm2Images::new(flip))),
+            Renderer::Block | Renderer::HalfBlock | Renderer::Braille => None,
+        }
+    }
+
+    /// 在终端左上角显示主题的一帧，裁剪范围与块模式相同
+    pub fn draw(
+        &mut self,
+        out: &mut Vec<u8>,
+        theme: &ThemeRef,
+        frame_idx: usize,
+        phase: usize,
+        size: &RenderSize,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Kitty(images) => images.draw(out, theme, frame_idx, phase, size),
+            Self::ITerm2(images) => images.draw(out, theme, frame_idx, phase, size),
+        }
+    }
+
+    /// 删除终端中保存的图片，退出前调用
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Kitty(images) => images.clear(out),
+            // iTerm2 的图片与文字一样，随备用屏幕一起清除
+            Self::ITerm2(_) => Ok(()),
+        }
+    }
+}
+
+/// 通过 kitty 图片协议显示帧
+///
+/// 每一帧在第一次显示时编码为 PNG 发送给终端，之后只发送显示命令。
+/// 图片覆盖与块模式相同的区域，一个字符占两列一行，裁剪由终端按像素完成。
+pub struct KittyImages {
+    encoder: FrameEncoder,
+    /// 已发送的图片 ID
+    images: HashMap<FrameKey, u32>,
+    next_id: u32,
+    /// 当前显示的图片
+    shown: Option<u32>,
+}
+
+impl KittyImages {
+    pub fn new(flip: Flip) -> Self {
+        Self {
+            encoder: FrameEncoder::new(flip),
+            images: HashMap::new(),
+            // 图片 ID 在终端窗口内共享，按进程号错开，避免与其他程序的图片冲突
+            next_id: (std::process::id() << 10).max(1),
+            shown: None,
+        }
+    }
+
+    /// 在终端左上角显示主题的一帧，裁剪范围与块模式相同
+    pub fn draw(
+        &mut self,
+        out: &mut Vec<u8>,
+        theme: &ThemeRef,
+        frame_idx: usize,
+        phase: usize,
+        size: &RenderSize,
+    ) -> anyhow::Result<()> {
+        let Some((cols, rows)) = visible_area(theme, size) else {
+            return Ok(());
+        };
+
+        // 删除重新加载前的帧的图片
+        let replaced = self.encoder.evict_replaced(theme);
+        let stale = self
+            .images
+            .extract_if(|(addr, ..), _| replaced.contains(addr))
+            .map(|(_, id)| id);
+        for id in stale {
+            write!(out, "\x1B_Ga=d,d=I,i={id},q=2\x1B\\")?;
+        }
+
+        let id = self.image(out, theme, frame_idx, phase)?;
+        if let Some(shown) = self.shown.replace(id)
+            && shown != id
+        {
+            // 只删除显示位置，保留图片数据供之后的循环使用
+            write!(out, "\x1B_Ga=d,d=i,i={shown},q=2\x1B\\")?;
+        }
+        queue!(out, cursor::MoveTo(0, 0))?;
+        self.place(out, id, &cols, &rows)?;
+        Ok(())
+    }
+
+    /// 删除所有已发送的图片，退出前调用
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        for id in self.images.drain().map(|(_, id)| id) {
+            write!(out, "\x1B_Ga=d,d=I,i={id},q=2\x1B\\")?;
+        }
+        self.shown = None;
+        Ok(())
+    }
+
+    /// 帧对应的图片 ID，第一次使用时发送图片
+    fn image(
+        &mut self,
+        out: &mut Vec<u8>,
+        theme: &ThemeRef,
+        frame_idx: usize,
+        phase: usize,
+    ) -> anyhow::Result<u32> {
+        let key = frame_key(theme, frame_idx, phase);
+        if let Some(&id) = self.images.get(&key) {
+            return Ok(id);
+        }
+
+        let (cols, rows) = (0..theme.width, 0..theme.height);
+        let image = self.encoder.encode(theme, frame_idx, phase, &cols, &rows)?;
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        transmit(out, id, &image)?;
+        self.images.insert(key, id);
+        Ok(id)
+    }
+
+    /// 在光标位置显示图片的 `cols` x `rows` 部分，替换该图片之前的显示位置
+    fn place(
+        &self,
+        out: &mut Vec<u8>,
+        id: u32,
+        cols: &Range<usize>,
+        rows: &Range<usize>,
+    ) -> io::Result<()> {
+        let pixels = self.encoder.cell_pixels;
+        // 放在文字下层，帧率等文字可以显示在图片上
+        write!(
+            out,
+            "\x1B_Ga=p,i={id},p=1,x={},y={},w={},h={},c={},r={},C=1,z=-1,q=2\x1B\\",
+            cols.start * pixels,
+            rows.start * pixels,
+            cols.len() * pixels,
+            rows.len() * pixels,
+            cols.len() * 2,
+            rows.len(),
+        )
+    }
+}
+
+/// 通过 iTerm2 内联图片协议显示帧
+///
+/// 协议没有图片 ID，每一帧都要发送完整的图片，也不能让终端裁剪，
+/// 所以只编码可见的部分。编码结果按帧缓存，裁剪范围变化时重新编码。
+pub struct ITerm2Images {
+    encoder: FrameEncoder,
+    /// 编码好的显示命令
+    images: HashMap<FrameKey, Vec<u8>>,
+    /// 缓存的图片对应的裁剪范围
+    area: Option<(Range<usize>, Range<usize>)>,
+}
+
+impl ITerm2Images {
+    pub fn new(flip: Flip) -> Self {
+        Self {
+            encoder: FrameEncoder::new(flip),
+            images: HashMap::new(),
+            area: None,
+        }
+    }
+
+    /// 在终端左上角显示主题的一帧，裁剪范围与块模式相同
+    pub fn draw(
+        &mut self,
+        out: &mut Vec<u8>,
+        theme: &ThemeRef,
+        frame_idx: usize,
+        phase: usize,
+        size: &RenderSize,
+    ) -> anyhow::Result<()> {
+        let Some(area) = visible_area(theme, size) else {
+            return Ok(());
+        };
+        if self.area.as_ref() != Some(&area) {
+            self.images.clear();
+            self.area = Some(area.clone());
+        }
+        let replaced = self.encoder.evict_replaced(theme);
+        self.images.retain(|(addr, ..), _| !replaced.contains(addr));
+
+        let (cols, rows) = area;
+        let command = match self.images.entry(frame_key(theme, frame_idx, phase)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let image = self.encoder.encode(theme, frame_idx, phase, &cols, &rows)?;
+                entry.insert(inline_image(&image, cols.len() * 2, rows.len()))
+            }
+        };
+        queue!(out, cursor::MoveTo(0, 0))?;
+        out.extend_from_slice(command);
+        Ok(())
+    }
+}
+
+/// iTerm2 显示图片的命令，图片缩放到 `width` x `height` 个字符
+fn inline_image(image: &[u8], width: usize, height: usize) -> Vec<u8> {
+    format!(
+        "\x1B]1337;File=inline=1;size={};width={width};height={height};preserveAspectRatio=0:{}\x07",
+        image.len(),
+        base64(image),
+    )
+    .into_bytes()
+}
+
+/// 分块发送 PNG 图片，`q=2` 使终端不回复
+fn transmit(out: &mut Vec<u8>, id: u32, image: &[u8]) -> io::Result<()> {
+    let data = base64(image);
+    let chunks = data.as_bytes().chunks(KITTY_CHUNK);
+    let count = chunks.len();
+    for (index, chunk) in chunks.enumerate() {
+        let more = u8::from(index + 1 < count);
+        if index == 0 {
+            write!(out, "\x1B_Ga=t,f=100,i={id},q=2,m={more};")?;
+        } else {
+            write!(out, "\x1B_Gm={more};")?;
+        }
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(b"\x1B\\");
+    }
+    Ok(())
+}
+
+/// 标准 Base64 编码，带填充
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                out.push(ALPHABET[(group >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_to_groups_of_four() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xFF, 0xEF]), "/+8=");
+    }
+
+    #[test]
+    fn image_protocol_is_detected_from_environment() {
+        assert_eq!(
+            detect_renderer_from(Some("xterm-kitty"), None),
+            Some(Renderer::Kitty)
+        );
+        assert_eq!(
+            detect_renderer_from(Some("xterm-256color"), Some("iTerm.app")),
+            Some(Renderer::ITerm2)
+        );
+        assert_eq!(
+            detect_renderer_from(Some("xterm-256color"), Some("Apple_Terminal")),
+            None
+        );
+        assert_eq!(detect_renderer_from(None, None), None);
+    }
+
+    #[test]
+    fn inline_image_sets_size_in_cells() {
+        let command = String::from_utf8(inline_image(b"foo", 10, 4)).unwrap();
+        assert_eq!(
+            command,
+            "\x1B]1337;File=inline=1;size=3;width=10;height=4;preserveAspectRatio=0:Zm9v\x07"
+        );
+    }
+
+    #[test]
+    fn transmit_splits_into_chunks() {
+        let mut out = Vec::new();
+        transmit(&mut out, 7, &[0; KITTY_CHUNK]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let commands = out.split("\x1B\\").filter(|command| !command.is_empty());
+        let headers = commands
+            .map(|command| command.split(';').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            ["\x1B_Ga=t,f=100,i=7,q=2,m=1", "\x1B_Gm=0"],
+            "{out:?}"
+        );
+    }
+}
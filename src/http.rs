@@ -1,16 +1,30 @@
-use std::{fmt::Display, net::SocketAddr, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 use anyhow::{Context, bail};
+
 use axum::{
-    Router,
-    body::Bytes,
+    Json, Router,
+    body::{Body, Bytes},
     extract::{
-        ConnectInfo, State, WebSocketUpgrade,
-        ws::{Message, WebSocket},
+        ConnectInfo, Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, Utf8Bytes, WebSocket, close_code},
     },
-    http::{HeaderMap, HeaderValue, Request},
-    response::Response,
-    routing::any,
+    http::{
+        HeaderMap, HeaderValue, Request, StatusCode as HttpStatusCode, Uri,
+        header::{ALLOW, AUTHORIZATION, CONTENT_TYPE, LOCATION, SEC_WEBSOCKET_PROTOCOL},
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{any, get},
 };
 use axum_extra::{TypedHeader, headers};
 use futures::{sink::SinkExt, stream::StreamExt};
@@ -18,14 +32,33 @@ use include_dir::{Dir, include_dir};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use tokio::{
-    sync::mpsc::{self, Sender},
-    time::{Instant, sleep},
+    sync::{
+        Mutex,
+        mpsc::{self, Sender},
+        watch,
+    },
+    task::JoinHandle,
+    time::{Instant, interval, sleep_until, timeout_at},
+};
+use tower_http::{
+    classify::ServerErrorsFailureClass,
+    cors::{AllowOrigin, Any, CorsLayer},
+    trace::TraceLayer,
 };
-use tower_http::{classify::ServerErrorsFailureClass, trace::TraceLayer};
 use tower_serve_static::ServeDir;
-use tracing::{Span, error, info, info_span};
+use tracing::{Span, debug, error, info, info_span, warn};
 
-use crate::{animation::FRAMES, cli::Args, telnet::build_frame};
+use crate::{
+    animation::{ColorMode, THEMES, Theme, ThemeRef, usable_size},
+    animator::Animator,
+    broadcast::FrameHub,
+    cli::Args,
+    conn::ConnectionEvents,
+    dump::OutputDump,
+    metrics::{self, Transport},
+    peer,
+    server::ServerContext,
+};
 
 /// Format request latency and status message
 /// return a string
@@ -39,6 +72,22 @@ fn format_latency(latency: Duration, status: impl Display) -> String {
     }
 }
 
+/// 日志中的请求地址，去掉查询参数中的访问令牌
+fn loggable_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_owned();
+    };
+    let query: Vec<&str> = query
+        .split('&')
+        .filter(|pair| pair.split('=').next() != Some("token"))
+        .collect();
+    if query.is_empty() {
+        uri.path().to_owned()
+    } else {
+        format!("{}?{}", uri.path(), query.join("&"))
+    }
+}
+
 /// Middleware for logging each request.
 ///
 /// This middleware will calculate each request latency
@@ -54,7 +103,8 @@ pub fn logging_route(router: Router) -> Router {
             .to_str()
             .unwrap_or("Unknown");
         let host = headers.get("Host").unwrap_or(empty).to_str().unwrap_or("");
-        info_span!("HTTP", method = ?req.method(), host, uri = ?req.uri(), ua)
+        let uri = loggable_uri(req.uri());
+        info_span!("HTTP", method = ?req.method(), host, uri, ua)
     };
 
     let trace_layer = TraceLayer::new_for_http()
@@ -74,40 +124,430 @@ pub fn logging_route(router: Router) -> Router {
     router.layer(trace_layer)
 }
 
+/// JSON 格式的错误响应 `{ "error": "...", "status": ... }`
+fn json_error(status: HttpStatusCode, message: &str) -> Response {
+    let body = serde_json::json!({
+        "error": message,
+        "status": status.as_u16(),
+    });
+    (status, Json(body)).into_response()
+}
+
+/// 将静态文件服务和路由返回的 404、405 替换为 JSON 错误
+async fn json_errors(req: Request<Body>, next: Next) -> Response {
+    let method = req.method().clone();
+    let uri = loggable_uri(req.uri());
+    let res = next.run(req).await;
+
+    let status = res.status();
+    if status != HttpStatusCode::NOT_FOUND && status != HttpStatusCode::METHOD_NOT_ALLOWED {
+        return res;
+    }
+
+    info!("No route for {method} {uri}");
+    let message = status.canonical_reason().unwrap_or("Error");
+    let mut json = json_error(status, message);
+    // 405 需要保留允许的方法
+    if let Some(allow) = res.headers().get(ALLOW) {
+        json.headers_mut().insert(ALLOW, allow.clone());
+    }
+    json
+}
+
 #[derive(Clone)]
 struct AppState {
     // 命令行参数
     args: Args,
+    // 按 IP 限制连接
+    limiter: Arc<ConnectionLimiter>,
+    // 服务器关闭信号，通知活跃的 WebSocket 连接退出
+    shutdown: watch::Receiver<bool>,
+    // 帧间隔，重新加载配置后更新
+    frame_delay: watch::Receiver<Duration>,
+    // `--frames-dir` 重新加载的帧
+    frames: Option<watch::Receiver<ThemeRef>>,
+    // 原始帧的转储文件
+    dump: Option<OutputDump>,
+    // 开启 `--shared-animation` 时所有连接共享的动画
+    hub: Option<FrameHub>,
+}
+
+/// 单个 IP 的连接记录
+#[derive(Default)]
+struct IpConnections {
+    // 当前活跃的连接数
+    active: usize,
+    // 时间窗口内建立连接的时间
+    recent: VecDeque<Instant>,
+}
+
+/// 按客户端 IP 限制 WebSocket 连接
+///
+/// 同时限制活跃连接数和时间窗口内新建连接的次数。
+struct ConnectionLimiter {
+    max_active: usize,
+    max_per_window: usize,
+    window: Duration,
+    connections: std::sync::Mutex<HashMap<IpAddr, IpConnections>>,
+}
+
+impl ConnectionLimiter {
+    fn new(args: &Args) -> Self {
+        Self {
+            max_active: args.max_connections_per_ip,
+            max_per_window: args.rate_limit,
+            window: Duration::from_secs(args.rate_window),
+            connections: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 尝试为 IP 占用一个连接，超出限制时返回 `None`
+    fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = connections.entry(ip).or_default();
+
+        let now = Instant::now();
+        while entry
+            .recent
+            .front()
+            .is_some_and(|time| now.duration_since(*time) > self.window)
+        {
+            entry.recent.pop_front();
+        }
+
+        if entry.active >= self.max_active || entry.recent.len() >= self.max_per_window {
+            return None;
+        }
+
+        entry.active += 1;
+        entry.recent.push_back(now);
+        Some(ConnectionGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = connections.get_mut(&ip) {
+            entry.active = entry.active.saturating_sub(1);
+            // 没有活跃连接且窗口内没有记录时移除，避免占用内存
+            let now = Instant::now();
+            entry
+                .recent
+                .retain(|time| now.duration_since(*time) <= self.window);
+            if entry.active == 0 && entry.recent.is_empty() {
+                connections.remove(&ip);
+            }
+        }
+    }
+}
+
+/// 连接限制使用的客户端地址，`None` 表示不限制
+///
+/// Unix 域套接字只有本机的反向代理能连接，所有连接的对端都是 [`UDS_PEER_ADDR`]，
+/// 所以按代理在 `X-Forwarded-For` 末尾追加的地址限制。没有该请求头时无法区分客户端，不做限制。
+fn limited_ip(addr: SocketAddr, headers: &HeaderMap, behind_uds: bool) -> Option<IpAddr> {
+    if !behind_uds {
+        return Some(addr.ip());
+    }
+    headers
+        .get("X-Forwarded-For")?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// 连接占用凭证，释放时归还连接数
+struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
 }
 
 static FRONTEND_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/frontend/dist");
 
-pub async fn run_http(args: Args) -> anyhow::Result<()> {
-    let state = AppState { args };
+pub async fn run_http(args: Args, context: ServerContext) -> anyhow::Result<()> {
+    #[cfg(not(unix))]
+    if args.uds.is_some() {
+        bail!("--uds is only supported on Unix platforms");
+    }
+
+    #[cfg(feature = "tls")]
+    let acceptor = crate::tls::acceptor(&args)?;
+    #[cfg(feature = "tls")]
+    if acceptor.is_some() && args.uds.is_some() {
+        bail!("--uds cannot be combined with TLS, terminate TLS in the reverse proxy instead");
+    }
+
+    let limiter = Arc::new(ConnectionLimiter::new(&args));
+    let shutdown = context.subscribe_shutdown();
+    let frame_delay = context.frame_delay.clone();
+    let frames = context.frames.clone();
+    #[cfg(unix)]
+    let uds = args.uds.clone();
+    let addr = args.http_addr;
+    let dump = context.dump.clone();
+    let hub = args
+        .shared_animation
+        .then(|| FrameHub::new(&args, frame_delay.clone(), frames.clone()));
+    let state = AppState {
+        args,
+        limiter,
+        shutdown,
+        frame_delay,
+        frames,
+        dump,
+        hub,
+    };
 
     let service = ServeDir::new(&FRONTEND_DIR);
 
+    let cors = cors_layer(&state.args.cors_origin)?;
+
     let app = Router::new()
         .fallback_service(service)
         .route("/ws", any(ws))
-        .with_state(state);
+        .route("/info", get(info))
+        .route(
+            "/metrics",
+            get(|| async {
+                (
+                    [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    metrics::render(),
+                )
+            }),
+        );
+    // 前端托管在其他位置时，访问首页重定向过去，其余静态文件仍由本服务提供
+    let app = match state.args.client_url.clone() {
+        Some(url) => app.route(
+            "/",
+            get(move || async move { (HttpStatusCode::FOUND, [(LOCATION, url)]) }),
+        ),
+        None => app,
+    };
+    #[cfg(feature = "png")]
+    let app = app.route("/frame.png", get(frame_png));
+    let app = app
+        .with_state(state)
+        .layer(middleware::from_fn(json_errors));
+    let app = match cors {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
 
     let app = logging_route(app);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    // 关闭通知同时让所有连接发送关闭帧，serve 等待连接结束后返回
+    let graceful = async move {
+        context.shutdown_requested().await;
+        info!("Closing websocket connections");
+    };
+
+    #[cfg(unix)]
+    if let Some(path) = uds {
+        use axum::serve::ListenerExt;
+
+        // UnixListener 的对端地址不是 SocketAddr，用占位地址包一层以复用 ConnectInfo
+        let listener = UdsListener::bind(&path)?.tap_io(|_| {});
+        info!("listening on {}", path.display());
+        let served = axum::serve(listener, make_service)
+            .with_graceful_shutdown(graceful)
+            .await;
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Remove socket file {} failed: {e}", path.display());
+        }
+        served?;
+        return Ok(());
+    }
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Could not bind {addr}"))?;
     info!("listening on {}", listener.local_addr()?);
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await?;
+
+    #[cfg(feature = "tls")]
+    if let Some(acceptor) = acceptor {
+        use axum::serve::ListenerExt;
+
+        info!("TLS enabled");
+        // axum 只为 TcpListener 和 TapIo 实现了 SocketAddr 的 ConnectInfo，
+        // 用空的 tap_io 包一层以获取客户端地址
+        let listener = crate::tls::TlsListener::new(listener, acceptor)?.tap_io(|_| {});
+        axum::serve(listener, make_service)
+            .with_graceful_shutdown(graceful)
+            .await?;
+        return Ok(());
+    }
+
+    axum::serve(listener, make_service)
+        .with_graceful_shutdown(graceful)
+        .await?;
     Ok(())
 }
 
+/// Unix 域套接字连接在日志和连接限制中使用的占位地址
+#[cfg(unix)]
+const UDS_PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+/// 监听 Unix 域套接字的 HTTP 监听器，所有连接使用 [`UDS_PEER_ADDR`] 作为对端地址
+#[cfg(unix)]
+struct UdsListener(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl UdsListener {
+    /// 绑定套接字文件，已存在的套接字文件视为上次运行遗留并删除
+    fn bind(path: &std::path::Path) -> anyhow::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                bail!("{} already exists and is not a socket", path.display());
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("remove stale socket {} failed", path.display()))?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)
+            .with_context(|| format!("bind unix socket {} failed", path.display()))?;
+        Ok(Self(listener))
+    }
+}
+
+#[cfg(unix)]
+impl axum::serve::Listener for UdsListener {
+    type Io = tokio::net::UnixStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match self.0.accept().await {
+                Ok((stream, _)) => return (stream, UDS_PEER_ADDR),
+                Err(e) => {
+                    error!("Accept error: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(UDS_PEER_ADDR)
+    }
+}
+
+/// 根据 `--cors-origin` 构建 CORS 中间件，未设置时返回 `None`
+///
+/// `*` 返回 `Access-Control-Allow-Origin: *`，明确的来源列表只回显匹配的来源。
+fn cors_layer(origins: &[String]) -> anyhow::Result<Option<CorsLayer>> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .with_context(|| format!("Invalid CORS origin `{origin}`"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(Any)
+            .allow_headers(Any),
+    ))
+}
+
+/// WebSocket 握手的 `Origin` 是否在 `--cors-origin` 允许的范围内
+///
+/// 未配置 CORS、配置了 `*` 或请求没有 `Origin`（非浏览器客户端）时都允许。
+fn origin_allowed(origins: &[String], origin: Option<&HeaderValue>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    origins.is_empty()
+        || origins.iter().any(|allowed| {
+            allowed == "*" || allowed.trim_end_matches('/').as_bytes() == origin.as_bytes()
+        })
+}
+
+/// 支持的 WebSocket 子协议，按优先顺序排列
+const PROTOCOLS: [&str; 1] = ["nyancat.v1"];
+
+/// 协商得到的协议版本，未携带子协议的旧客户端按 v1 处理
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProtocolVersion {
+    V1,
+}
+
+impl ProtocolVersion {
+    fn from_protocol(protocol: Option<&HeaderValue>) -> Self {
+        match protocol.and_then(|protocol| protocol.to_str().ok()) {
+            Some("nyancat.v1") | None => Self::V1,
+            // PROTOCOLS 之外的子协议不会被选中
+            Some(_) => unreachable!("unsupported protocol selected"),
+        }
+    }
+}
+
+/// WebSocket 连接参数
+#[derive(Deserialize)]
+struct WsQuery {
+    /// 动画主题，未指定或不存在时使用命令行参数中的主题
+    theme: Option<String>,
+    /// 访问令牌，也可以通过 `Authorization` 头提供
+    token: Option<String>,
+    /// 初始终端宽度，与 `height` 同时提供时无需等待客户端上报大小
+    width: Option<u16>,
+    /// 初始终端高度
+    height: Option<u16>,
+}
+
+/// 检查请求是否携带正确的访问令牌，未设置 `--http-token` 时总是通过
+fn token_authorized(expected: Option<&str>, query: Option<&str>, headers: &HeaderMap) -> bool {
+    let Some(expected) = expected else {
+        return true;
+    };
+    let header = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).trim());
+    [query, header]
+        .into_iter()
+        .flatten()
+        .any(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+/// 常量时间比较，避免通过响应时间猜测令牌
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 async fn ws(
     ws: WebSocketUpgrade,
+    req_headers: HeaderMap,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<WsQuery>,
     state: State<AppState>,
 ) -> axum::response::Response {
     info!("`{user_agent:?}` at {addr:?} connected.");
@@ -118,7 +558,84 @@ async fn ws(
     };
     info!("`{user_agent}` at {addr:?} connected.");
 
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, state.args.clone()))
+    if !token_authorized(
+        state.args.http_token.as_deref(),
+        query.token.as_deref(),
+        &req_headers,
+    ) {
+        warn!("Websocket from {addr} rejected, invalid token");
+        return json_error(HttpStatusCode::UNAUTHORIZED, "Invalid token");
+    }
+
+    if !origin_allowed(&state.args.cors_origin, req_headers.get("Origin")) {
+        warn!(
+            "Websocket from {addr} with disallowed origin {:?}",
+            req_headers.get("Origin")
+        );
+        return json_error(HttpStatusCode::FORBIDDEN, "Origin not allowed");
+    }
+
+    // 客户端指定了子协议但都不支持时拒绝，未指定时按 v1 处理
+    let ws = ws.protocols(PROTOCOLS);
+    if req_headers.contains_key(SEC_WEBSOCKET_PROTOCOL) && ws.selected_protocol().is_none() {
+        warn!(
+            "Websocket from {addr} rejected, unsupported subprotocol {:?}",
+            req_headers.get(SEC_WEBSOCKET_PROTOCOL)
+        );
+        return json_error(HttpStatusCode::BAD_REQUEST, "Unsupported subprotocol");
+    }
+    let version = ProtocolVersion::from_protocol(ws.selected_protocol());
+
+    let guard = match limited_ip(addr, &req_headers, state.args.uds.is_some()) {
+        Some(ip) => {
+            let Some(guard) = state.limiter.acquire(ip) else {
+                warn!("Too many connections from {ip}, rejected");
+                return json_error(HttpStatusCode::TOO_MANY_REQUESTS, "Too many connections");
+            };
+            Some(guard)
+        }
+        None => None,
+    };
+
+    let theme = match query.theme.as_deref() {
+        Some(name) => requested_theme(&state.args, name).unwrap_or_else(|| {
+            warn!("Unknown theme `{name}` requested by {addr}, using default");
+            state.args.theme
+        }),
+        None => state.args.theme,
+    };
+    // 只有使用默认主题的连接跟随重新加载的帧
+    let theme_updates = state
+        .frames
+        .clone()
+        .filter(|_| std::ptr::eq(theme, state.args.theme));
+
+    // 通过参数提供大小的客户端可以跳过握手直接开始动画
+    let initial_size = query
+        .width
+        .zip(query.height)
+        .map(|size| clamp_size(size, (state.args.default_width, state.args.default_height)));
+
+    // guard 随连接处理结束释放，包括任务被中止的情况
+    ws.on_upgrade(move |socket| async move {
+        handle_socket(
+            socket,
+            WsClient {
+                who: addr,
+                version,
+                theme,
+                theme_updates,
+                initial_size,
+            },
+            state.args.clone(),
+            state.frame_delay.clone(),
+            state.shutdown.clone(),
+            state.dump.clone(),
+            state.hub.clone(),
+        )
+        .await;
+        drop(guard);
+    })
 }
 
 #[derive(Serialize_repr, Deserialize_repr, PartialEq, Debug)]
@@ -129,6 +646,147 @@ pub enum StatusCode {
     /// 处理消息，客户端返回大小，服务端发送动画
     Ok = 1,
     Error = 2,
+    /// 客户端调整帧间隔，携带 `delay` 毫秒数，或相对服务端帧间隔的 `speed` 倍数
+    SetRate = 3,
+}
+
+/// 客户端可设置的最小帧间隔（毫秒）
+const MIN_DELAY_MS: u64 = 16;
+/// 客户端可设置的最大帧间隔（毫秒）
+const MAX_DELAY_MS: u64 = 2000;
+
+/// 客户端可设置的速度倍数，与 `--speed` 一致
+const MIN_SPEED: f32 = 0.1;
+const MAX_SPEED: f32 = 10.0;
+
+/// 请求参数中的主题，与启动时的主题一样应用动画、缩放、亮度和 ASCII 设置
+///
+/// 主题名称未知时返回 `None`。
+fn requested_theme(args: &Args, name: &str) -> Option<&'static Theme> {
+    Theme::by_name(name).map(|theme| args.adjust_theme(theme.with_animation(args.animation)))
+}
+
+/// 客户端可上报的最大终端宽高
+const MAX_TERMINAL_SIZE: u16 = 1000;
+
+/// 将客户端上报的大小限制在 `1..=MAX_TERMINAL_SIZE` 内，宽或高为 0 时使用 `fallback`
+fn clamp_size(size: (u16, u16), fallback: (u16, u16)) -> (u16, u16) {
+    let (width, height) = usable_size(size, fallback);
+    (
+        width.clamp(1, MAX_TERMINAL_SIZE),
+        height.clamp(1, MAX_TERMINAL_SIZE),
+    )
+}
+
+/// `/info` 响应格式的版本，只增加字段时不变
+const INFO_VERSION: u32 = 1;
+
+/// 动画的基本信息，客户端据此设置画布大小
+#[derive(Serialize)]
+struct AnimationInfo {
+    version: u32,
+    /// 默认主题的帧数
+    frames: usize,
+    /// 帧宽度（列）
+    width: usize,
+    /// 帧高度（行）
+    height: usize,
+    /// 默认帧率，由 `--frame-delay-ms` 换算时取整
+    fps: u32,
+    /// 默认主题
+    theme: &'static str,
+    themes: Vec<ThemeInfo>,
+    /// 支持的 WebSocket 子协议
+    protocols: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+struct ThemeInfo {
+    name: &'static str,
+    description: &'static str,
+}
+
+/// 返回动画的帧数、帧大小、帧率、可用主题和协议版本
+async fn info(State(state): State<AppState>) -> Json<AnimationInfo> {
+    let theme = state.args.theme;
+    Json(AnimationInfo {
+        version: INFO_VERSION,
+        frames: theme.frames.len(),
+        width: theme.width,
+        height: theme.height,
+        fps: (1.0 / state.args.frame_delay().as_secs_f64()).round() as u32,
+        theme: theme.name,
+        themes: THEMES
+            .iter()
+            .map(|theme| ThemeInfo {
+                name: theme.name,
+                description: theme.description,
+            })
+            .collect(),
+        protocols: &PROTOCOLS,
+    })
+}
+
+/// `/frame.png` 单元格的最大像素
+#[cfg(feature = "png")]
+const MAX_CELL_PIXELS: usize = 64;
+
+/// 将单帧渲染为 PNG 图片
+///
+/// 参数：`frame` 帧索引（按帧数取模）、`width`/`height` 以帧为中心裁剪的单元格数、
+/// `cell_width`/`cell_height` 每个单元格的像素、`theme` 主题。
+/// 缺失或无效的参数使用默认值并限制在有效范围内，不会返回错误。
+#[cfg(feature = "png")]
+async fn frame_png(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let param = |name: &str| {
+        params
+            .get(name)
+            .and_then(|value| value.parse::<usize>().ok())
+    };
+    let theme = params
+        .get("theme")
+        .and_then(|name| requested_theme(&state.args, name))
+        .unwrap_or(state.args.theme);
+
+    let frame_idx = param("frame").unwrap_or(0) % theme.frames.len();
+    let width = param("width").unwrap_or(theme.width).clamp(1, theme.width);
+    let height = param("height")
+        .unwrap_or(theme.height)
+        .clamp(1, theme.height);
+    let cell_width = param("cell_width")
+        .unwrap_or(state.args.gif_cell_width.into())
+        .clamp(1, MAX_CELL_PIXELS);
+    let cell_height = param("cell_height")
+        .unwrap_or(state.args.gif_cell_height.into())
+        .clamp(1, MAX_CELL_PIXELS);
+
+    // 与终端一样以帧为中心裁剪
+    let min_col = (theme.width - width) / 2;
+    let min_row = (theme.height - height) / 2;
+    let (cols, rows) = (min_col..min_col + width, min_row..min_row + height);
+
+    let image =
+        crate::raster::Rasterizer::new(theme.into(), cell_width, cell_height).and_then(|raster| {
+            let (image_width, image_height) = raster.image_size(&cols, &rows);
+            let mut pixels = vec![0; image_width * image_height];
+            raster.render(frame_idx, 0, &cols, &rows, &mut pixels);
+            crate::png::encode_indexed(
+                image_width as u32,
+                image_height as u32,
+                &raster.palette(),
+                &pixels,
+            )
+        });
+    match image {
+        Ok(image) => ([(CONTENT_TYPE, "image/png")], image).into_response(),
+        Err(err) => {
+            error!("Render frame image failed: {err:#}");
+            json_error(HttpStatusCode::INTERNAL_SERVER_ERROR, "render failed")
+        }
+    }
 }
 
 /// 消息帧
@@ -141,108 +799,463 @@ pub struct MessageFrame {
     height: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     frame: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay: Option<u64>,
+    /// 速度倍数，只在调整帧间隔的消息中生效，同时提供时优先于 `delay`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    speed: Option<f32>,
+    /// 客户端的终端类型，如 `xterm-256color`，用于选择颜色模式，只在初始化消息中生效
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    term: Option<String>,
+    /// 客户端无法显示方块和制表符时为 `true`，只在初始化消息中生效
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient"
+    )]
+    ascii: Option<bool>,
 }
 
-async fn handle_socket(socket: WebSocket, who: SocketAddr, args: Args) {
-    let (mut sender, mut receiver) = socket.split();
+/// 把一帧序列化为发送给客户端的消息
+pub fn frame_message(frame: String) -> anyhow::Result<Utf8Bytes> {
+    let msg = MessageFrame {
+        code: StatusCode::Ok,
+        width: None,
+        height: None,
+        frame: Some(frame),
+        delay: None,
+        speed: None,
+        term: None,
+        ascii: None,
+    };
+    let serialized = serde_json::to_string(&msg).with_context(|| "Could not serialize message")?;
+    Ok(serialized.into())
+}
+
+/// 类型不符的可选字段视为未提供，旧客户端或写错的字段不会导致连接断开
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+/// 终端类型的最大长度，与 Telnet TTYPE 一致只接受可打印 ASCII
+const MAX_TERM_LEN: usize = 64;
+
+/// 根据客户端上报的终端类型选择颜色模式，无法识别或不合法时返回 `None`
+fn client_color_mode(term: &str) -> Option<ColorMode> {
+    let valid = !term.is_empty()
+        && term.len() <= MAX_TERM_LEN
+        && term.bytes().all(|byte| byte.is_ascii_graphic());
+    valid.then(|| ColorMode::from_term(term)).flatten()
+}
+
+/// 升级时确定的连接信息
+struct WsClient {
+    who: SocketAddr,
+    version: ProtocolVersion,
+    theme: &'static Theme,
+    /// `--frames-dir` 重新加载的帧，请求了其他主题时为 `None`
+    theme_updates: Option<watch::Receiver<ThemeRef>>,
+    /// 连接参数中提供的终端大小
+    initial_size: Option<(u16, u16)>,
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    client: WsClient,
+    args: Args,
+    mut frame_delay: watch::Receiver<Duration>,
+    mut shutdown: watch::Receiver<bool>,
+    dump: Option<OutputDump>,
+    hub: Option<FrameHub>,
+) {
+    let WsClient {
+        who,
+        version,
+        theme,
+        theme_updates,
+        initial_size,
+    } = client;
+
+    // 发送任务也持有一份，全部结束或被中止后发出断开事件
+    let events = Arc::new(ConnectionEvents::connect(Transport::WebSocket, who));
+    let send_events = events.clone();
+    peer::log_hostname(who.ip(), args.resolve_hostnames);
+
+    // 以后的协议版本在这里区分消息格式
+    match version {
+        ProtocolVersion::V1 => debug!("Websocket {who} speaks protocol v1"),
+    }
+
+    let (sender, mut receiver) = socket.split();
+    // 发送任务、心跳任务和关闭时共用发送端
+    let sender = Arc::new(Mutex::new(sender));
+    let ping_sender = sender.clone();
+    let close_sender = sender.clone();
+
+    // 最后一次收到客户端消息的时间，用于检测失效连接
+    let last_seen = Arc::new(std::sync::Mutex::new(Instant::now()));
+    let ping_last_seen = last_seen.clone();
 
     // 从 WebSocket 接收消息并发送到应用程序
-    let (tx_from_ws, mut rx_from_ws) = mpsc::channel::<MessageFrame>(128); // WebSocket → 应用程序
+    let (tx_from_ws, mut rx_from_ws) =
+        mpsc::channel::<MessageFrame>(args.ws_channel_capacity as usize); // WebSocket → 应用程序
+    // 动画开始后的大小调整不排队，发送任务每帧只取最新的大小
+    let (resize_tx, mut resize_rx) = watch::channel((None, None));
+
+    // 连接参数中带有大小时，相当于客户端已经上报了大小
+    if let Some((width, height)) = initial_size {
+        let _ = tx_from_ws.try_send(MessageFrame {
+            code: StatusCode::Ok,
+            width: Some(width),
+            height: Some(height),
+            frame: None,
+            delay: None,
+            speed: None,
+            term: None,
+            ascii: None,
+        });
+    }
+
+    // 帧间隔，由接收任务更新，发送任务每帧读取
+    let delay = Arc::new(AtomicU64::new(
+        frame_delay.borrow_and_update().as_millis() as u64
+    ));
+    let send_delay = delay.clone();
+    // 客户端设置过帧间隔后不再使用共享动画，改为单独渲染
+    let custom_rate = Arc::new(AtomicBool::new(false));
+    let send_custom_rate = custom_rate.clone();
+
+    let mut control = WsControl {
+        who,
+        messages: tx_from_ws,
+        resize: resize_tx,
+        delay,
+        base_delay: frame_delay.clone(),
+        custom_rate,
+        started: initial_size.is_some(),
+    };
+
+    let ping_interval = Duration::from_secs(args.ws_ping_interval.max(1));
+    let ping_timeout = Duration::from_secs(args.ws_timeout);
 
     // 发送方向 从 channel 接受消息
     let args = args.clone();
     let mut send_task = tokio::spawn(async move {
+        let finish_sender = sender.clone();
+        let init_sender = sender.clone();
         // 第一帧
         let msg = MessageFrame {
             code: StatusCode::Init,
             width: None,
             height: None,
             frame: None,
+            delay: None,
+            speed: None,
+            term: None,
+            ascii: None,
         };
         let msg_serialized =
             serde_json::to_string(&msg).with_context(|| "Could not serialize init message")?;
         sender
+            .lock()
+            .await
             .send(Message::Text(msg_serialized.into()))
             .await
             .with_context(|| "Could not send message")?;
 
-        // 后续帧
-        while let Some(msg) = rx_from_ws.recv().await {
-            match msg.code {
-                StatusCode::Ok => {
-                    // 构建帧内容
-                    let width = msg
-                        .width
-                        .ok_or_else(|| anyhow::anyhow!("Could not get width from message"))?;
-                    let height = msg
-                        .height
-                        .ok_or_else(|| anyhow::anyhow!("Could not get height from message"))?;
-
-                    // 发送动画帧
-                    let mut frame_idx = 0;
-                    let start_time = Instant::now();
-                    loop {
-                        let frame_data = build_frame(width, height, &args, frame_idx, start_time);
-
-                        let msg = MessageFrame {
-                            code: StatusCode::Ok,
-                            width: None,
-                            height: None,
-                            frame: Some(frame_data),
+        // 最新一帧，客户端跟不上时未发送的旧帧直接被覆盖，动画保持实时
+        let (frame_tx, mut frame_rx) = watch::channel(None::<Utf8Bytes>);
+        // 是否有已生成但尚未被取走的帧
+        let pending = Arc::new(AtomicBool::new(false));
+        let writer_pending = pending.clone();
+        // 只统计实际发送的帧，被丢弃的帧不计入
+        let writer_events = send_events.clone();
+        let writer = async move {
+            while frame_rx.changed().await.is_ok() {
+                let Some(frame) = frame_rx.borrow_and_update().clone() else {
+                    continue;
+                };
+                writer_pending.store(false, Ordering::Relaxed);
+                let mut sender = sender.lock().await;
+                // 只统计发送本身，不包含等待锁的时间
+                let sent_at = Instant::now();
+                let len = frame.len() as u64;
+                sender
+                    .send(Message::Text(frame))
+                    .await
+                    .with_context(|| "Could not send message")?;
+                writer_events.frame_sent(len);
+                metrics::observe_send(Transport::WebSocket, sent_at.elapsed());
+            }
+            anyhow::Ok(())
+        };
+
+        // 按帧间隔生成帧，发送不会阻塞计时和大小调整
+        // 动画结束后发送的关闭帧，客户端可以区分正常结束和连接中断
+        let finished = || CloseFrame {
+            code: close_code::NORMAL,
+            reason: "Animation finished".into(),
+        };
+        // 客户端需要在限定时间内上报大小，收到后不再计时
+        let init_deadline = Instant::now() + Duration::from_secs(args.ws_init_timeout);
+        let producer = async move {
+            loop {
+                let Ok(msg) = timeout_at(init_deadline, rx_from_ws.recv()).await else {
+                    warn!(
+                        "Websocket {who} sent no terminal size within {}s, closing",
+                        args.ws_init_timeout
+                    );
+                    send_events.close("init_timeout");
+                    let msg = MessageFrame {
+                        code: StatusCode::Error,
+                        width: None,
+                        height: None,
+                        frame: None,
+                        delay: None,
+                        speed: None,
+                        term: None,
+                        ascii: None,
+                    };
+                    let msg_serialized = serde_json::to_string(&msg)
+                        .with_context(|| "Could not serialize error message")?;
+                    init_sender
+                        .lock()
+                        .await
+                        .send(Message::Text(msg_serialized.into()))
+                        .await
+                        .with_context(|| "Could not send message")?;
+                    return anyhow::Ok(CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "No terminal size received".into(),
+                    });
+                };
+                let Some(msg) = msg else {
+                    break;
+                };
+                match msg.code {
+                    StatusCode::Ok => {
+                        // 构建帧内容
+                        // 客户端未上报大小时使用默认值
+                        let default_size = (args.default_width, args.default_height);
+                        let (mut width, mut height) = clamp_size(
+                            (
+                                msg.width.unwrap_or(args.default_width),
+                                msg.height.unwrap_or(args.default_height),
+                            ),
+                            default_size,
+                        );
+
+                        // 根据客户端能力选择颜色模式和字符集，未上报时保持完整效果
+                        let color_mode = match msg.term.as_deref() {
+                            Some(term) => client_color_mode(term).unwrap_or_else(|| {
+                                debug!("Ignoring unknown terminal type {term:?} from {who}");
+                                ColorMode::default()
+                            }),
+                            None => ColorMode::default(),
+                        };
+                        let theme = match msg.ascii {
+                            Some(true) => theme.with_ascii_only(),
+                            _ => theme,
                         };
+                        send_events.size_detected(width, height, msg.term.as_deref(), color_mode);
 
-                        let msg_serialized = serde_json::to_string(&msg)
-                            .with_context(|| "Could not serialize message")?;
+                        // 发送动画帧
+                        let mut animator = Animator::new(&args)
+                            .with_theme(theme.into())
+                            .with_theme_updates(theme_updates.clone());
+                        // 共享动画的时间限制按连接计算
+                        let deadline = args
+                            .duration
+                            .map(|secs| animator.start_time() + Duration::from_secs(secs));
+                        // 共享动画的订阅和对应的大小，大小变化后重新订阅
+                        let mut shared = None;
+                        loop {
+                            let shared_hub = hub
+                                .as_ref()
+                                .filter(|_| !send_custom_rate.load(Ordering::Relaxed));
+                            let msg_serialized = match shared_hub {
+                                Some(hub) => {
+                                    if shared
+                                        .as_ref()
+                                        .is_some_and(|(size, _)| *size != (width, height))
+                                    {
+                                        shared = None;
+                                    }
+                                    let (_, frames) = shared.get_or_insert_with(|| {
+                                        let frames =
+                                            hub.subscribe(theme, color_mode, width, height);
+                                        ((width, height), frames)
+                                    });
+                                    let changed = tokio::select! {
+                                        changed = frames.changed() => changed,
+                                        () = async {
+                                            match deadline {
+                                                Some(deadline) => sleep_until(deadline).await,
+                                                None => std::future::pending().await,
+                                            }
+                                        } => return anyhow::Ok(finished()),
+                                    };
+                                    if changed.is_err() {
+                                        return anyhow::Ok(finished());
+                                    }
+                                    let Some(frame) = frames.borrow_and_update().clone() else {
+                                        continue;
+                                    };
+                                    if let Some(dump) = &dump {
+                                        dump.write(frame.frame.as_bytes());
+                                    }
+                                    frame.message.clone()
+                                }
+                                None => {
+                                    shared = None;
+                                    let frame_data = animator
+                                        .next_frame_string(width, height, &args, color_mode);
+                                    // 转储帧内容而不是 JSON 消息，便于直接回放
+                                    if let Some(dump) = &dump {
+                                        dump.write(frame_data.as_bytes());
+                                    }
+                                    frame_message(frame_data)?
+                                }
+                            };
 
-                        // 发送帧数据
-                        sender
-                            .send(Message::Text(msg_serialized.into()))
-                            .await
-                            .with_context(|| "Could not send message")?;
+                            // 交给发送方，上一帧还没发出时被替换
+                            frame_tx.send_replace(Some(msg_serialized));
+                            if pending.swap(true, Ordering::Relaxed) {
+                                debug!("Client {who} is behind, dropping a stale frame");
+                            }
 
-                        // 控制帧率
-                        sleep(Duration::from_millis(100)).await;
+                            // 控制帧率，重新加载配置后覆盖客户端设置的帧间隔
+                            if frame_delay.has_changed().unwrap_or(false) {
+                                let reloaded = frame_delay.borrow_and_update().as_millis() as u64;
+                                send_delay.store(reloaded, Ordering::Relaxed);
+                            }
+                            animator.set_delay(Duration::from_millis(
+                                send_delay.load(Ordering::Relaxed),
+                            ));
+                            if let Some(limit) = args.max_bytes
+                                && send_events.bytes_sent() >= limit
+                            {
+                                info!("Websocket {who} reached the limit of {limit} bytes");
+                                return anyhow::Ok(finished());
+                            }
+                            // 共享动画由分组控制帧率，下一次循环等待新帧
+                            if shared.is_none() && !animator.tick().await {
+                                return anyhow::Ok(finished());
+                            }
 
-                        // 下一帧
-                        frame_idx = (frame_idx + 1) % FRAMES.len();
+                            // 动画过程中客户端上报的新大小在下一帧生效
+                            if resize_rx.has_changed().unwrap_or(false) {
+                                let (new_width, new_height) = *resize_rx.borrow_and_update();
+                                (width, height) = clamp_size(
+                                    (new_width.unwrap_or(width), new_height.unwrap_or(height)),
+                                    (width, height),
+                                );
+                            }
+                            while let Ok(msg) = rx_from_ws.try_recv() {
+                                if msg.code == StatusCode::Error {
+                                    bail!("Error received from client");
+                                }
+                            }
+                        }
                     }
+                    StatusCode::Error => {
+                        bail!("Error received from client");
+                    }
+                    _ => continue,
                 }
-                StatusCode::Error => {
-                    bail!("Error received from client");
-                }
-                _ => continue,
             }
-        }
+            // 生成结束后关闭通道，发送方发完最后一帧后退出
+            drop(frame_tx);
+            anyhow::Ok(finished())
+        };
+
+        let ((), frame) = tokio::try_join!(writer, producer)?;
 
+        finish_sender
+            .lock()
+            .await
+            .send(Message::Close(Some(frame)))
+            .await
+            .with_context(|| "Could not send close frame")?;
         anyhow::Ok(())
     });
 
     // This second task will receive messages from client and print them on server console
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = receiver.next().await {
+            // 任何消息（包括 Pong）都说明连接仍然存活
+            *last_seen.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
             // print message and break if instructed to do so
-            process_message(msg, who, tx_from_ws.clone())
+            process_message(msg, &mut control)
                 .await
                 .with_context(|| "Error processing message")?;
         }
         anyhow::Ok(())
     });
 
-    // If any one of the tasks exit, abort the other.
+    // 心跳任务，定时发送 Ping，超时未收到客户端消息则断开
+    let mut ping_task: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
+        let mut ticker = interval(ping_interval);
+        // 第一次 tick 立即完成，跳过
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let idle = ping_last_seen
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .elapsed();
+            if idle > ping_timeout {
+                bail!("No response from client for {}s", idle.as_secs());
+            }
+            ping_sender
+                .lock()
+                .await
+                .send(Message::Ping(Bytes::new()))
+                .await
+                .with_context(|| "Could not send ping")?;
+        }
+    });
+
+    // If any one of the tasks exit, abort the others.
     tokio::select! {
         rv_a = (&mut send_task) => {
             match rv_a {
                 Ok(a) => {
                     match a {
-                        Ok(_) => info!("messages sent to {who}"),
-                        Err(a) => error!("Error sending messages {a:?}")
+                        Ok(_) => {
+                            events.close("finished");
+                            info!("messages sent to {who}");
+                        }
+                        Err(a) => {
+                            events.close("error");
+                            error!("Error sending messages {a:?}");
+                        }
                     }
                 },
-                Err(a) => error!("Error sending messages {a:?}")
+                Err(a) => {
+                    events.close("error");
+                    error!("Error sending messages {a:?}");
+                }
             }
             recv_task.abort();
+            ping_task.abort();
         },
         rv_b = (&mut recv_task) => {
+            // 客户端的关闭帧也会以错误结束接收任务
+            events.close("client_closed");
             match rv_b {
                 Ok(b) => {
                     match b {
@@ -253,6 +1266,31 @@ async fn handle_socket(socket: WebSocket, who: SocketAddr, args: Args) {
                 Err(b) => error!("Error receiving messages {b:?}")
             }
             send_task.abort();
+            ping_task.abort();
+        },
+        rv_c = (&mut ping_task) => {
+            events.close("timeout");
+            match rv_c {
+                Ok(Err(c)) => error!("Websocket keepalive failed {c:?}"),
+                Ok(Ok(_)) => {}
+                Err(c) => error!("Websocket keepalive failed {c:?}")
+            }
+            send_task.abort();
+            recv_task.abort();
+        },
+        _ = async { shutdown.wait_for(|shutdown| *shutdown).await.is_ok() } => {
+            events.close("shutdown");
+            send_task.abort();
+            recv_task.abort();
+            ping_task.abort();
+            // 服务器关闭时发送关闭帧，客户端看到的是正常关闭而不是连接重置
+            let frame = CloseFrame {
+                code: close_code::AWAY,
+                reason: "Server shutting down".into(),
+            };
+            if let Err(e) = close_sender.lock().await.send(Message::Close(Some(frame))).await {
+                warn!("Could not send close frame to {who}: {e}");
+            }
         }
     }
 
@@ -260,17 +1298,81 @@ async fn handle_socket(socket: WebSocket, who: SocketAddr, args: Args) {
     info!("Websocket context {who} destroyed");
 }
 
-/// helper to print contents of messages to stdout. Has special treatment for Close.
-async fn process_message(
-    msg: Message,
+/// 接收任务把客户端消息转交给发送任务的通道
+struct WsControl {
     who: SocketAddr,
-    tx_from_ws: Sender<MessageFrame>,
-) -> anyhow::Result<()> {
+    /// 初始化消息和错误，按顺序处理
+    messages: Sender<MessageFrame>,
+    /// 动画开始后的大小调整，只保留最新的值
+    resize: watch::Sender<(Option<u16>, Option<u16>)>,
+    /// 帧间隔（毫秒），发送任务每帧读取
+    delay: Arc<AtomicU64>,
+    /// 服务端的帧间隔，客户端按速度倍数调整时以此为基准
+    base_delay: watch::Receiver<Duration>,
+    /// 客户端是否设置过帧间隔
+    custom_rate: Arc<AtomicBool>,
+    /// 是否已经转交过初始化消息，之后的初始化消息都是大小调整
+    started: bool,
+}
+
+impl WsControl {
+    /// 按顺序转交消息，队列快满时输出警告
+    async fn forward(&self, msg: MessageFrame) -> anyhow::Result<()> {
+        let free = self.messages.capacity();
+        let capacity = self.messages.max_capacity();
+        if free <= capacity / 4 {
+            warn!(
+                "Message queue for {} is nearly full ({free} of {capacity} slots free), \
+                 the animation task is falling behind",
+                self.who
+            );
+        }
+        self.messages.send(msg).await?;
+        Ok(())
+    }
+}
+
+/// helper to print contents of messages to stdout. Has special treatment for Close.
+async fn process_message(msg: Message, control: &mut WsControl) -> anyhow::Result<()> {
+    let who = control.who;
     match msg {
         Message::Text(t) => {
             info!(">>> {who} sent str: {t:?}");
             let msg = serde_json::from_str::<MessageFrame>(&t)?;
-            tx_from_ws.send(msg).await?;
+            match msg.code {
+                StatusCode::SetRate => {
+                    // 帧间隔直接写入共享状态，发送任务在下一帧生效
+                    let requested = match (msg.speed, msg.delay) {
+                        (Some(speed), _) => {
+                            let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+                            control
+                                .base_delay
+                                .borrow()
+                                .div_f64(speed.into())
+                                .as_millis() as u64
+                        }
+                        (None, Some(delay)) => delay,
+                        (None, None) => {
+                            anyhow::bail!("Could not get delay or speed from message")
+                        }
+                    };
+                    let clamped = requested.clamp(MIN_DELAY_MS, MAX_DELAY_MS);
+                    control.delay.store(clamped, Ordering::Relaxed);
+                    control.custom_rate.store(true, Ordering::Relaxed);
+                    info!(">>> {who} set frame delay to {clamped}ms");
+                }
+                // 连续的大小调整合并为最新的一次，不会排在其他消息后面
+                StatusCode::Ok if control.started => {
+                    control.resize.send_modify(|(width, height)| {
+                        *width = msg.width.or(*width);
+                        *height = msg.height.or(*height);
+                    });
+                }
+                _ => {
+                    control.started |= msg.code == StatusCode::Ok;
+                    control.forward(msg).await?;
+                }
+            }
         }
         Message::Binary(d) => {
             info!(">>> {} sent {} bytes: {:?}", who, d.len(), d);
@@ -299,3 +1401,448 @@ async fn process_message(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tokio::{net::TcpStream, time::timeout};
+    use tokio_tungstenite::{
+        MaybeTlsStream, WebSocketStream, connect_async,
+        tungstenite::{Message as WsMessage, protocol::CloseFrame as WsCloseFrame},
+    };
+
+    use super::*;
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// 在空闲端口上启动 HTTP 服务，返回服务任务和地址
+    async fn start_server(extra: &[&str]) -> (JoinHandle<anyhow::Result<()>>, SocketAddr) {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let addr_arg = addr.to_string();
+        let mut argv = vec!["nyancat", "-H", "--http-addr", &addr_arg];
+        argv.extend_from_slice(extra);
+        let args = Args::parse_from(argv);
+        let context = ServerContext::new(&args).unwrap();
+        let server = tokio::spawn(run_http(args, context));
+
+        // 等待服务开始监听
+        for _ in 0..100 {
+            if TcpStream::connect(addr).await.is_ok() {
+                return (server, addr);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("server did not start on {addr}");
+    }
+
+    async fn connect(addr: SocketAddr) -> Client {
+        let (client, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        client
+    }
+
+    /// 读取下一条文本消息并解析为消息帧
+    async fn recv_frame(client: &mut Client) -> MessageFrame {
+        loop {
+            let msg = timeout(Duration::from_secs(2), client.next())
+                .await
+                .expect("timed out waiting for a message")
+                .expect("connection closed")
+                .unwrap();
+            if let WsMessage::Text(text) = msg {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    async fn send_frame(client: &mut Client, msg: &MessageFrame) {
+        let text = serde_json::to_string(msg).unwrap();
+        client.send(WsMessage::Text(text.into())).await.unwrap();
+    }
+
+    fn size_frame(width: u16, height: u16) -> MessageFrame {
+        MessageFrame {
+            code: StatusCode::Ok,
+            width: Some(width),
+            height: Some(height),
+            frame: None,
+            delay: None,
+            speed: None,
+            term: None,
+            ascii: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn websocket_streams_frames_after_init() {
+        let (server, addr) = start_server(&[]).await;
+        let mut client = connect(addr).await;
+
+        let init = recv_frame(&mut client).await;
+        assert_eq!(init.code, StatusCode::Init);
+        assert!(init.frame.is_none());
+
+        send_frame(&mut client, &size_frame(80, 24)).await;
+        for _ in 0..3 {
+            let msg = recv_frame(&mut client).await;
+            assert_eq!(msg.code, StatusCode::Ok);
+            assert!(msg.frame.is_some_and(|frame| !frame.is_empty()));
+        }
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn websocket_init_selects_client_glyphs() {
+        let (server, addr) = start_server(&[]).await;
+
+        // 不支持颜色和方块字符的客户端只收到 ASCII
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        let init = r#"{"code":1,"width":80,"height":24,"term":"vt100","ascii":true}"#;
+        client.send(WsMessage::Text(init.into())).await.unwrap();
+        let frame = recv_frame(&mut client).await.frame.unwrap();
+        assert!(frame.is_ascii(), "{frame:?}");
+        assert!(!frame.contains("\x1B[4"), "{frame:?}");
+
+        // 类型不符和无法识别的值被忽略，保持默认效果
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        let init = r#"{"code":1,"width":80,"height":24,"term":42,"ascii":"yes"}"#;
+        client.send(WsMessage::Text(init.into())).await.unwrap();
+        let frame = recv_frame(&mut client).await.frame.unwrap();
+        assert!(frame.contains("\x1B[48;5;"), "{frame:?}");
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn requested_theme_matches_startup_adjustments() {
+        let args = Args::parse_from(["nyancat", "--dim", "--ascii-only", "--scale", "2"]);
+        let theme = requested_theme(&args, "poptart").unwrap();
+        let expected = args.adjust_theme(Theme::by_name("poptart").unwrap());
+        assert!(std::ptr::eq(theme, expected));
+        assert!(theme.is_ascii_only());
+        assert_eq!(theme.width, Theme::by_name("poptart").unwrap().width * 2);
+        assert_ne!(theme.rgb('@'), Theme::by_name("poptart").unwrap().rgb('@'));
+        assert!(requested_theme(&args, "missing").is_none());
+
+        // 请求其他主题的连接与本地按同样设置渲染的帧一致
+        let flags = ["--dim", "--no-counter", "--scale", "2"];
+        let (server, addr) = start_server(&flags).await;
+        let (mut client, _) = connect_async(format!("ws://{addr}/ws?theme=poptart"))
+            .await
+            .unwrap();
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        send_frame(&mut client, &size_frame(40, 12)).await;
+        let frame = recv_frame(&mut client).await.frame.unwrap();
+
+        let args = Args::parse_from(std::iter::once("nyancat").chain(flags));
+        let theme = args.adjust_theme(Theme::by_name("poptart").unwrap());
+        let expected = Animator::new(&args)
+            .with_theme(theme.into())
+            .next_frame_string(40, 12, &args, ColorMode::default());
+        assert_eq!(frame, expected);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn websocket_resize_bursts_take_latest_size() {
+        // 队列只有一个位置，大小调整仍然不会阻塞接收任务
+        let (server, addr) = start_server(&["--ws-channel-capacity", "1"]).await;
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        send_frame(&mut client, &size_frame(80, 24)).await;
+        let rows = |msg: MessageFrame| msg.frame.unwrap().lines().count();
+        assert!(rows(recv_frame(&mut client).await) > 10);
+
+        for height in (5..=24).rev().cycle().take(200) {
+            send_frame(&mut client, &size_frame(80, height)).await;
+        }
+        let mut resized = false;
+        for _ in 0..10 {
+            if rows(recv_frame(&mut client).await) <= 5 {
+                resized = true;
+                break;
+            }
+        }
+        assert!(resized, "latest size was not applied");
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn shared_animation_sends_same_frames() {
+        let (server, addr) = start_server(&["--shared-animation", "--fps", "20"]).await;
+        let start = |mut client: Client| async move {
+            assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+            send_frame(&mut client, &size_frame(40, 12)).await;
+            client
+        };
+        let frames = |mut client: Client, count: usize| async move {
+            let mut frames = Vec::new();
+            for _ in 0..count {
+                frames.push(recv_frame(&mut client).await.frame.unwrap());
+            }
+            frames
+        };
+
+        let first = start(connect(addr).await).await;
+        let first = tokio::spawn(frames(first, 12));
+        // 后加入的连接从分组的当前帧开始，计数器也与先加入的连接一致
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let late = frames(start(connect(addr).await).await, 3).await;
+        let first = first.await.unwrap();
+
+        assert_ne!(late[0], first[0], "late client restarted the animation");
+        for frame in &late {
+            assert!(first.contains(frame), "late client got a frame of its own");
+        }
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn websocket_closes_without_size() {
+        let (server, addr) = start_server(&["--ws-init-timeout", "1"]).await;
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Error);
+        let close = timeout(Duration::from_secs(2), client.next())
+            .await
+            .expect("connection was not closed")
+            .expect("connection closed without a close frame")
+            .unwrap();
+        let WsMessage::Close(Some(frame)) = close else {
+            panic!("expected a close frame, got {close:?}");
+        };
+        assert_eq!(u16::from(frame.code), 1008);
+
+        // 及时上报大小的客户端不受影响
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        send_frame(&mut client, &size_frame(40, 12)).await;
+        for _ in 0..15 {
+            assert!(recv_frame(&mut client).await.frame.is_some());
+        }
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn websocket_closes_after_max_bytes() {
+        let (server, addr) = start_server(&["--max-bytes", "1"]).await;
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        send_frame(&mut client, &size_frame(80, 24)).await;
+        assert!(recv_frame(&mut client).await.frame.is_some());
+
+        // 第一帧就超过了限制，最多再收到一帧已经生成的帧，之后是正常的关闭帧
+        let mut frames = 1;
+        let close = loop {
+            let msg = timeout(Duration::from_secs(2), client.next())
+                .await
+                .expect("connection was not closed")
+                .expect("connection closed without a close frame")
+                .unwrap();
+            match msg {
+                WsMessage::Text(_) => frames += 1,
+                WsMessage::Close(frame) => break frame,
+                _ => {}
+            }
+        };
+        assert!(frames <= 2, "{frames} frames sent");
+        assert_eq!(
+            close.map(|frame| u16::from(frame.code)),
+            Some(close_code::NORMAL)
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn websocket_close_tears_down_connection() {
+        // 每个 IP 只允许一个连接，能够重新连接说明服务端已释放上一个连接
+        let (server, addr) = start_server(&["--max-connections-per-ip", "1"]).await;
+        let mut client = connect(addr).await;
+        assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+        send_frame(&mut client, &size_frame(40, 20)).await;
+        recv_frame(&mut client).await;
+
+        client
+            .close(Some(WsCloseFrame {
+                code: close_code::NORMAL.into(),
+                reason: "bye".into(),
+            }))
+            .await
+            .unwrap();
+        // 关闭握手完成后连接结束
+        let closed = timeout(Duration::from_secs(2), async {
+            while let Some(Ok(_)) = client.next().await {}
+        })
+        .await;
+        assert!(closed.is_ok(), "server did not close the connection");
+
+        let mut reconnected = None;
+        for _ in 0..50 {
+            if let Ok((mut client, _)) = connect_async(format!("ws://{addr}/ws")).await {
+                assert_eq!(recv_frame(&mut client).await.code, StatusCode::Init);
+                reconnected = Some(client);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(reconnected.is_some(), "connection slot was not released");
+
+        server.abort();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn uds_limits_by_forwarded_address() {
+        use tokio::net::UnixStream;
+        use tokio_tungstenite::{client_async, tungstenite::client::IntoClientRequest};
+
+        let path = std::env::temp_dir().join(format!("nyancat-{}.sock", std::process::id()));
+        let uds_arg = path.to_str().unwrap();
+        let argv = [
+            "nyancat",
+            "-H",
+            "--uds",
+            uds_arg,
+            "--max-connections-per-ip",
+            "1",
+        ];
+        let args = Args::parse_from(argv);
+        let context = ServerContext::new(&args).unwrap();
+        let server = tokio::spawn(run_http(args, context.clone()));
+        for _ in 0..100 {
+            if UnixStream::connect(&path).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let connect = async |forwarded: Option<&str>| {
+            let stream = UnixStream::connect(&path).await.unwrap();
+            let mut request = "ws://localhost/ws".into_client_request().unwrap();
+            if let Some(forwarded) = forwarded {
+                let value = forwarded.parse().unwrap();
+                request.headers_mut().insert("X-Forwarded-For", value);
+            }
+            client_async(request, stream)
+                .await
+                .map(|(client, _)| client)
+        };
+
+        // 没有 X-Forwarded-For 时无法区分客户端，不做限制
+        let first = connect(None).await.unwrap();
+        let second = connect(None).await.unwrap();
+        // 按代理追加在末尾的地址限制，客户端伪造的前面的地址不影响
+        let proxied = connect(Some("203.0.113.9, 198.51.100.1")).await.unwrap();
+        assert!(connect(Some("198.51.100.1")).await.is_err());
+        assert!(connect(Some("198.51.100.2")).await.is_ok());
+
+        drop((first, second, proxied));
+        // 正常关闭后删除套接字文件
+        context.shutdown();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    /// 发送 GET 请求，返回响应头部分
+    async fn get_head(addr: SocketAddr, path: &str, headers: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request =
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{headers}Connection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        response
+            .split_once("\r\n\r\n")
+            .unwrap()
+            .0
+            .to_ascii_lowercase()
+    }
+
+    #[tokio::test]
+    async fn token_is_not_logged() {
+        use crate::conn::tests::Recorder;
+
+        let recorder = Recorder::default();
+        let _guard = recorder.install();
+        let (server, addr) = start_server(&["--http-token", "s3cret"]).await;
+        let (client, _) = connect_async(format!("ws://{addr}/ws?theme=classic&token=s3cret"))
+            .await
+            .unwrap();
+        drop(client);
+        get_head(addr, "/missing?token=s3cret", "").await;
+        server.abort();
+
+        let logs = recorder.own();
+        assert!(
+            logs.iter()
+                .any(|fields| fields.contains("/ws?theme=classic"))
+        );
+        assert!(logs.iter().any(|fields| fields.contains("/missing")));
+        assert!(
+            logs.iter().all(|fields| !fields.contains("s3cret")),
+            "{logs:#?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cors_allows_listed_origins() {
+        let (server, addr) = start_server(&["--cors-origin", "https://example.com/"]).await;
+        let head = get_head(addr, "/", "Origin: https://example.com\r\n").await;
+        assert!(
+            head.contains("access-control-allow-origin: https://example.com\r\n"),
+            "{head}"
+        );
+        let head = get_head(addr, "/", "Origin: https://other.example\r\n").await;
+        assert!(!head.contains("access-control-allow-origin"), "{head}");
+        server.abort();
+
+        let (server, addr) = start_server(&["--cors-origin", "*"]).await;
+        let head = get_head(addr, "/", "Origin: https://other.example\r\n").await;
+        assert!(head.contains("access-control-allow-origin: *"), "{head}");
+        server.abort();
+
+        // 未设置时不启用 CORS
+        let (server, addr) = start_server(&[]).await;
+        let head = get_head(addr, "/", "Origin: https://example.com\r\n").await;
+        assert!(!head.contains("access-control-allow-origin"), "{head}");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn info_describes_animation() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (server, addr) = start_server(&["--fps", "12"]).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /info HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+        let info: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(info["version"], INFO_VERSION);
+        assert_eq!(info["frames"], THEMES[0].frames.len());
+        assert_eq!(info["width"], THEMES[0].width);
+        assert_eq!(info["fps"], 12);
+        assert_eq!(info["themes"].as_array().unwrap().len(), THEMES.len());
+        assert_eq!(info["protocols"][0], "nyancat.v1");
+
+        server.abort();
+    }
+}
@@ -0,0 +1,6 @@
+//! 帧渲染相关的模块
+//!
+//! 程序本身是二进制目标，渲染代码单独放在库中，基准测试可以直接调用。
+
+pub mod animation;
+pub mod palette;
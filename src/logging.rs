@@ -6,22 +6,39 @@ use tracing_subscriber::{
     layer::SubscriberExt,
 };
 
+/// 日志过滤环境变量，格式同 `RUST_LOG`
+const LOG_ENV: &str = "NYANCAT_LOG";
+
+/// 根据 `--quiet` 和 `-v` 次数计算日志级别
+pub fn level_from_flags(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::WARN,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    }
+}
+
 /// 初始化 Logger
 ///
 /// 从配置文件中读取 log 级别，同时读取日志文件存储路径。
 /// 无论是否设置了日志文件路径，都会将日志输出到控制台。
 ///
 /// 配置文件路径只能文件夹，日志文件将按天分割。
-pub fn init_logger() -> anyhow::Result<()> {
+///
+/// `level` 由 `--quiet` / `-v` 决定，设置了 `NYANCAT_LOG` 环境变量时以环境变量为准。
+pub fn init_logger(level: LevelFilter) -> anyhow::Result<()> {
     let formatting_layer = fmt::layer()
         // .pretty()
         // .with_thread_ids(true)
         .with_target(false)
         .with_writer(std::io::stdout);
 
+    let from_env = std::env::var_os(LOG_ENV).is_some_and(|value| !value.is_empty());
+    let default_level = if from_env { LevelFilter::INFO } else { level };
     let env_layer = EnvFilter::builder()
-        .with_default_directive(LevelFilter::INFO.into())
-        .with_env_var("NYANCAT_LOG")
+        .with_default_directive(default_level.into())
+        .with_env_var(LOG_ENV)
         .from_env_lossy();
 
     let collector = tracing_subscriber::registry()
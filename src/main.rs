@@ -1,30 +1,174 @@
+use std::{io::IsTerminal, sync::Arc};
+
 use anyhow::Context;
-use clap::Parser;
+use nyancat::{animation, palette};
 
-use crate::cli::Args;
+use crate::animation::{ANIMATIONS, THEMES};
 
-mod animation;
+mod animation_file;
+mod animator;
+#[cfg(feature = "http")]
+mod broadcast;
 mod cli;
+mod config;
+mod conn;
+mod dump;
+#[cfg(feature = "gif")]
+mod export;
+#[cfg(feature = "graphics")]
+mod graphics;
 #[cfg(feature = "http")]
 mod http;
 mod logging;
+mod markup;
+mod metrics;
+mod peer;
+mod plain;
+#[cfg(any(feature = "png", feature = "graphics"))]
+mod png;
+mod probe;
+#[cfg(any(feature = "gif", feature = "graphics"))]
+mod raster;
+mod record;
+mod reload;
+mod server;
 mod standalone;
 mod telnet;
+#[cfg(feature = "tls")]
+mod tls;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let mut args = config::load_args()?;
+
+    logging::init_logger(logging::level_from_flags(args.quiet, args.verbose))
+        .with_context(|| "init logger failed")?;
+
+    // 在加载帧文件和调色板之前输出，只反映参数本身
+    if args.print_config {
+        cli::print_config(&args)?;
+        return Ok(());
+    }
+
+    if args.truecolor {
+        args.color = Some(animation::ColorMode::TrueColor);
+    }
+    if args.no_color {
+        args.color = Some(animation::ColorMode::None);
+    }
+    args.theme = args.theme.with_animation(args.animation);
+    if let Some(path) = &args.frames_file {
+        args.theme = markup::load(path)?.into_static();
+    }
+    if let Some(path) = &args.animation_file {
+        args.theme = animation_file::load(path)?.into_static();
+    }
+    if let Some(dir) = &args.frames_dir {
+        args.theme = markup::load_dir(dir)?.into_static();
+    }
+    if let Some(path) = &args.palette {
+        let palette = palette::CustomPalette::load(path)?;
+        args.theme = args.theme.with_palette(Arc::new(palette)).into_static();
+    }
+    args.theme = args.adjust_theme(args.theme);
+    args.check_frame_range()?;
+
+    if args.list_themes {
+        for theme in THEMES {
+            println!("{:<12}{}", theme.name, theme.description);
+        }
+        return Ok(());
+    }
+
+    if args.list_animations {
+        for animation in ANIMATIONS {
+            println!("{:<12}{}", animation.name, animation.description);
+        }
+        return Ok(());
+    }
+
+    if args.detect_color {
+        probe::report(&args)?;
+        return Ok(());
+    }
+
+    if args.preview_themes {
+        let mode = args.color.unwrap_or_else(animation::ColorMode::detect);
+        let (width, _) = crossterm::terminal::size().unwrap_or((args.default_width, 0));
+        for line in animation::theme_preview(THEMES, width, mode) {
+            println!("{line}");
+        }
+        return Ok(());
+    }
 
-    logging::init_logger().with_context(|| "init logger failed")?;
+    if args.preview_palette {
+        for line in args.theme.palette_preview() {
+            println!("{line}");
+        }
+        return Ok(());
+    }
+
+    if args.glyph_test {
+        let mode = args.color.unwrap_or_else(animation::ColorMode::detect);
+        for line in args.theme.glyph_test(mode) {
+            println!("{line}");
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "gif")]
+    if let Some(path) = &args.export_gif {
+        export::export_gif(&args, path)?;
+        return Ok(());
+    }
+
+    // 图片只能显示在独立模式的终端中，其他模式按块绘制
+    if let Some(renderer) = args.render.filter(|renderer| renderer.is_image()) {
+        if !cfg!(feature = "graphics") {
+            anyhow::bail!(
+                "--render {} requires the `graphics` feature",
+                renderer.name()
+            );
+        }
+        #[cfg(feature = "http")]
+        let server = args.telnet || args.http;
+        #[cfg(not(feature = "http"))]
+        let server = args.telnet;
+        if server || args.plain || !std::io::stdout().is_terminal() {
+            tracing::warn!(
+                "--render {} only works in standalone mode, drawing blocks instead",
+                renderer.name()
+            );
+        }
+    }
+
+    // 同时开启时在同一进程中运行两个服务，任一服务退出时另一个随之关闭，
+    // 等待两个服务都完成清理后返回先出现的错误
+    #[cfg(feature = "http")]
+    if args.telnet && args.http {
+        let context = server::ServerContext::new(&args)?;
+        let (telnet, http) = tokio::join!(
+            context.run(telnet::run_telnet_server(&args, context.clone())),
+            context.run(http::run_http(args.clone(), context.clone())),
+        );
+        telnet.and(http)?;
+        return Ok(());
+    }
 
     if args.telnet {
-        telnet::run_telnet_server(&args).await?;
+        telnet::run_telnet_server(&args, server::ServerContext::new(&args)?).await?;
         return Ok(());
     }
 
     #[cfg(feature = "http")]
     if args.http {
-        http::run_http(args).await?;
+        let context = server::ServerContext::new(&args)?;
+        http::run_http(args, context).await?;
+        return Ok(());
+    }
+
+    if args.plain || !std::io::stdout().is_terminal() {
+        plain::run_plain(&args).await?;
         return Ok(());
     }
 
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, bail};
+use tracing::warn;
+
+use crate::{
+    animation::{ColorMode, Frames, Theme, normalize_frames, validate_frames},
+    palette::{CustomPalette, PaletteColor},
+};
+
+/// 帧文件中分隔帧的行
+const FRAME_SEPARATOR: &str = "---";
+
+/// 为带颜色的单元格分配的字符从 Unicode 私用区开始
+const GLYPH_BASE: u32 = 0xE000;
+
+/// 带颜色的单元格
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ColoredCell {
+    pub ch: char,
+    /// `None` 表示按原样输出字符
+    pub color: Option<PaletteColor>,
+}
+
+impl ColoredCell {
+    /// 渲染为指定颜色模式下的单元格，占两列
+    pub fn render(&self, mode: ColorMode) -> String {
+        match self.color {
+            Some(color) => color.render(self.ch, mode),
+            None => format!("{0}{0}", self.ch),
+        }
+    }
+}
+
+/// 解析一行标记，返回该行的单元格
+///
+/// `[#RRGGBB]` 或 `[<xterm 256 色索引>]` 设置后续字符的颜色，`[/]` 清除颜色，
+/// `[[` 表示字面的 `[`。颜色只在当前行内有效，格式错误的标记按原样输出并给出警告。
+fn parse_line(line: &str, line_no: usize, path: &Path) -> Vec<ColoredCell> {
+    let mut cells = Vec::new();
+    let mut color = None;
+    let mut rest = line;
+
+    while let Some(ch) = rest.chars().next() {
+        rest = &rest[ch.len_utf8()..];
+        if ch != '[' {
+            cells.push(ColoredCell { ch, color });
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('[') {
+            rest = after;
+            cells.push(ColoredCell { ch, color });
+            continue;
+        }
+
+        let tag = rest.split_once(']').and_then(|(tag, after)| match tag {
+            "/" => Some((None, after)),
+            _ => PaletteColor::parse(tag).map(|color| (Some(color), after)),
+        });
+        match tag {
+            Some((next, after)) => {
+                color = next;
+                rest = after;
+            }
+            None => {
+                warn!(
+                    "Malformed markup on line {} in frames file {}, keeping `[` as text",
+                    line_no + 1,
+                    path.display()
+                );
+                cells.push(ColoredCell { ch, color });
+            }
+        }
+    }
+
+    cells
+}
+
+/// 解析帧文件，帧之间用单独一行 `---` 分隔，空帧会被忽略
+pub fn parse(content: &str, path: &Path) -> Vec<Vec<Vec<ColoredCell>>> {
+    let mut frames = Vec::new();
+    let mut frame = Vec::new();
+
+    for (line_no, line) in content.lines().enumerate() {
+        if line.trim_end() == FRAME_SEPARATOR {
+            if !frame.is_empty() {
+                frames.push(std::mem::take(&mut frame));
+            }
+            continue;
+        }
+        frame.push(parse_line(line, line_no, path));
+    }
+    if !frame.is_empty() {
+        frames.push(frame);
+    }
+
+    frames
+}
+
+/// 读取帧文件并创建主题
+pub fn load(path: &Path) -> anyhow::Result<Theme> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("read frames file {} failed", path.display()))?;
+    let frames = parse(&content, path);
+    if frames.is_empty() {
+        bail!("frames file {} contains no frames", path.display());
+    }
+    build_theme("file", frames)
+}
+
+/// 读取目录中的帧文件并创建主题，每个文件是一帧，按文件名排序
+///
+/// 以 `.` 开头的隐藏文件和以 `~` 结尾的备份文件会被忽略，避免编辑器的临时文件混入动画。
+/// 文件中的 `---` 不再分隔帧，空文件视为错误。
+pub fn load_dir(dir: &Path) -> anyhow::Result<Theme> {
+    let frames = frame_files(dir)?
+        .iter()
+        .map(|path| {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("read frame file {} failed", path.display()))?;
+            let frame = content
+                .lines()
+                .enumerate()
+                .map(|(line_no, line)| parse_line(line, line_no, path))
+                .collect::<Vec<_>>();
+            if frame.is_empty() {
+                bail!("frame file {} is empty", path.display());
+            }
+            Ok(frame)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if frames.is_empty() {
+        bail!("frames directory {} contains no frames", dir.display());
+    }
+    build_theme("dir", frames)
+}
+
+/// 目录中的帧文件，按文件名排序
+pub fn frame_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("read frames directory {} failed", dir.display()))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry =
+            entry.with_context(|| format!("read frames directory {} failed", dir.display()))?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name.ends_with('~') {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// 从解析后的帧创建主题
+///
+/// 每种带颜色的单元格映射为一个私用区字符并写入调色板，
+/// 这样可以直接复用内置主题的渲染、翻转和裁剪逻辑。
+fn build_theme(name: &'static str, frames: Vec<Vec<Vec<ColoredCell>>>) -> anyhow::Result<Theme> {
+    let mut palette = CustomPalette::default();
+    let mut glyphs: HashMap<ColoredCell, char> = HashMap::new();
+    let mut glyph_for = |cell: ColoredCell| -> anyhow::Result<char> {
+        let Some(color) = cell.color else {
+            return Ok(cell.ch);
+        };
+        if let Some(&glyph) = glyphs.get(&cell) {
+            return Ok(glyph);
+        }
+        let glyph = u32::try_from(glyphs.len())
+            .ok()
+            .and_then(|offset| char::from_u32(GLYPH_BASE + offset))
+            .filter(|glyph| *glyph <= '\u{F8FF}')
+            .context("too many distinct colored cells in frames")?;
+        let label = match color {
+            PaletteColor::Ansi(index) => format!("{index}"),
+            PaletteColor::Rgb(r, g, b) => format!("#{r:02x}{g:02x}{b:02x}"),
+        };
+        palette.insert(glyph, label, color, |mode| cell.render(mode));
+        glyphs.insert(cell, glyph);
+        Ok(glyph)
+    };
+
+    let mut frames = frames
+        .into_iter()
+        .map(|frame| {
+            frame
+                .into_iter()
+                .map(|row| row.into_iter().map(&mut glyph_for).collect())
+                .collect::<anyhow::Result<Vec<String>>>()
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // 手绘的帧行长常常不一致，补齐后再交给渲染
+    normalize_frames(&mut frames);
+    validate_frames(&frames)?;
+
+    Ok(Theme::from_frames(
+        name,
+        Frames::new(frames),
+        Arc::new(palette),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_dir_is_sorted_and_rejects_empty_frames() {
+        let dir = std::env::temp_dir().join(format!("nyancat-frames-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("02.txt"), "cd\nef").unwrap();
+        fs::write(dir.join("01.txt"), "[#ff0000]ab").unwrap();
+        fs::write(dir.join(".01.txt.swp"), "").unwrap();
+
+        let theme = load_dir(&dir).unwrap();
+        assert_eq!(theme.frames.len(), 2);
+        assert_eq!((theme.width, theme.height), (2, 2));
+        assert_eq!(theme.frames.get(1), ["cd", "ef"]);
+        assert_eq!(
+            theme.render_color(
+                theme.frames.get(0).row(0).chars().next().unwrap(),
+                ColorMode::None
+            ),
+            "aa"
+        );
+
+        // 编辑过程中的空文件不会替换已加载的帧
+        fs::write(dir.join("03.txt"), "").unwrap();
+        let err = load_dir(&dir).unwrap_err();
+        assert!(err.to_string().contains("03.txt"), "{err:#}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
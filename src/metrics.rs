@@ -0,0 +1,115 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tracing::warn;
+
+/// 超过该时间的单帧发送会输出警告
+const SLOW_SEND: Duration = Duration::from_millis(500);
+
+/// 直方图桶的上界（秒），最后隐含 `+Inf`
+const BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// 发送帧的连接类型
+#[derive(Clone, Copy, Debug)]
+pub enum Transport {
+    Telnet,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    WebSocket,
+}
+
+impl Transport {
+    #[cfg(feature = "http")]
+    const ALL: [Transport; 2] = [Transport::Telnet, Transport::WebSocket];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Transport::Telnet => "telnet",
+            Transport::WebSocket => "websocket",
+        }
+    }
+}
+
+/// 无锁的累计直方图
+struct Histogram {
+    /// 每个桶的计数，不累计，输出时再累加
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    /// 所有观测值之和（微秒）
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let index = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+static TELNET_SEND: Histogram = Histogram::new();
+static WEBSOCKET_SEND: Histogram = Histogram::new();
+
+fn histogram(transport: Transport) -> &'static Histogram {
+    match transport {
+        Transport::Telnet => &TELNET_SEND,
+        Transport::WebSocket => &WEBSOCKET_SEND,
+    }
+}
+
+/// 记录一帧的发送耗时，只应包含发送本身，不包含帧间隔
+pub fn observe_send(transport: Transport, elapsed: Duration) {
+    histogram(transport).observe(elapsed);
+    if elapsed >= SLOW_SEND {
+        warn!(
+            "Sending a {} frame took {}ms, client may be too slow",
+            transport.label(),
+            elapsed.as_millis()
+        );
+    }
+}
+
+/// 以 Prometheus 文本格式输出所有指标
+#[cfg(feature = "http")]
+pub fn render() -> String {
+    use std::fmt::Write;
+
+    let name = "nyancat_frame_send_seconds";
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP {name} Time spent sending one frame to a client."
+    );
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for transport in Transport::ALL {
+        let histogram = histogram(transport);
+        let label = transport.label();
+        let mut cumulative = 0;
+        for (index, bucket) in histogram.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = BUCKETS
+                .get(index)
+                .map_or_else(|| "+Inf".to_string(), f64::to_string);
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{transport=\"{label}\",le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{name}_sum{{transport=\"{label}\"}} {sum}");
+        let _ = writeln!(out, "{name}_count{{transport=\"{label}\"}} {cumulative}");
+    }
+    out
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use tracing::warn;
+
+use crate::animation::{ColorMode, nearest_ansi8, nearest_ansi16, rgb_to_xterm, xterm_to_rgb};
+
+/// 调色板文件中的颜色
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PaletteColor {
+    /// xterm 256 色索引
+    Ansi(u8),
+    /// `#RRGGBB`
+    Rgb(u8, u8, u8),
+}
+
+impl PaletteColor {
+    /// 解析 `#RRGGBB` 或 xterm 256 色索引
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Some(hex) = value.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Self::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        value.parse().ok().map(Self::Ansi)
+    }
+
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Self::Ansi(color) => xterm_to_rgb(color),
+            Self::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// 渲染为指定颜色模式下的单元格，无颜色模式下输出 `glyph`
+    pub fn render(self, glyph: char, mode: ColorMode) -> String {
+        match mode {
+            ColorMode::TrueColor => {
+                let (r, g, b) = self.rgb();
+                format!("\x1B[48;2;{r};{g};{b}m  \x1B[0m")
+            }
+            ColorMode::Color256 => {
+                let color = match self {
+                    Self::Ansi(color) => color,
+                    Self::Rgb(r, g, b) => rgb_to_xterm((r, g, b)),
+                };
+                format!("\x1B[48;5;{color}m  \x1B[0m")
+            }
+            ColorMode::Color16 => {
+                let ansi = nearest_ansi16(self.rgb());
+                let sgr = if ansi < 8 { 40 + ansi } else { 100 + ansi - 8 };
+                format!("\x1B[{sgr}m  \x1B[0m")
+            }
+            ColorMode::Color8 => format!("\x1B[{}m  \x1B[0m", 40 + nearest_ansi8(self.rgb())),
+            ColorMode::None => format!("{glyph}{glyph}"),
+        }
+    }
+}
+
+/// 单元格与内置主题的单元格一样是静态字符串，相同的内容只保存一份
+///
+/// 重新加载帧目录时调色板会重新创建，单元格的数量只取决于出现过的不同颜色。
+fn intern_cell(cell: String) -> &'static str {
+    static CELLS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut cells = CELLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(&interned) = cells.get(cell.as_str()) {
+        return interned;
+    }
+    let interned: &'static str = cell.leak();
+    cells.insert(interned);
+    interned
+}
+
+/// 从文件加载的字符到颜色的映射
+///
+/// 文件每行一条 `char = #RRGGBB` 或 `char = <xterm 256 色索引>`，
+/// 空行和 `#` 开头的行会被忽略。
+#[derive(Debug, Default)]
+pub struct CustomPalette {
+    /// 每个字符在各颜色模式下的单元格，顺序与 [`ColorMode::ALL`] 一致
+    cells: HashMap<char, [&'static str; 5]>,
+    /// 每个字符的 RGB 颜色，用于导出图片和调整亮度
+    colors: HashMap<char, (u8, u8, u8)>,
+    /// 文件中的顺序，用于预览
+    order: Vec<(char, String)>,
+}
+
+impl CustomPalette {
+    /// 读取并解析调色板文件，格式错误的行会被跳过并输出警告
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("read palette file {} failed", path.display()))?;
+        Ok(Self::parse(&content, path))
+    }
+
+    /// 解析调色板内容，`path` 只用于警告信息
+    pub fn parse(content: &str, path: &Path) -> Self {
+        let mut palette = Self::default();
+
+        for (line_no, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let entry = line.split_once('=').and_then(|(glyph, value)| {
+                let mut chars = glyph.trim().chars();
+                let glyph = chars.next().filter(|_| chars.next().is_none())?;
+                let value = value.trim();
+                Some((glyph, value, PaletteColor::parse(value)?))
+            });
+            let Some((glyph, value, color)) = entry else {
+                warn!(
+                    "Ignoring malformed line {} in palette {}: `{line}`",
+                    line_no + 1,
+                    path.display()
+                );
+                continue;
+            };
+
+            let render = |mode| color.render(glyph, mode);
+            if palette.insert(glyph, value.to_string(), color, render) {
+                warn!(
+                    "Duplicate glyph `{glyph}` on line {} in palette {}, using the last one",
+                    line_no + 1,
+                    path.display()
+                );
+            }
+        }
+
+        palette
+    }
+
+    /// 添加字符的颜色，`label` 用于预览，`render` 生成各颜色模式下的单元格
+    ///
+    /// 字符已存在时覆盖并返回 `true`。
+    pub fn insert(
+        &mut self,
+        glyph: char,
+        label: String,
+        color: PaletteColor,
+        render: impl Fn(ColorMode) -> String,
+    ) -> bool {
+        self.colors.insert(glyph, color.rgb());
+        let rendered = ColorMode::ALL.map(|mode| intern_cell(render(mode)));
+        let replaced = self.cells.insert(glyph, rendered).is_some();
+        if replaced {
+            self.order.retain(|(existing, _)| *existing != glyph);
+        }
+        self.order.push((glyph, label));
+        replaced
+    }
+
+    /// 字符在指定颜色模式下的单元格，不在映射中时返回 `None`
+    pub fn get(&self, glyph: char, mode: ColorMode) -> Option<&'static str> {
+        let index = ColorMode::ALL.iter().position(|m| *m == mode)?;
+        self.cells.get(&glyph).map(|cells| cells[index])
+    }
+
+    /// 字符的 RGB 颜色，不在映射中时返回 `None`
+    pub fn rgb(&self, glyph: char) -> Option<(u8, u8, u8)> {
+        self.colors.get(&glyph).copied()
+    }
+
+    /// 按文件顺序返回字符和原始颜色值
+    pub fn entries(&self) -> impl Iterator<Item = (char, &str)> {
+        self.order
+            .iter()
+            .map(|(glyph, value)| (*glyph, value.as_str()))
+    }
+}
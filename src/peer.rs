@@ -0,0 +1,49 @@
+use std::{net::IpAddr, time::Duration};
+
+use tokio::time::timeout;
+use tracing::{Instrument, debug, info};
+
+/// 反向解析的超时时间，超时后日志中只有 IP
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 反向解析对端地址的主机名，失败或超时返回 `None`
+///
+/// 系统解析函数是阻塞的，放到阻塞线程中执行；超时后不再等待，但线程会继续运行到解析函数返回。
+pub async fn lookup_hostname(ip: IpAddr) -> Option<String> {
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+    match timeout(LOOKUP_TIMEOUT, lookup).await {
+        Ok(Ok(Ok(host))) => Some(host),
+        Ok(Ok(Err(e))) => {
+            debug!("Reverse lookup of {ip} failed: {e}");
+            None
+        }
+        Ok(Err(e)) => {
+            debug!("Reverse lookup of {ip} panicked: {e}");
+            None
+        }
+        Err(_) => {
+            debug!(
+                "Reverse lookup of {ip} timed out after {}s",
+                LOOKUP_TIMEOUT.as_secs()
+            );
+            None
+        }
+    }
+}
+
+/// 开启 `--resolve-hostnames` 时在后台解析对端的主机名并记录日志
+///
+/// 解析在独立的任务中进行，不会推迟握手和动画，日志沿用当前连接的 span。
+pub fn log_hostname(ip: IpAddr, enabled: bool) {
+    if !enabled {
+        return;
+    }
+    tokio::spawn(
+        async move {
+            if let Some(host) = lookup_hostname(ip).await {
+                info!("Peer {ip} resolves to {host}");
+            }
+        }
+        .in_current_span(),
+    );
+}
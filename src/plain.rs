@@ -0,0 +1,67 @@
+use std::io::{self, IsTerminal, Write};
+
+use crate::{
+    animation::{ColorMode, usable_size},
+    animator::{Animator, render_options},
+    cli::Args,
+    dump::{OutputDump, Tee},
+    reload,
+};
+
+/// 纯文本输出模式
+///
+/// 每帧裁剪后的内容按行写入标准输出，帧之间用换行分隔，不清屏、不移动光标，
+/// 也不进入 raw 模式，适合通过管道交给其他程序处理。
+pub async fn run_plain(args: &Args) -> anyhow::Result<()> {
+    // 管道另一端通常不是终端，转义序列只会变成乱码，改用 ASCII 字符画
+    let color_mode = args.color.unwrap_or_else(|| {
+        if io::stdout().is_terminal() {
+            ColorMode::detect()
+        } else {
+            ColorMode::None
+        }
+    });
+    let options = {
+        let mut options = render_options(args, color_mode);
+        options.no_clear = true;
+        options.plain_counter = args.plain_counter(color_mode, io::stdout().is_terminal());
+        options
+    };
+    // 输出到管道时没有终端大小，使用默认大小
+    let (width, height) = usable_size(
+        crossterm::terminal::size().unwrap_or((0, 0)),
+        (args.default_width, args.default_height),
+    );
+
+    let mut animator = Animator::new(args)
+        .with_frame_limits(args.frames, args.loop_count)
+        .with_theme_updates(reload::watch_frames_dir(args));
+    let dump = OutputDump::from_path(args.dump_output.as_deref())?;
+    let mut stdout = Tee::new(io::stdout(), dump);
+    let mut frame_data = String::new();
+
+    loop {
+        frame_data.clear();
+        animator.write_frame_with(&mut frame_data, width, height, options);
+        if !args.no_counter {
+            // 计数器后没有换行，补上以免与下一帧连在一起
+            frame_data.push('\n');
+        }
+
+        match stdout
+            .write_all(frame_data.as_bytes())
+            .and_then(|()| stdout.flush())
+        {
+            Ok(()) => {}
+            // 管道另一端关闭，正常结束
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        if !animator.tick().await {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::io::Write;
+
+use flate2::{Compression, write::ZlibEncoder};
+
+/// PNG 文件签名
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// 编码 8 位调色板索引的 PNG
+///
+/// `palette` 为扁平的 RGB 数组，最多 256 种颜色，`pixels` 按行排列，每个像素一个索引。
+pub fn encode_indexed(
+    width: u32,
+    height: u32,
+    palette: &[u8],
+    pixels: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 位深 8，颜色类型 3（调色板），默认压缩、过滤，无隔行
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"PLTE", palette);
+
+    // 每行前加过滤类型 0（无过滤）
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks(width as usize) {
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    write_chunk(&mut out, b"IDAT", &encoder.finish()?);
+    write_chunk(&mut out, b"IEND", &[]);
+
+    Ok(out)
+}
+
+/// 写入数据块：长度、类型、数据和类型加数据的 CRC
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32fast::hash(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
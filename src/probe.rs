@@ -0,0 +1,140 @@
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    sync::mpsc,
+    time::Duration,
+};
+
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+use crate::{animation::ColorMode, cli::Args};
+
+/// 等待终端回复查询的时间，不支持查询的终端不会回复
+const QUERY_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// 设置一个 24 位背景色，再用 DECRQSS 查询当前的 SGR，最后恢复
+///
+/// 支持 24 位颜色的终端会原样回复该颜色，不支持的终端会回复降级后的颜色或不回复。
+const TRUECOLOR_QUERY: &str = "\x1B[48;2;1;2;3m\x1BP$qm\x1B\\\x1B[0m";
+
+/// 回复的最大长度，超过时放弃解析
+const MAX_REPLY: usize = 256;
+
+/// `--detect-color` 的诊断输出
+///
+/// 列出检测用到的环境变量和终端查询的结果，以及最终使用的颜色模式和原因。
+pub fn report(args: &Args) -> anyhow::Result<()> {
+    let colorterm = std::env::var("COLORTERM").ok();
+    let term = std::env::var("TERM").ok();
+    let (detected, reason) = ColorMode::detect_from(colorterm.as_deref(), term.as_deref());
+
+    let show = |value: &Option<String>| match value.as_deref() {
+        Some("") | None => "(not set)".to_string(),
+        Some(value) => value.to_string(),
+    };
+    println!("{:<12}{}", "COLORTERM", show(&colorterm));
+    println!("{:<12}{}", "TERM", show(&term));
+
+    let truecolor = query_truecolor();
+    let query = match truecolor {
+        Some(true) => "terminal accepts 24-bit colors",
+        Some(false) => "terminal does not keep 24-bit colors",
+        None if !io::stdin().is_terminal() || !io::stdout().is_terminal() => {
+            "skipped, not running in a terminal"
+        }
+        None => "no reply, the terminal does not support the query",
+    };
+    println!("{:<12}{query}", "Query");
+
+    match args.color {
+        Some(mode) => println!(
+            "{:<12}{} (set by --color, detection would pick {}: {reason})",
+            "Color mode",
+            mode.name(),
+            detected.name()
+        ),
+        None => println!("{:<12}{} ({reason})", "Color mode", detected.name()),
+    }
+
+    let mode = args.color.unwrap_or(detected);
+    if truecolor == Some(true) && mode != ColorMode::TrueColor {
+        println!();
+        println!(
+            "The terminal supports 24-bit colors, pass `--color truecolor` or set COLORTERM=truecolor to use them."
+        );
+    }
+    Ok(())
+}
+
+/// 查询终端是否支持 24 位颜色，不在终端中运行或没有回复时返回 `None`
+fn query_truecolor() -> Option<bool> {
+    let mut stdout = io::stdout();
+    if !io::stdin().is_terminal() || !stdout.is_terminal() {
+        return None;
+    }
+
+    // raw 模式下回复不会回显，也不需要等待换行
+    enable_raw_mode().ok()?;
+    let reply = stdout
+        .write_all(TRUECOLOR_QUERY.as_bytes())
+        .and_then(|()| stdout.flush())
+        .ok()
+        .and_then(|()| read_reply(QUERY_TIMEOUT));
+    let _ = disable_raw_mode();
+
+    reply.map(|reply| parse_reply(&reply))
+}
+
+/// 读取以 ST 结尾的回复，超时返回 `None`
+///
+/// 标准输入的读取无法取消，放到单独的线程中；超时后线程会一直阻塞，进程随后就会退出。
+fn read_reply(timeout: Duration) -> Option<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        while reply.len() < MAX_REPLY && io::stdin().read_exact(&mut byte).is_ok() {
+            reply.push(byte[0]);
+            if reply.ends_with(b"\x1B\\") {
+                let _ = tx.send(reply);
+                return;
+            }
+        }
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+/// 解析 DECRQSS 回复，回复中保留了查询时设置的颜色说明支持 24 位颜色
+///
+/// 不同终端的参数格式不同，如 `48;2;1;2;3`、`48:2::1:2:3` 或带颜色空间的 `48:2:0:1:2:3`。
+fn parse_reply(reply: &[u8]) -> bool {
+    let Some(start) = reply.windows(3).position(|window| window == b"1$r") else {
+        return false;
+    };
+    let params = String::from_utf8_lossy(&reply[start + 3..]);
+    let numbers = params
+        .split([';', ':'])
+        .map(|part| part.trim_end_matches(|c: char| !c.is_ascii_digit()))
+        .collect::<Vec<_>>();
+    numbers.windows(2).enumerate().any(|(index, window)| {
+        let rest = &numbers[index + 2..];
+        // 颜色空间参数可以省略
+        let rgb = ["1", "2", "3"];
+        window == ["48", "2"]
+            && (rest.starts_with(&rgb) || rest.get(1..).is_some_and(|rest| rest.starts_with(&rgb)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truecolor_reply_is_recognized() {
+        assert!(parse_reply(b"\x1BP1$r0;48;2;1;2;3m\x1B\\"));
+        assert!(parse_reply(b"\x1BP1$r48:2::1:2:3m\x1B\\"));
+        assert!(parse_reply(b"\x1BP1$r48:2:0:1:2:3m\x1B\\"));
+        // 降级为 256 色或查询无效
+        assert!(!parse_reply(b"\x1BP1$r0;48;5;16m\x1B\\"));
+        assert!(!parse_reply(b"\x1BP0$r\x1B\\"));
+    }
+}
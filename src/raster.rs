@@ -0,0 +1,128 @@
+use std::ops::Range;
+
+use anyhow::bail;
+
+use crate::animation::{Flip, ThemeRef, rainbow_glyph};
+
+/// 将主题的帧栅格化为调色板索引像素，GIF 导出、PNG 接口和终端图片共用
+///
+/// 每个字符渲染为 `cell_width` x `cell_height` 像素的色块，
+/// 颜色与终端中的颜色一致，调色板中没有的字符渲染为黑色。
+pub struct Rasterizer {
+    theme: ThemeRef,
+    cell_width: usize,
+    cell_height: usize,
+    flip: Flip,
+    /// 帧中出现的颜色，索引 0 保留给未知字符
+    colors: Vec<(u8, u8, u8)>,
+    glyph_index: Vec<(char, u8)>,
+}
+
+impl Rasterizer {
+    /// 收集主题所有帧中出现的颜色，颜色超过 256 种时返回错误
+    pub fn new(theme: ThemeRef, cell_width: usize, cell_height: usize) -> anyhow::Result<Self> {
+        let mut colors = vec![(0, 0, 0)];
+        let mut glyph_index = Vec::<(char, u8)>::new();
+        for frame in theme.frames.iter() {
+            for row in frame.rows() {
+                for glyph in row.chars() {
+                    if glyph_index.iter().any(|(known, _)| *known == glyph) {
+                        continue;
+                    }
+                    let index = match theme.rgb(glyph) {
+                        Some(rgb) => match colors.iter().position(|color| *color == rgb) {
+                            Some(index) => index,
+                            None => {
+                                colors.push(rgb);
+                                colors.len() - 1
+                            }
+                        },
+                        None => 0,
+                    };
+                    if index > usize::from(u8::MAX) {
+                        bail!("Too many colors for an indexed palette");
+                    }
+                    glyph_index.push((glyph, index as u8));
+                }
+            }
+        }
+
+        Ok(Self {
+            theme,
+            cell_width,
+            cell_height,
+            flip: Flip::default(),
+            colors,
+            glyph_index,
+        })
+    }
+
+    /// 按翻转后的帧渲染，裁剪范围同样是翻转后的坐标
+    #[cfg(feature = "graphics")]
+    pub fn with_flip(mut self, flip: Flip) -> Self {
+        self.flip = flip;
+        self
+    }
+
+    /// 栅格化的主题
+    #[cfg(feature = "graphics")]
+    pub fn theme(&self) -> &ThemeRef {
+        &self.theme
+    }
+
+    /// 扁平的 RGB 调色板
+    pub fn palette(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|&(r, g, b)| [r, g, b])
+            .collect()
+    }
+
+    /// 裁剪范围对应的图片宽高（像素）
+    pub fn image_size(&self, cols: &Range<usize>, rows: &Range<usize>) -> (usize, usize) {
+        (cols.len() * self.cell_width, rows.len() * self.cell_height)
+    }
+
+    /// 将帧在 `cols` x `rows` 范围内的单元格渲染到 `pixels`
+    ///
+    /// 彩虹的颜色按 `phase` 轮换，见 [`Theme::render_color_at`]。
+    /// `pixels` 的大小必须与 [`Rasterizer::image_size`] 一致。
+    pub fn render(
+        &self,
+        frame_idx: usize,
+        phase: usize,
+        cols: &Range<usize>,
+        rows: &Range<usize>,
+        pixels: &mut [u8],
+    ) {
+        let (width, _) = self.image_size(cols, rows);
+        let (cell_width, cell_height) = (self.cell_width, self.cell_height);
+        // 帧之间的行长度可能不同，先清空上一帧
+        pixels.fill(0);
+        let frame = self.theme.frames.get(frame_idx);
+        for (y, row) in self
+            .flip
+            .rows(frame)
+            .enumerate()
+            .skip(rows.start)
+            .take(rows.len())
+        {
+            let y = y - rows.start;
+            let chars = self.flip.chars(row).enumerate();
+            for (x, glyph) in chars.skip(cols.start).take(cols.len()) {
+                let x = x - cols.start;
+                let glyph = rainbow_glyph(glyph, phase);
+                let index = self
+                    .glyph_index
+                    .iter()
+                    .find(|(known, _)| *known == glyph)
+                    .map_or(0, |&(_, index)| index);
+                // 填充单元格对应的像素块
+                for py in y * cell_height..(y + 1) * cell_height {
+                    let line = py * width;
+                    pixels[line + x * cell_width..line + (x + 1) * cell_width].fill(index);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// asciinema v2 录制器
+///
+/// 第一行写入 JSON 头部，之后每次输出写入一条 `[time, "o", data]` 事件。
+pub struct CastRecorder {
+    writer: BufWriter<File>,
+}
+
+impl CastRecorder {
+    /// 创建录制文件并写入头部
+    ///
+    /// ## Arguments
+    ///
+    /// * `path`: 录制文件路径
+    /// * `width`: 终端宽度
+    /// * `height`: 终端高度
+    pub fn create(path: &Path, width: u16, height: u16) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        writeln!(
+            writer,
+            r#"{{"version": 2, "width": {width}, "height": {height}, "timestamp": {timestamp}, "env": {{"TERM": "xterm-256color"}}}}"#
+        )?;
+        Ok(Self { writer })
+    }
+
+    /// 写入一条输出事件
+    ///
+    /// ## Arguments
+    ///
+    /// * `elapsed`: 距离开始的时间
+    /// * `data`: 终端输出的原始字节
+    pub fn write_output(&mut self, elapsed: Duration, data: &[u8]) -> io::Result<()> {
+        let data = String::from_utf8_lossy(data);
+        writeln!(
+            self.writer,
+            r#"[{:.6}, "o", "{}"]"#,
+            elapsed.as_secs_f64(),
+            escape_json(&data)
+        )
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// 转义 JSON 字符串中的特殊字符
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
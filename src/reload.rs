@@ -0,0 +1,235 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration, SystemTime},
+};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::watch;
+
+use crate::{animation::ThemeRef, cli::Args, markup};
+
+/// 收到帧目录的变化后等待后续事件的时间
+const FRAMES_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// 没有文件变化时检查播放器是否都已退出的间隔
+const CLOSED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 服务模式共享的帧间隔
+///
+/// Unix 下收到 SIGHUP 时重新读取命令行参数和配置文件，帧间隔的变化会通知到所有连接，
+/// 已连接的客户端无需重连即可生效。其他平台上帧间隔保持不变。
+pub fn watch_frame_delay(args: &Args) -> watch::Receiver<Duration> {
+    let (tx, rx) = watch::channel(args.frame_delay());
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::error!("Could not listen for SIGHUP: {e}");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            reload_frame_delay(&tx, crate::config::load_args());
+        }
+    });
+    #[cfg(not(unix))]
+    drop(tx);
+
+    rx
+}
+
+/// 应用重新读取的参数和配置，读取失败时保留原来的帧间隔
+///
+/// 收到 SIGHUP 时调用，测试中可以不发送信号直接重新加载。
+#[cfg(any(unix, test))]
+fn reload_frame_delay(tx: &watch::Sender<Duration>, reloaded: anyhow::Result<Args>) {
+    use tracing::{error, info};
+
+    match reloaded {
+        Ok(args) => {
+            info!("Config reloaded, frame delay = {:?}", args.frame_delay());
+            tx.send_replace(args.frame_delay());
+        }
+        Err(e) => error!("Reload config failed: {e:#}"),
+    }
+}
+
+/// `--frames-dir` 加载的帧，目录中的文件变化后通知所有播放器
+///
+/// 后台线程等待文件系统事件，短暂合并连续的事件后比较帧文件的列表、大小和修改时间，
+/// 有变化时重新读取整个目录。读取失败或没有帧时给出警告并保留上一次成功加载的帧。
+/// 重新加载的主题按引用计数共享，所有播放器切换到新的帧后释放。
+pub fn watch_frames_dir(args: &Args) -> Option<watch::Receiver<ThemeRef>> {
+    use tracing::{info, warn};
+
+    let dir = args.frames_dir.clone()?;
+    let (tx, rx) = watch::channel(ThemeRef::Static(args.theme));
+    let args = args.clone();
+
+    let mut last = dir_snapshot(&dir);
+    let (events_tx, events) = mpsc::channel();
+    let watcher = notify::recommended_watcher(events_tx).and_then(|mut watcher| {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!(
+                "Could not watch {}, frames will not reload: {e}",
+                dir.display()
+            );
+            return Some(rx);
+        }
+    };
+
+    std::thread::spawn(move || {
+        // 线程退出时停止监听
+        let _watcher = watcher;
+        // 所有播放器退出后结束
+        while !tx.is_closed() {
+            match events.recv_timeout(CLOSED_CHECK_INTERVAL) {
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            // 编辑器保存一个文件常常产生多个事件，合并后只读取一次
+            std::thread::sleep(FRAMES_DEBOUNCE);
+            while events.try_recv().is_ok() {}
+
+            let snapshot = dir_snapshot(&dir);
+            if snapshot == last {
+                continue;
+            }
+            last = snapshot;
+            match markup::load_dir(&dir) {
+                Ok(theme) => {
+                    info!(
+                        "Frames reloaded from {}, {} frames",
+                        dir.display(),
+                        theme.frames.len()
+                    );
+                    tx.send_replace(ThemeRef::Shared(Arc::new(args.adjust_reloaded(theme))));
+                }
+                Err(e) => warn!("Reload frames failed, keeping the previous frames: {e:#}"),
+            }
+        }
+    });
+
+    Some(rx)
+}
+
+/// 帧文件的路径、大小和修改时间，目录无法读取时为空
+fn dir_snapshot(dir: &Path) -> Vec<(PathBuf, u64, Option<SystemTime>)> {
+    markup::frame_files(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let len = metadata.as_ref().map_or(0, |metadata| metadata.len());
+            let modified = metadata.and_then(|metadata| metadata.modified().ok());
+            (path, len, modified)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tokio::{
+        io::{AsyncReadExt, DuplexStream, duplex},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{
+        config::load_args_from, conn::ConnectionEvents, metrics::Transport, server::ServerContext,
+        telnet::handle_telnet_client,
+    };
+
+    /// 连续两帧之间的时间，先读完已经发送的数据
+    async fn frame_interval(client: &mut DuplexStream) -> Duration {
+        let mut buf = vec![0; 1 << 20];
+        assert!(client.read(&mut buf).await.unwrap() > 0);
+        let start = Instant::now();
+        assert!(client.read(&mut buf).await.unwrap() > 0);
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloaded_config_changes_connected_client_speed() {
+        let path = std::env::temp_dir().join(format!("nyancat-{}.toml", std::process::id()));
+        fs::write(&path, "fps = 10\n").unwrap();
+        let argv = [
+            "nyancat",
+            "-t",
+            "--raw-tcp",
+            "--config",
+            path.to_str().unwrap(),
+        ];
+        let args = load_args_from(argv).unwrap();
+
+        let (tx, frame_delay) = watch::channel(args.frame_delay());
+        let (server, mut client) = duplex(1 << 20);
+        let session = tokio::spawn(async move {
+            let events = ConnectionEvents::connect(Transport::Telnet, ([127, 0, 0, 1], 0).into());
+            handle_telnet_client(
+                server,
+                &args,
+                ServerContext::from_frame_delay(frame_delay),
+                &events,
+            )
+            .await
+        });
+        assert_eq!(
+            frame_interval(&mut client).await,
+            Duration::from_millis(100)
+        );
+
+        // 与收到 SIGHUP 时相同，重新读取修改后的配置文件
+        fs::write(&path, "fps = 50\n").unwrap();
+        reload_frame_delay(&tx, load_args_from(argv));
+        // 正在等待的一帧仍使用原来的间隔
+        frame_interval(&mut client).await;
+        assert_eq!(frame_interval(&mut client).await, Duration::from_millis(20));
+        assert!(!session.is_finished());
+
+        // 配置文件出错时保留当前的帧间隔
+        fs::write(&path, "fps = 0\n").unwrap();
+        reload_frame_delay(&tx, load_args_from(argv));
+        assert_eq!(*tx.borrow(), Duration::from_millis(20));
+
+        session.abort();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn frames_dir_change_sends_new_theme() {
+        let dir = std::env::temp_dir().join(format!("nyancat-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("01.txt"), "ab").unwrap();
+
+        let args = Args::parse_from(["nyancat", "--frames-dir", dir.to_str().unwrap()]);
+        let mut frames = watch_frames_dir(&args).unwrap();
+        fs::write(dir.join("02.txt"), "cd").unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), frames.changed())
+            .await
+            .expect("frames were not reloaded")
+            .unwrap();
+        let theme = frames.borrow_and_update().clone();
+        assert!(matches!(theme, ThemeRef::Shared(_)));
+        assert_eq!(theme.frames.get(1), ["cd"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
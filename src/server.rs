@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::{animation::ThemeRef, cli::Args, dump::OutputDump, reload};
+
+/// Telnet 和 HTTP 服务共享的状态
+///
+/// 两个服务在同一进程中运行时只创建一份，SIGHUP、帧目录和退出信号只各监听一次，
+/// 转储文件也只打开一次。
+#[derive(Clone)]
+pub struct ServerContext {
+    /// 帧间隔，见 [`reload::watch_frame_delay`]
+    pub frame_delay: watch::Receiver<Duration>,
+    /// `--frames-dir` 重新加载的帧，见 [`reload::watch_frames_dir`]
+    pub frames: Option<watch::Receiver<ThemeRef>>,
+    /// `--dump-output` 的转储文件，所有连接共享
+    pub dump: Option<OutputDump>,
+    /// 收到 Ctrl+C、SIGTERM 或任一服务退出后变为 `true`
+    shutdown: watch::Sender<bool>,
+}
+
+impl ServerContext {
+    /// 根据命令行参数创建，需要在 Tokio 运行时中调用
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let context = Self {
+            frame_delay: reload::watch_frame_delay(args),
+            frames: reload::watch_frames_dir(args),
+            dump: OutputDump::from_path(args.dump_output.as_deref())?,
+            shutdown: watch::channel(false).0,
+        };
+        let shutdown = context.shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            info!("Shutting down");
+            shutdown.send_replace(true);
+        });
+        Ok(context)
+    }
+
+    /// 通知所有服务关闭
+    #[cfg(any(feature = "http", test))]
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// 订阅关闭通知，见 [`Self::shutdown_requested`]
+    pub fn subscribe_shutdown(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// 等待关闭通知，已经通知过时立即返回
+    pub async fn shutdown_requested(&self) {
+        // 发送端由自身持有，不会关闭
+        let _ = self
+            .subscribe_shutdown()
+            .wait_for(|shutdown| *shutdown)
+            .await;
+    }
+
+    /// 运行一个服务，服务因任何原因退出时通知其他服务一起关闭
+    #[cfg(feature = "http")]
+    pub async fn run(
+        &self,
+        server: impl Future<Output = anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        let result = server.await;
+        self.shutdown();
+        result
+    }
+}
+
+#[cfg(test)]
+impl ServerContext {
+    /// 使用给定的帧间隔，不监听信号和帧目录
+    pub fn from_frame_delay(frame_delay: watch::Receiver<Duration>) -> Self {
+        Self {
+            frame_delay,
+            frames: None,
+            dump: None,
+            shutdown: watch::channel(false).0,
+        }
+    }
+}
+
+/// 等待 Ctrl+C 或 SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Could not listen for Ctrl+C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Could not listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -1,140 +1,560 @@
 use std::{
+    collections::VecDeque,
     io::{self, Write},
-    time::Duration,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
+use colored::Colorize;
 use crossterm::{
     cursor,
     event::{self, Event, KeyCode, KeyEventKind},
-    execute,
+    execute, queue,
     terminal::{LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
-use tokio::time::{Instant, sleep};
+use tokio::sync::Notify;
 
 use crate::{
-    animation::{FRAMES, NyanedTime, RenderSize, render_color},
+    animation::{
+        ColorMode, Frame, NyanedTime, RenderOptions, RenderSize, THEMES, Theme, ThemeRef,
+        counter_position, screen_cells, screen_lines, usable_size,
+    },
+    animator::Animator,
     cli::Args,
+    dump::{OutputDump, Tee},
+    record::CastRecorder,
+    reload,
 };
 
 // 独立模式运行
 pub async fn run_standalone(args: &Args) -> anyhow::Result<()> {
-    let mut stdout = io::stdout();
+    // 指定 --dump-output 时同时写入转储文件
+    let dump = OutputDump::from_path(args.dump_output.as_deref())?;
+    let mut stdout = Tee::new(io::stdout(), dump);
+
+    // Windows 控制台需要开启虚拟终端处理才能解析 ANSI 转义序列
+    #[cfg(windows)]
+    if !crossterm::ansi_support::supports_ansi() {
+        tracing::warn!("Terminal does not support ANSI escape sequences, output may be garbled");
+    }
+
+    // 显式指定的颜色模式优先，否则根据终端类型选择支持的最丰富的模式
+    let color_mode = args.color.unwrap_or_else(ColorMode::detect);
 
     // 终端初始化
-    enable_raw_mode()?;
-    execute!(stdout, LeaveAlternateScreen, cursor::Hide)?;
+    let terminal = TerminalGuard::enter(&mut stdout, !args.no_hide_cursor)?;
+
+    // 终端大小变化或切换主题时通知动画循环立即重绘
+    // Unix 上监听 SIGWINCH，其他平台使用终端事件
+    let redraw = Arc::new(Notify::new());
+    #[cfg(unix)]
+    let winch = watch_window_change(redraw.clone())?;
+
+    // 按 `t` 切换主题的次数，动画循环在下一帧取走
+    let theme_switches = Arc::new(AtomicUsize::new(0));
+    let event_theme_switches = theme_switches.clone();
 
     // 监听退出信号
-    let event_loop = tokio::spawn(async {
-        loop {
-            if event::poll(Duration::from_millis(100))?
-                && let Event::Key(event) = event::read()?
-                && event.kind == KeyEventKind::Press
-                && (event.code == KeyCode::Esc || event.code == KeyCode::Char('q'))
-            {
-                return anyhow::Ok(());
+    // 事件读取是阻塞的，放到阻塞线程中，动画结束时通过 running 通知其退出
+    let running = Arc::new(AtomicBool::new(true));
+    let event_running = running.clone();
+    let event_redraw = redraw.clone();
+    let event_loop = tokio::task::spawn_blocking(move || {
+        while event_running.load(Ordering::Relaxed) {
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            match event::read()? {
+                Event::Key(event)
+                    if event.kind == KeyEventKind::Press
+                        && (event.code == KeyCode::Esc || event.code == KeyCode::Char('q')) =>
+                {
+                    // 唤醒动画循环立即退出，不必等到下一帧
+                    event_running.store(false, Ordering::Relaxed);
+                    event_redraw.notify_one();
+                    break;
+                }
+                Event::Key(event)
+                    if event.kind == KeyEventKind::Press && event.code == KeyCode::Char('t') =>
+                {
+                    // 立即重绘，下一帧的时间不变，连续切换不会让动画停住
+                    event_theme_switches.fetch_add(1, Ordering::Relaxed);
+                    event_redraw.notify_one();
+                }
+                #[cfg(not(unix))]
+                Event::Resize(..) => event_redraw.notify_one(),
+                _ => {}
             }
         }
+        anyhow::Ok(())
     });
 
     // 动画循环
-    let mut frame_idx = 0;
-    let start_time = Instant::now();
+    let mut animator = Animator::new(args).with_frame_limits(args.frames, args.loop_count);
+    let start_time = animator.start_time();
+    let mut themes = theme_cycle(args);
+    let mut theme_idx = 0;
+    // `--frames-dir` 重新加载的帧替换切换列表中的第一个主题
+    let mut frame_updates = reload::watch_frames_dir(args);
+
+    // 录制到 asciinema 文件
+    let mut recorder = match &args.record {
+        Some(path) => {
+            let (width, height) = terminal_size(args);
+            Some(CastRecorder::create(path, width, height)?)
+        }
+        None => None,
+    };
+
+    // 终端支持图片协议时把帧作为图片显示
+    #[cfg(feature = "graphics")]
+    let mut images = args
+        .render
+        .or_else(crate::graphics::detect_renderer)
+        .and_then(|renderer| crate::graphics::Images::new(renderer, args.flip()));
+
+    // 每帧输出先写入缓冲区，再同时输出到终端和录制文件
+    let mut buf = Vec::new();
+    // 上一帧的单元格，只输出变化的部分
+    let mut back_buffer = BackBuffer::default();
+    let mut fps_meter = FpsMeter::default();
+    // 本次输出是否为新的一帧，终端大小变化或切换主题时的重绘不计入帧率
+    let mut advanced = true;
+    // 上一帧的终端大小，变化时清屏一次，避免残留旧的行
+    let mut last_size = None;
 
     if !args.no_clear {
-        execute!(
-            stdout,
+        queue!(
+            buf,
             crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
         )?;
     }
 
     loop {
         // 检查是否退出
-        if event_loop.is_finished() {
+        if !running.load(Ordering::Relaxed) || event_loop.is_finished() {
             break;
         }
 
+        // 切换主题，新主题的大小可能不同，清屏一次并全量重绘
+        let switches = theme_switches.swap(0, Ordering::Relaxed);
+        let mut switched = switches > 0;
+        if switched {
+            theme_idx = (theme_idx + switches) % themes.len();
+            animator.set_theme(themes[theme_idx].clone());
+            back_buffer.invalidate();
+        }
+
+        // 重新加载的帧在正在播放时立即生效，大小变化时与切换主题一样清屏
+        if let Some(updates) = frame_updates.as_mut()
+            && updates.has_changed().unwrap_or(false)
+        {
+            let theme = updates.borrow_and_update().clone();
+            let resized = (theme.width, theme.height) != (themes[0].width, themes[0].height);
+            themes[0] = theme.clone();
+            if theme_idx == 0 {
+                animator.set_theme(theme);
+                if resized {
+                    switched = true;
+                    back_buffer.invalidate();
+                }
+            }
+        }
+
         // 获取终端大小
-        let size = crossterm::terminal::size()?;
+        let size = terminal_size(args);
         let (terminal_width, terminal_height) = size;
+        let resized = last_size.replace(size).is_some_and(|last| last != size);
+        if (resized || switched) && !args.no_clear {
+            queue!(
+                buf,
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
+            )?;
+        }
+        back_buffer.resize(size);
 
-        let RenderSize {
-            min_col,
-            max_col,
-            min_row,
-            max_row,
-        } = RenderSize::new(terminal_width, terminal_height);
+        let render_size = animator.render_size(terminal_width, terminal_height);
 
         // 渲染当前帧
-        render_frame(FRAMES[frame_idx], min_row, max_row, min_col, max_col)?;
+        #[cfg(feature = "graphics")]
+        let drawn = match images.as_mut() {
+            Some(images) => {
+                let (theme, phase) = (animator.theme(), animator.color_phase());
+                images.draw(&mut buf, theme, animator.frame_index(), phase, &render_size)?;
+                true
+            }
+            None => false,
+        };
+        #[cfg(not(feature = "graphics"))]
+        let drawn = false;
+        if !drawn {
+            render_frame(
+                &mut buf,
+                &mut back_buffer,
+                animator.frame(),
+                &render_size,
+                &animator.render_options(args, color_mode),
+            )?;
+        }
 
         // 显示计数器
         if !args.no_counter {
-            let nyaned_time = NyanedTime::new(start_time, terminal_width);
-            if nyaned_time.text_len >= terminal_width.into() {
-                execute!(stdout, cursor::MoveTo(0, size.1 - 1))?;
-                print!("{}", nyaned_time.nyaned);
-            } else {
-                execute!(stdout, cursor::MoveTo(0, size.1))?;
-                print!("{}", nyaned_time.counter_text);
-            }
-            stdout.flush()?;
+            write_counter(
+                &mut buf,
+                args,
+                animator.theme(),
+                color_mode,
+                start_time,
+                size,
+                animator.rendered(),
+            )?;
         }
 
-        // 控制帧率
-        sleep(Duration::from_millis(100)).await;
+        // 帧率显示在左上角，位于天空背景上，不会遮挡猫
+        if args.show_fps {
+            if advanced {
+                fps_meter.record(Instant::now());
+            }
+            let status = format!(
+                " {:>5.1} fps {:>8} frames ",
+                fps_meter.fps(),
+                animator.rendered()
+            );
+            queue!(buf, cursor::MoveTo(0, 0))?;
+            write!(buf, "{}", status.black().on_white())?;
+        }
 
-        // 检查帧限制
-        if let Some(limit) = args.frames
-            && frame_idx >= limit
-        {
-            break;
+        stdout.write_all(&buf)?;
+        stdout.flush()?;
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.write_output(start_time.elapsed(), &buf)?;
         }
+        buf.clear();
 
-        // 下一帧
-        frame_idx = (frame_idx + 1) % FRAMES.len();
+        // 等待下一帧，期间终端大小变化时立即按新大小重绘当前帧，之后继续等待原来的下一帧时间
+        advanced = tokio::select! {
+            more = animator.tick() => if more {
+                true
+            } else {
+                break;
+            },
+            () = redraw.notified() => false,
+        };
+    }
+
+    running.store(false, Ordering::Relaxed);
+    #[cfg(unix)]
+    winch.abort();
+
+    // 图片不随文字清除，退出前删除
+    #[cfg(feature = "graphics")]
+    if let Some(images) = images.as_mut() {
+        images.clear(&mut stdout)?;
     }
 
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    // 恢复终端前记录，不包含恢复终端的时间
+    let elapsed = start_time.elapsed();
+    let frames = animator.rendered();
+
     // 恢复终端
-    disable_raw_mode()?;
-    execute!(stdout, LeaveAlternateScreen, cursor::Show)?;
+    terminal.restore(&mut stdout)?;
+
+    if args.summary {
+        writeln!(stdout, "{}", summary(elapsed, frames))?;
+    }
+
+    Ok(())
+}
+
+/// 按 `t` 依次切换的主题
+///
+/// 第一个是命令行参数选择的主题，保留帧文件和调色板等调整，其他内置主题使用相同的亮度和字符集。
+fn theme_cycle(args: &Args) -> Vec<ThemeRef> {
+    let others = THEMES
+        .iter()
+        .filter(|theme| theme.name != args.theme.name)
+        .map(|theme| args.adjust_theme(theme.with_animation(args.animation)));
+    std::iter::once(args.theme)
+        .chain(others)
+        .map(ThemeRef::from)
+        .collect()
+}
+
+/// 退出时输出的统计信息
+fn summary(elapsed: Duration, frames: usize) -> String {
+    let plural = if frames == 1 { "" } else { "s" };
+    format!(
+        "You have nyaned for {:.1} seconds, {frames} frame{plural} in total.",
+        elapsed.as_secs_f64()
+    )
+}
+
+/// 收到 SIGWINCH 时通知动画循环
+#[cfg(unix)]
+fn watch_window_change(resized: Arc<Notify>) -> io::Result<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut window_change = signal(SignalKind::window_change())?;
+    Ok(tokio::spawn(async move {
+        while window_change.recv().await.is_some() {
+            resized.notify_one();
+        }
+    }))
+}
+
+/// 独立模式的终端状态
+///
+/// 正常结束时调用 [`TerminalGuard::restore`]，出错提前返回时在 drop 中恢复，
+/// 保证不会让终端停留在 raw 模式或隐藏光标。
+struct TerminalGuard {
+    active: bool,
+}
+
+impl TerminalGuard {
+    /// 进入 raw 模式，`hide_cursor` 为 `true` 时隐藏光标
+    fn enter(out: &mut impl Write, hide_cursor: bool) -> io::Result<Self> {
+        enable_raw_mode()?;
+        let guard = Self { active: true };
+        execute!(out, LeaveAlternateScreen)?;
+        if hide_cursor {
+            execute!(out, cursor::Hide)?;
+        }
+        Ok(guard)
+    }
 
+    /// 退出 raw 模式并显示光标
+    fn restore(mut self, out: &mut impl Write) -> io::Result<()> {
+        self.active = false;
+        disable_raw_mode()?;
+        execute!(out, LeaveAlternateScreen, cursor::Show)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if self.active {
+            let _ = disable_raw_mode();
+            let _ = execute!(io::stdout(), cursor::Show);
+        }
+    }
+}
+
+/// 帧率统计
+///
+/// 取最近若干帧的时间戳计算滚动平均，避免数值跳动。
+#[derive(Default)]
+struct FpsMeter {
+    /// 最近帧的时间戳
+    recent: VecDeque<Instant>,
+}
+
+impl FpsMeter {
+    /// 参与平均的帧数
+    const WINDOW: usize = 30;
+
+    fn record(&mut self, now: Instant) {
+        if self.recent.len() == Self::WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+    }
+
+    fn fps(&self) -> f64 {
+        let (Some(first), Some(last)) = (self.recent.front(), self.recent.back()) else {
+            return 0.0;
+        };
+        let elapsed = last.duration_since(*first).as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        (self.recent.len() - 1) as f64 / elapsed
+    }
+}
+
+/// 上一帧已输出到终端的单元格
+///
+/// 按裁剪后的屏幕坐标保存，终端大小变化时清空，下一帧全量重绘。
+#[derive(Default)]
+pub struct BackBuffer {
+    size: (u16, u16),
+    cells: Vec<Vec<&'static str>>,
+}
+
+impl BackBuffer {
+    /// 终端大小变化时清空缓冲区
+    pub fn resize(&mut self, size: (u16, u16)) {
+        if self.size != size {
+            self.size = size;
+            self.cells.clear();
+        }
+    }
+
+    /// 清空缓冲区，下一帧全量重绘
+    pub fn invalidate(&mut self) {
+        self.cells.clear();
+    }
+
+    /// 记录单元格，返回该单元格是否与上一帧不同
+    fn update(&mut self, row: usize, col: usize, cell: &'static str) -> bool {
+        if self.cells.len() <= row {
+            self.cells.resize_with(row + 1, Vec::new);
+        }
+        let line = &mut self.cells[row];
+        if line.len() <= col {
+            line.resize(col + 1, "");
+        }
+        if line[col] == cell {
+            return false;
+        }
+        line[col] = cell;
+        true
+    }
+}
+
+/// 在终端最后一行输出计数器
+///
+/// 开启 `--scroll-counter` 时，放不下的计数文本按已渲染的帧数 `frame` 每帧滚动一列。
+pub fn write_counter(
+    out: &mut impl Write,
+    args: &Args,
+    theme: &Theme,
+    color_mode: ColorMode,
+    start_time: tokio::time::Instant,
+    size: (u16, u16),
+    frame: usize,
+) -> io::Result<()> {
+    let nyaned_time = NyanedTime::new(start_time, size.0, theme, color_mode, &args.counter_format);
+    let fits = nyaned_time.text_len < size.0.into();
+    // 独立模式总是输出到终端，只有显式指定或无颜色时输出纯文本
+    let plain = args.plain_counter(color_mode, true);
+    let (col, row) = counter_position(size, nyaned_time.text_len, fits && args.center_counter);
+    if !fits && args.scroll_counter {
+        let text = nyaned_time.scrolled(size.0.into(), frame);
+        queue!(out, cursor::MoveTo(0, row))?;
+        if plain {
+            write!(out, "{text}")?;
+        } else {
+            write!(out, "{}", NyanedTime::style(&text, color_mode))?;
+        }
+    } else if !fits {
+        queue!(out, cursor::MoveTo(col, row))?;
+        write!(out, "{}", nyaned_time.nyaned)?;
+    } else if args.center_counter {
+        // 计数文本长度会变化，先清除整行
+        queue!(
+            out,
+            cursor::MoveTo(0, row),
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine),
+            cursor::MoveTo(col, row)
+        )?;
+        if plain {
+            write!(out, "{}", nyaned_time.nyaned)?;
+        } else {
+            write!(out, "{}", nyaned_time.styled)?;
+        }
+    } else {
+        queue!(out, cursor::MoveTo(col, row))?;
+        write!(out, "{}", nyaned_time.line(plain))?;
+    }
     Ok(())
 }
 
-/// 渲染帧到终端
+/// 当前终端大小，获取失败或为 0 时使用默认大小
+fn terminal_size(args: &Args) -> (u16, u16) {
+    let size = crossterm::terminal::size().unwrap_or((0, 0));
+    render_area(args, size)
+}
+
+/// 渲染使用的大小，`--width`/`--height` 指定的值优先于终端的实际大小
+///
+/// 固定的大小可以超过终端，超出的部分由终端自行裁剪。
+fn render_area(args: &Args, size: (u16, u16)) -> (u16, u16) {
+    let (width, height) = usable_size(size, (args.default_width, args.default_height));
+    (args.width.unwrap_or(width), args.height.unwrap_or(height))
+}
+
+/// 渲染帧到输出
+///
+/// 只输出与上一帧不同的单元格，连续变化的单元格合并输出，避免多余的光标移动。
 pub fn render_frame(
-    frame: &[&str],
-    min_row: usize,
-    max_row: usize,
-    min_col: usize,
-    max_col: usize,
+    out: &mut impl Write,
+    back_buffer: &mut BackBuffer,
+    frame: Frame,
+    size: &RenderSize,
+    options: &RenderOptions,
 ) -> io::Result<()> {
-    let mut stdout = io::stdout();
+    let cell_width = options.renderer.cell_width();
 
-    execute!(stdout, cursor::MoveTo(0, 0))?;
+    // 当前光标位置，None 表示未知
+    let mut cursor_at = None;
 
     // 渲染帧内容
-    // 行
-    for (y, row) in frame.iter().enumerate() {
-        if y < min_row || y >= max_row {
-            continue;
-        }
-
-        let mut line = String::new();
-        // 列
-        for (x, c) in row.chars().enumerate() {
-            if x < min_col || x >= max_col {
+    for (screen_row, line) in screen_lines(frame, *size, options).enumerate() {
+        for (screen_col, cell) in screen_cells(line, *size, options).enumerate() {
+            if !back_buffer.update(screen_row, screen_col, cell) {
                 continue;
             }
 
-            line.push_str(render_color(c));
+            // 块模式每个单元格占两列，其他绘制方式占一列
+            let position = ((screen_col * cell_width) as u16, screen_row as u16);
+            if cursor_at != Some(position) {
+                queue!(out, cursor::MoveTo(position.0, position.1))?;
+            }
+            out.write_all(cell.as_bytes())?;
+            cursor_at = Some((position.0 + cell_width as u16, position.1));
         }
-        // 渲染的行数减去最小行数，就是跳过的行
-        execute!(stdout, cursor::MoveTo(0, (y - min_row) as u16))?;
-        println!("{}", line);
     }
 
-    stdout.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::animator::render_options;
+
+    #[test]
+    fn forced_size_ignores_terminal() {
+        let args = Args::parse_from(["nyancat", "--width", "40", "--height", "20"]);
+        let options = render_options(&args, ColorMode::Color256);
+
+        let mut frames = Vec::new();
+        for live in [(0, 0), (80, 24), (300, 100)] {
+            let size = render_area(&args, live);
+            assert_eq!(size, (40, 20));
+
+            let mut out = Vec::new();
+            let render_size = RenderSize::new(
+                (args.theme.width, args.theme.height),
+                size.0,
+                size.1,
+                args.renderer(),
+            );
+            render_frame(
+                &mut out,
+                &mut BackBuffer::default(),
+                args.theme.frames.get(0),
+                &render_size,
+                &options,
+            )
+            .unwrap();
+            frames.push(out);
+        }
+        assert!(!frames[0].is_empty());
+        assert!(frames.windows(2).all(|pair| pair[0] == pair[1]));
+
+        // 只指定一边时另一边跟随终端
+        let args = Args::parse_from(["nyancat", "--height", "20"]);
+        assert_eq!(render_area(&args, (120, 40)), (120, 20));
+        assert_eq!(render_area(&args, (0, 0)), (80, 20));
+    }
+}
@@ -1,88 +1,186 @@
 use std::time::Duration;
 
+use anyhow::Context;
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    time::{Instant, sleep},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::watch,
+    task::JoinSet,
+    time::{sleep, timeout},
 };
+use tracing::{Instrument, debug, error, info, info_span, warn};
 
 use crate::{
-    animation::{FRAMES, NyanedTime, RenderSize, render_color},
+    animation::{ColorMode, usable_size, visible_chars},
+    animator::Animator,
     cli::Args,
+    conn::ConnectionEvents,
+    dump::Tee,
+    metrics::{self, Transport},
+    peer,
+    server::ServerContext,
+    standalone::{BackBuffer, render_frame, write_counter},
 };
 
 // Telnet协议常量
 const IAC: u8 = 255;
 const DO: u8 = 253;
 const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DONT: u8 = 254;
 const SGA: u8 = 3;
 const TTYPE: u8 = 24;
 const NAWS: u8 = 31;
 const SB: u8 = 250; // 子协商开始
 const SE: u8 = 240; // 子协商结束
+const TTYPE_IS: u8 = 0; // 终端类型子协商：客户端上报
+const TTYPE_SEND: u8 = 1; // 终端类型子协商：请求客户端发送
 
-pub fn build_frame(
+// 等待客户端协商响应的超时时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+/// 发送一帧时临时错误的最大重试次数
+const MAX_WRITE_RETRIES: u32 = 5;
+/// 重试前的等待时间
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(10);
+/// 握手阶段缓存的未完成命令的上限
+const MAX_HANDSHAKE_BUFFER: usize = 64 * 1024;
+/// 服务关闭时等待连接恢复客户端终端的时间，超时后直接断开
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 断开前恢复客户端终端：重置颜色、显示光标
+const RESET_SEQUENCE: &str = "\x1B[0m\x1B[?25h";
+// 清屏并将光标移到左上角
+const CLEAR_SEQUENCE: &str = "\x1B[2J\x1B[H";
+
+/// 握手阶段从客户端获取的信息
+#[derive(Debug)]
+struct ClientInfo {
     width: u16,
     height: u16,
-    args: &Args,
-    frame_idx: usize,
-    start_time: Instant,
-) -> String {
-    // 渲染帧到缓冲区
-    let mut frame_data = String::new();
-    if !args.no_clear {
-        frame_data.push_str("\x1B[2J\x1B[1;1H"); // 清屏
-    }
-
-    let RenderSize {
-        min_col,
-        max_col,
-        min_row,
-        max_row,
-    } = RenderSize::new(width, height);
+    /// 客户端同意协商终端类型（WILL TTYPE）
+    ttype_accepted: bool,
+    /// 客户端拒绝协商终端类型（WONT TTYPE）
+    ttype_refused: bool,
+    /// 客户端上报的终端类型
+    term: Option<String>,
+}
 
-    // 构建帧内容
-    for (y, row) in FRAMES[frame_idx].iter().enumerate() {
-        if y < min_row || y >= max_row {
-            continue;
+impl ClientInfo {
+    /// 以默认窗口大小创建，NAWS 协商成功后会被覆盖
+    fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            ttype_accepted: false,
+            ttype_refused: false,
+            term: None,
         }
+    }
 
-        for (x, c) in row.chars().enumerate() {
-            if x < min_col || x >= max_col {
-                continue;
-            }
+    /// 根据终端类型选择颜色模式，无法识别时回退到 256 色
+    fn color_mode(&self) -> ColorMode {
+        self.term
+            .as_deref()
+            .and_then(ColorMode::from_term)
+            .unwrap_or_default()
+    }
+}
 
-            frame_data.push_str(render_color(c));
-        }
+// 处理Telnet客户端
+// 对流的类型没有要求，明文 TCP、TLS 连接和测试用的内存管道共用同一套处理逻辑
+pub async fn handle_telnet_client(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    args: &Args,
+    context: ServerContext,
+    events: &ConnectionEvents,
+) -> io::Result<()> {
+    // 原始 TCP 客户端（如 `nc`）不理解 Telnet 命令，跳过握手，使用默认大小和颜色模式
+    let (client, color_mode) = if args.raw_tcp {
+        info!("New raw TCP connection");
+        let client = ClientInfo::new(args.default_width, args.default_height);
+        (client, args.color.unwrap_or_default())
+    } else {
+        info!("New telnet connection");
+        let client = negotiate(
+            &mut stream,
+            args.default_width,
+            args.default_height,
+            args.telnet_read_size as usize,
+        )
+        .await?;
+        // `--color` 限制客户端的颜色模式，老终端可能报告了它显示不了的类型
+        let color_mode = match args.color {
+            Some(limit) => client.color_mode().at_most(limit),
+            None => client.color_mode(),
+        };
+        (client, color_mode)
+    };
+    info!(
+        width = client.width,
+        height = client.height,
+        term = ?client.term,
+        ?color_mode,
+        "Handshake finished"
+    );
+    events.size_detected(
+        client.width,
+        client.height,
+        client.term.as_deref(),
+        color_mode,
+    );
 
-        #[cfg(feature = "http")]
-        if args.http {
-            frame_data.push_str("\r\n");
-        }
-        #[cfg(not(feature = "http"))]
-        {
-            frame_data.push('\n');
+    if let Some(banner) = args
+        .banner
+        .as_deref()
+        .filter(|banner| !banner.trim().is_empty())
+    {
+        stream
+            .write_all(render_banner(banner, client.width).as_bytes())
+            .await?;
+        stream.flush().await?;
+        // 欢迎信息由第一帧清屏覆盖，服务关闭时不再等待
+        tokio::select! {
+            () = sleep(Duration::from_secs(args.banner_duration)) => {}
+            () = context.shutdown_requested() => {}
         }
     }
 
-    // 显示计数器
-    if !args.no_counter {
-        let nyaned_time = NyanedTime::new(start_time, width);
-        if nyaned_time.text_len >= width.into() {
-            frame_data.push_str(&nyaned_time.nyaned);
-        } else {
-            frame_data.push_str(&nyaned_time.counter_text);
-        }
+    let animator = Animator::new(args)
+        .with_delay_updates(context.frame_delay.clone())
+        .with_theme_updates(context.frames.clone());
+    let result = send_animation(
+        &mut stream,
+        (client.width, client.height),
+        args,
+        color_mode,
+        animator,
+        context.subscribe_shutdown(),
+        events,
+    )
+    .await;
+
+    // 无论动画正常结束还是出错，都尽量恢复客户端终端
+    let mut reset = String::from(RESET_SEQUENCE);
+    if !args.no_clear {
+        reset.push_str(CLEAR_SEQUENCE);
+    }
+    if stream.write_all(reset.as_bytes()).await.is_ok() {
+        let _ = stream.flush().await;
     }
 
-    frame_data
+    result
 }
 
-// 处理Telnet客户端
-pub async fn handle_telnet_client(mut stream: TcpStream, args: &Args) -> io::Result<()> {
-    let addr = stream.peer_addr()?;
-    println!("New telnet connection from {}", addr);
-
+/// Telnet 握手，协商窗口大小和终端类型
+///
+/// 客户端未上报窗口大小时使用传入的默认值。每次最多读取 `read_size` 字节，
+/// 跨多次读取的命令会拼接后再解析。
+async fn negotiate(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    default_width: u16,
+    default_height: u16,
+    read_size: usize,
+) -> io::Result<ClientInfo> {
     // Telnet握手
     let handshake = [
         IAC, WILL, SGA, // 启用SGA
@@ -92,138 +190,969 @@ pub async fn handle_telnet_client(mut stream: TcpStream, args: &Args) -> io::Res
     stream.write_all(&handshake).await?;
 
     // 读取客户端响应
-    let mut buf = [0; 1024];
-    let mut client_width = 80;
-    let mut client_height = 24;
+    // 尚未解析完的数据，只保存实际读到的字节
+    let mut buf = Vec::with_capacity(read_size);
+    let mut client = ClientInfo::new(default_width, default_height);
+    let mut got_size = false;
+    let mut ttype_requested = false;
 
     loop {
-        match stream.read(&mut buf).await {
-            Ok(0) => break, // 连接关闭
-            Ok(n) => {
-                // 简单处理Telnet命令（实际需要更完整的解析）
-                if parse_telnet_commands(&buf[..n], &mut client_width, &mut client_height) {
-                    // 命令处理完成，开始发送动画
+        let filled = buf.len();
+        buf.resize(filled + read_size, 0);
+        match timeout(HANDSHAKE_TIMEOUT, stream.read(&mut buf[filled..])).await {
+            // 握手期间断开的多半是端口扫描，交给调用方按断开处理
+            Ok(Ok(0)) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(Ok(n)) => {
+                // 去掉未被填充的部分
+                buf.truncate(filled + n);
+                let (size, consumed) = parse_telnet_commands(&buf, &mut client);
+                got_size |= size;
+                buf.drain(..consumed);
+                // 一直无法结束的命令说明客户端行为异常，丢弃以免缓冲区无限增长
+                if buf.len() > MAX_HANDSHAKE_BUFFER {
+                    warn!(
+                        "Discarding {} bytes of unterminated telnet negotiation",
+                        buf.len()
+                    );
+                    buf.clear();
+                }
+
+                // 客户端同意后请求具体的终端类型
+                if client.ttype_accepted && !ttype_requested {
+                    stream
+                        .write_all(&[IAC, SB, TTYPE, TTYPE_SEND, IAC, SE])
+                        .await?;
+                    ttype_requested = true;
+                }
+
+                // 获取到窗口大小，且终端类型已知或无需等待，开始发送动画
+                let ttype_pending = ttype_requested && client.term.is_none();
+                if got_size && (!ttype_pending || client.ttype_refused) {
                     break;
                 }
             }
-            Err(e) => {
-                eprintln!("Read error: {}", e);
+            Ok(Err(e)) => return Err(e),
+            // 客户端未完成协商，使用已获取的信息
+            Err(_) => {
+                debug!("Handshake timed out, using negotiated values");
                 break;
             }
         }
     }
 
-    // 发送动画帧
-    let mut frame_idx = 0;
-    let start_time = Instant::now();
+    // 部分客户端上报 0x0，使用默认大小
+    (client.width, client.height) = usable_size(
+        (client.width, client.height),
+        (default_width, default_height),
+    );
+
+    Ok(client)
+}
+
+/// 清屏后输出加粗的欢迎信息，按客户端宽度折行
+fn render_banner(banner: &str, width: u16) -> String {
+    let mut out = String::from(CLEAR_SEQUENCE);
+    for line in wrap_text(banner, width.into()) {
+        out.push_str("\x1B[1m");
+        out.push_str(&line);
+        out.push_str("\x1B[0m\r\n");
+    }
+    out
+}
+
+/// 按单词折行，超过宽度的单词按字符拆分，宽度按终端列数计算
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_len = 0;
+        for word in paragraph.split_whitespace() {
+            let word_len: usize = visible_chars(word).map(|(_, columns)| columns).sum();
+            if line_len > 0 && line_len + 1 + word_len > width {
+                lines.push(std::mem::take(&mut line));
+                line_len = 0;
+            }
+            if line_len > 0 {
+                line.push(' ');
+                line_len += 1;
+            }
+            for (ch, columns) in visible_chars(word) {
+                if line_len > 0 && line_len + columns > width {
+                    lines.push(std::mem::take(&mut line));
+                    line_len = 0;
+                }
+                line.push(ch);
+                line_len += columns;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// 向客户端循环发送动画帧，直到达到限制、服务关闭或连接出错
+///
+/// 服务关闭时在两帧之间停止，不会截断正在发送的帧。
+async fn send_animation(
+    stream: &mut (impl AsyncWrite + Unpin),
+    (client_width, client_height): (u16, u16),
+    args: &Args,
+    color_mode: ColorMode,
+    mut animator: Animator,
+    mut shutdown: watch::Receiver<bool>,
+    events: &ConnectionEvents,
+) -> io::Result<()> {
+    // 每帧复用同一个缓冲区，清屏和帧内容一次写出
+    let mut frame_data = String::new();
+    // 增量模式下上一帧已发送的单元格，第一帧全量发送
+    let mut back_buffer = BackBuffer::default();
+    back_buffer.resize((client_width, client_height));
+    let mut diff_data = Vec::new();
+    if !args.no_diff && !args.no_clear {
+        diff_data.extend_from_slice(CLEAR_SEQUENCE.as_bytes());
+    }
 
     loop {
-        let frame_data = build_frame(client_width, client_height, args, frame_idx, start_time);
+        let data = if args.no_diff {
+            frame_data.clear();
+            animator.write_frame(
+                &mut frame_data,
+                client_width,
+                client_height,
+                args,
+                color_mode,
+            );
+            frame_data.as_bytes()
+        } else {
+            write_diff_frame(
+                &mut diff_data,
+                &mut back_buffer,
+                &animator,
+                (client_width, client_height),
+                args,
+                color_mode,
+            )?;
+            &diff_data
+        };
 
-        // 发送帧数据
-        stream.write_all(frame_data.as_bytes()).await?;
-        stream.flush().await?;
+        // 发送帧数据，只统计发送本身的耗时
+        let sent_at = std::time::Instant::now();
+        write_with_retry(stream, data).await?;
+        events.frame_sent(data.len() as u64);
+        diff_data.clear();
+        metrics::observe_send(Transport::Telnet, sent_at.elapsed());
+
+        if let Some(limit) = args.max_bytes
+            && events.bytes_sent() >= limit
+        {
+            info!(
+                "Sent {} bytes, reached the limit of {limit} bytes",
+                events.bytes_sent()
+            );
+            break;
+        }
+        let size = (animator.theme().width, animator.theme().height);
+        let more = tokio::select! {
+            more = animator.tick() => more,
+            _ = shutdown.wait_for(|shutdown| *shutdown) => {
+                events.close("shutdown");
+                false
+            }
+        };
+        if !more {
+            break;
+        }
+        // 重新加载的帧大小不同时清屏并全量重绘，避免残留旧的单元格
+        let reloaded = animator.theme();
+        if (reloaded.width, reloaded.height) != size {
+            back_buffer.invalidate();
+            if !args.no_diff && !args.no_clear {
+                diff_data.extend_from_slice(CLEAR_SEQUENCE.as_bytes());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 只输出与上一帧不同的单元格，用光标定位代替清屏，计数器每帧重绘
+fn write_diff_frame(
+    out: &mut Vec<u8>,
+    back_buffer: &mut BackBuffer,
+    animator: &Animator,
+    size: (u16, u16),
+    args: &Args,
+    color_mode: ColorMode,
+) -> io::Result<()> {
+    render_frame(
+        out,
+        back_buffer,
+        animator.frame(),
+        &animator.render_size(size.0, size.1),
+        &animator.render_options(args, color_mode),
+    )?;
+    if !args.no_counter {
+        write_counter(
+            out,
+            args,
+            animator.theme(),
+            color_mode,
+            animator.start_time(),
+            size,
+            animator.rendered(),
+        )?;
+    }
+    Ok(())
+}
 
-        // 控制帧率
-        sleep(Duration::from_millis(100)).await;
+/// 写入一帧并刷新
+///
+/// `Interrupted` 和 `WouldBlock` 属于临时错误，从已写入的位置继续，
+/// 连续重试超过 [`MAX_WRITE_RETRIES`] 次后放弃；其他错误（如客户端断开）立即返回。
+async fn write_with_retry(stream: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+    let mut retries = 0;
+    loop {
+        // 数据写完后再刷新，刷新成功即发送完成
+        let result = if written < data.len() {
+            match stream.write(&data[written..]).await {
+                Ok(0) => Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    written += n;
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            stream.flush().await.map(|()| true)
+        };
 
-        // 下一帧
-        frame_idx = (frame_idx + 1) % FRAMES.len();
+        match result {
+            Ok(true) => return Ok(()),
+            Ok(false) => retries = 0,
+            Err(e) if is_retryable(&e) && retries < MAX_WRITE_RETRIES => {
+                retries += 1;
+                debug!("Transient write error, retrying ({retries}/{MAX_WRITE_RETRIES}): {e}");
+                sleep(WRITE_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
     }
 }
 
+fn is_retryable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
 /// 解析Telnet客户端发送的协议命令
-/// 提取窗口大小信息并更新到width和height
-/// 成功获取窗口大小后返回true
-fn parse_telnet_commands(data: &[u8], width: &mut u16, height: &mut u16) -> bool {
+/// 提取窗口大小和终端类型信息并更新到client
+///
+/// 返回是否成功获取窗口大小，以及已处理的字节数。
+/// 末尾不完整的命令不计入已处理的字节，调用方应保留这部分数据，与下一次读取的数据拼接后重新解析。
+fn parse_telnet_commands(data: &[u8], client: &mut ClientInfo) -> (bool, usize) {
     let mut i = 0;
     let data_len = data.len();
+    let mut got_size = false;
 
     while i < data_len {
-        // 查找Telnet命令标记(IAC)
-        if data[i] == IAC && i + 1 < data_len {
-            match data[i + 1] {
-                // 处理子协商命令
-                SB => {
-                    // 确保有足够的字节进行解析
-                    if i + 2 >= data_len {
-                        break;
-                    }
-
-                    let option = data[i + 2];
-                    i += 3; // 跳过IAC, SB, option
-
-                    // 处理窗口大小子协商
-                    if option == NAWS {
-                        // NAWS需要4字节数据(宽度高8位、宽度低8位、高度高8位、高度低8位)
-                        if i + 4 <= data_len {
-                            *width = ((data[i] as u16) << 8) | data[i + 1] as u16;
-                            *height = ((data[i + 2] as u16) << 8) | data[i + 3] as u16;
+        // 非命令数据：向前移动1字节
+        if data[i] != IAC {
+            i += 1;
+            continue;
+        }
+        // 查找Telnet命令标记(IAC)，命令被截断时等待后续数据
+        if i + 1 >= data_len {
+            break;
+        }
 
-                            // 跳过数据并寻找子协商结束标记
-                            i += 4;
-                            while i + 1 < data_len && !(data[i] == IAC && data[i + 1] == SE) {
-                                i += 1;
-                            }
+        match data[i + 1] {
+            // 处理子协商命令
+            SB => {
+                // 确保有足够的字节进行解析
+                if i + 2 >= data_len {
+                    break;
+                }
 
-                            // 跳过SE标记
-                            // if i + 1 < data_len {
-                            //     i += 2;
-                            // }
+                let option = data[i + 2];
+                let mut j = i + 3; // 跳过IAC, SB, option
 
-                            return true; // 成功获取窗口大小
+                // 寻找子协商结束标记 IAC SE，数据中的 255 转义为 IAC IAC
+                let mut payload = Vec::new();
+                let mut terminated = false;
+                while j < data_len {
+                    if data[j] == IAC && j + 1 < data_len {
+                        if data[j + 1] == SE {
+                            terminated = true;
+                            j += 2;
+                            break;
                         }
-                    }
-                    // 处理终端类型子协商（仅跳过，不处理具体类型）
-                    else if option == TTYPE {
-                        // 跳过终端类型数据直到子协商结束
-                        while i + 1 < data_len && !(data[i] == IAC && data[i + 1] == SE) {
-                            i += 1;
-                        }
-                        // 跳过SE标记
-                        if i + 1 < data_len {
-                            i += 2;
+                        if data[j + 1] == IAC {
+                            payload.push(IAC);
+                            j += 2;
+                            continue;
                         }
                     }
-                    // 其他子协商类型：直接跳到结束
-                    else {
-                        while i + 1 < data_len && !(data[i] == IAC && data[i + 1] == SE) {
-                            i += 1;
-                        }
-                        if i + 1 < data_len {
-                            i += 2;
-                        }
+                    payload.push(data[j]);
+                    j += 1;
+                }
+                // 没有结束标记的子协商不完整，等待后续数据
+                if !terminated {
+                    break;
+                }
+                i = j;
+
+                // 处理窗口大小子协商
+                if option == NAWS {
+                    // NAWS需要4字节数据(宽度高8位、宽度低8位、高度高8位、高度低8位)
+                    if let [w_hi, w_lo, h_hi, h_lo] = payload[..] {
+                        client.width = u16::from_be_bytes([w_hi, w_lo]);
+                        client.height = u16::from_be_bytes([h_hi, h_lo]);
+                        got_size = true; // 成功获取窗口大小
+                    }
+                }
+                // 处理终端类型子协商：IS + 终端名称
+                else if option == TTYPE
+                    && let Some((&TTYPE_IS, name)) = payload.split_first()
+                {
+                    let name = String::from_utf8_lossy(name).trim().to_string();
+                    if !name.is_empty() {
+                        client.term = Some(name);
                     }
                 }
+            }
 
-                // 其他Telnet命令：跳过3字节(IAC + cmd + opt)
-                _ => {
-                    i += 3;
+            // 选项协商缺少选项字节，等待后续数据
+            DO | DONT | WILL | WONT if i + 2 >= data_len => break,
+
+            // 客户端对终端类型选项的应答
+            WILL | WONT if data[i + 2] == TTYPE => {
+                if data[i + 1] == WILL {
+                    client.ttype_accepted = true;
+                } else {
+                    client.ttype_refused = true;
                 }
+                i += 3;
+            }
+
+            // 其他选项协商：跳过3字节(IAC + cmd + opt)
+            DO | DONT | WILL | WONT => {
+                i += 3;
+            }
+
+            // 其他Telnet命令和转义的 IAC：跳过2字节
+            _ => {
+                i += 2;
             }
-        }
-        // 非命令数据：向前移动1字节
-        else {
-            i += 1;
         }
     }
 
-    false
+    (got_size, i)
 }
 
 // 运行Telnet服务器
-pub async fn run_telnet_server(args: &Args) -> io::Result<()> {
+pub async fn run_telnet_server(args: &Args, context: ServerContext) -> anyhow::Result<()> {
+    let mut args = args.clone();
+    if let Some(path) = &args.banner_file {
+        let banner = std::fs::read_to_string(path)
+            .with_context(|| format!("read banner file {} failed", path.display()))?;
+        args.banner = Some(banner);
+    }
+    let args = &args;
+
     let addr = format!("0.0.0.0:{}", args.port);
-    let listener = TcpListener::bind(&addr).await?;
-    println!("Telnet server running on {}", addr);
+    #[cfg(feature = "tls")]
+    let acceptor = crate::tls::acceptor(args)?;
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        // 1024 以下的端口需要特权，非 root 用户绑定默认的 23 端口会失败
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            anyhow::bail!(
+                "Permission denied when binding {addr}, try a port above 1024 (e.g. `--port 2323`) or run with elevated privileges"
+            );
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context(format!("Could not bind {addr}")));
+        }
+    };
+    if args.raw_tcp {
+        info!("Raw TCP server running on {}", addr);
+    } else {
+        info!("Telnet server running on {}", addr);
+    }
+
+    // 关闭时等待所有连接恢复客户端终端后再返回
+    let mut sessions = JoinSet::new();
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            // 回收已结束的连接
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => continue,
+            () = context.shutdown_requested() => break,
+        };
         let cli_args = args.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_telnet_client(stream, &cli_args).await {
-                eprintln!("Telnet client error: {}", e);
+        let context = context.clone();
+        let dump = context.dump.clone();
+        // 每个连接的日志都带上客户端地址
+        let span = info_span!("telnet", peer = %peer);
+        #[cfg(feature = "tls")]
+        let acceptor = acceptor.clone();
+        sessions.spawn(
+            async move {
+                // 任务结束或被中止时发出断开事件
+                let events = ConnectionEvents::connect(Transport::Telnet, peer);
+                peer::log_hostname(peer.ip(), cli_args.resolve_hostnames);
+
+                #[cfg(feature = "tls")]
+                let result = match acceptor {
+                    Some(acceptor) => {
+                        // 与 HTTP 一样限制握手时间，握手失败多半是扫描器，只给出警告
+                        let handshake = acceptor.accept(stream);
+                        match timeout(crate::tls::TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                            Ok(Ok(stream)) => {
+                                handle_telnet_client(
+                                    Tee::new(stream, dump),
+                                    &cli_args,
+                                    context,
+                                    &events,
+                                )
+                                .await
+                            }
+                            Ok(Err(e)) => {
+                                events.close("error");
+                                warn!("TLS handshake with {peer} failed: {e}");
+                                return;
+                            }
+                            Err(_) => {
+                                events.close("timeout");
+                                warn!("TLS handshake with {peer} timed out");
+                                return;
+                            }
+                        }
+                    }
+                    None => {
+                        handle_telnet_client(Tee::new(stream, dump), &cli_args, context, &events)
+                            .await
+                    }
+                };
+                #[cfg(not(feature = "tls"))]
+                let result =
+                    handle_telnet_client(Tee::new(stream, dump), &cli_args, context, &events).await;
+
+                finish(&events, result);
+            }
+            .instrument(span),
+        );
+    }
+
+    info!("Resetting {} telnet clients", sessions.len());
+    let drain = async { while sessions.join_next().await.is_some() {} };
+    if timeout(SHUTDOWN_TIMEOUT, drain).await.is_err() {
+        warn!("Telnet clients did not finish in time, disconnecting them");
+    }
+    Ok(())
+}
+
+/// 记录连接结束的原因
+fn finish(events: &ConnectionEvents, result: io::Result<()>) {
+    match result {
+        Ok(()) => {
+            events.close("finished");
+            info!("Telnet client finished");
+        }
+        // 客户端主动断开属于正常结束
+        Err(e) if is_disconnect(&e) => {
+            events.close("client_closed");
+            info!("Telnet client disconnected: {}", e);
+        }
+        Err(e) => {
+            events.close("error");
+            error!("Telnet client error: {}", e);
+        }
+    }
+}
+
+/// 判断错误是否由客户端断开连接引起
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+
+    fn test_events() -> ConnectionEvents {
+        ConnectionEvents::connect(Transport::Telnet, ([127, 0, 0, 1], 0).into())
+    }
+
+    /// 解析一段数据，返回是否获取到窗口大小和解析后的客户端信息
+    fn parse(data: &[u8]) -> (bool, ClientInfo) {
+        let mut client = ClientInfo::new(80, 24);
+        let (got_size, _) = parse_telnet_commands(data, &mut client);
+        (got_size, client)
+    }
+
+    #[test]
+    fn parse_clean_naws() {
+        let (got_size, client) = parse(&[IAC, SB, NAWS, 0, 100, 0, 40, IAC, SE]);
+        assert!(got_size);
+        assert_eq!((client.width, client.height), (100, 40));
+    }
+
+    #[test]
+    fn parse_naws_with_trailing_data() {
+        let (got_size, client) = parse(&[IAC, SB, NAWS, 1, 0, 0, 50, IAC, SE, b'h', b'i']);
+        assert!(got_size);
+        assert_eq!((client.width, client.height), (256, 50));
+    }
+
+    #[test]
+    fn parse_interleaved_ttype_and_naws() {
+        let mut data = vec![IAC, WILL, TTYPE, IAC, WILL, NAWS];
+        data.extend_from_slice(&[IAC, SB, TTYPE, TTYPE_IS]);
+        data.extend_from_slice(b"xterm-256color");
+        data.extend_from_slice(&[IAC, SE, IAC, SB, NAWS, 0, 120, 0, 30, IAC, SE]);
+        let (got_size, client) = parse(&data);
+        assert!(got_size);
+        assert!(client.ttype_accepted);
+        assert_eq!(client.term.as_deref(), Some("xterm-256color"));
+        assert_eq!((client.width, client.height), (120, 30));
+    }
+
+    #[test]
+    fn parse_iac_at_end_of_buffer() {
+        let (got_size, client) = parse(&[IAC]);
+        assert!(!got_size);
+        assert_eq!((client.width, client.height), (80, 24));
+
+        let (got_size, client) = parse(&[IAC, SB, NAWS, 0, 90, 0, 30, IAC, SE, IAC]);
+        assert!(got_size);
+        assert_eq!((client.width, client.height), (90, 30));
+    }
+
+    #[test]
+    fn parse_truncated_subnegotiation() {
+        for data in [
+            &[IAC, SB][..],
+            &[IAC, SB, NAWS, 0, 100][..],
+            &[IAC, SB, NAWS, 0, 100, 0, 40][..],
+            &[IAC, SB, NAWS, 0, 100, 0, 40, IAC][..],
+        ] {
+            let (got_size, client) = parse(data);
+            assert!(!got_size, "{data:?}");
+            assert_eq!((client.width, client.height), (80, 24), "{data:?}");
+        }
+    }
+
+    #[test]
+    fn parse_keeps_incomplete_command() {
+        let mut client = ClientInfo::new(80, 24);
+        let data = [IAC, WILL, TTYPE, IAC, SB, NAWS, 0, 100];
+        assert_eq!(parse_telnet_commands(&data, &mut client), (false, 3));
+        assert_eq!(
+            parse_telnet_commands(&[b'x', IAC, DO], &mut client),
+            (false, 1)
+        );
+        assert_eq!(parse_telnet_commands(&[IAC, IAC], &mut client), (false, 2));
+    }
+
+    #[test]
+    fn parse_malformed_naws_length() {
+        let (got_size, client) = parse(&[IAC, SB, NAWS, 0, 100, IAC, SE]);
+        assert!(!got_size);
+        assert_eq!((client.width, client.height), (80, 24));
+    }
+
+    #[test]
+    fn parse_escaped_iac_in_naws() {
+        // 宽度 255 和高度 0xFFF0，其中 0xF0 与 SE 的值相同
+        let (got_size, client) = parse(&[IAC, SB, NAWS, 0, IAC, IAC, IAC, IAC, SE, IAC, SE]);
+        assert!(got_size);
+        assert_eq!((client.width, client.height), (255, 0xFFF0));
+    }
+
+    #[test]
+    fn parse_two_byte_command_keeps_following_data() {
+        const NOP: u8 = 241;
+        let (got_size, client) = parse(&[IAC, NOP, IAC, SB, NAWS, 0, 100, 0, 40, IAC, SE]);
+        assert!(got_size);
+        assert_eq!((client.width, client.height), (100, 40));
+    }
+
+    /// 先依次返回指定的错误，之后每次最多写入 4 字节
+    struct FlakyWriter {
+        errors: Vec<io::ErrorKind>,
+        written: Vec<u8>,
+    }
+
+    impl AsyncWrite for FlakyWriter {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            if !self.errors.is_empty() {
+                let kind = self.errors.remove(0);
+                return std::task::Poll::Ready(Err(kind.into()));
             }
+            let n = buf.len().min(4);
+            self.written.extend_from_slice(&buf[..n]);
+            std::task::Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn write_retries_transient_errors() {
+        use io::ErrorKind::*;
+
+        let mut writer = FlakyWriter {
+            errors: vec![Interrupted, WouldBlock],
+            written: Vec::new(),
+        };
+        write_with_retry(&mut writer, b"nyan nyan").await.unwrap();
+        assert_eq!(writer.written, b"nyan nyan");
+
+        let mut writer = FlakyWriter {
+            errors: vec![Interrupted; MAX_WRITE_RETRIES as usize + 1],
+            written: Vec::new(),
+        };
+        let err = write_with_retry(&mut writer, b"nyan").await.unwrap_err();
+        assert_eq!(err.kind(), Interrupted);
+
+        let mut writer = FlakyWriter {
+            errors: vec![BrokenPipe],
+            written: Vec::new(),
+        };
+        let err = write_with_retry(&mut writer, b"nyan").await.unwrap_err();
+        assert_eq!(err.kind(), BrokenPipe);
+        assert!(writer.written.is_empty());
+    }
+
+    #[test]
+    fn wrap_text_fits_width() {
+        assert_eq!(
+            wrap_text("Welcome to nyancat\n\nenjoy", 10),
+            ["Welcome to", "nyancat", "", "enjoy"]
+        );
+        assert_eq!(wrap_text("nyanyanyan", 4), ["nyan", "yany", "an"]);
+        assert_eq!(
+            wrap_text("欢迎来到 nyancat", 7),
+            ["欢迎来", "到", "nyancat"]
+        );
+        assert_eq!(wrap_text("🐱🐱🐱 cat", 5), ["🐱🐱", "🐱", "cat"]);
+    }
+
+    #[tokio::test]
+    async fn negotiate_reads_window_size() {
+        let (mut server, mut client) = duplex(1024);
+        let negotiation = tokio::spawn(async move { negotiate(&mut server, 80, 24, 1024).await });
+
+        let mut handshake = [0; 9];
+        client.read_exact(&mut handshake).await.unwrap();
+        assert_eq!(handshake, [IAC, WILL, SGA, IAC, DO, TTYPE, IAC, DO, NAWS]);
+
+        // 拒绝终端类型，上报 100x40
+        client
+            .write_all(&[IAC, WONT, TTYPE, IAC, SB, NAWS, 0, 100, 0, 40, IAC, SE])
+            .await
+            .unwrap();
+
+        let info = negotiation.await.unwrap().unwrap();
+        assert_eq!((info.width, info.height), (100, 40));
+        assert_eq!(info.term, None);
+    }
+
+    #[tokio::test]
+    async fn negotiate_requests_terminal_type() {
+        let (mut server, mut client) = duplex(1024);
+        let negotiation = tokio::spawn(async move { negotiate(&mut server, 80, 24, 1024).await });
+
+        let mut handshake = [0; 9];
+        client.read_exact(&mut handshake).await.unwrap();
+        client
+            .write_all(&[IAC, WILL, TTYPE, IAC, SB, NAWS, 0, 120, 0, 50, IAC, SE])
+            .await
+            .unwrap();
+
+        // 客户端同意后服务端请求终端类型
+        let mut request = [0; 6];
+        client.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, [IAC, SB, TTYPE, TTYPE_SEND, IAC, SE]);
+
+        let mut reply = vec![IAC, SB, TTYPE, TTYPE_IS];
+        reply.extend_from_slice(b"xterm-direct");
+        reply.extend_from_slice(&[IAC, SE]);
+        client.write_all(&reply).await.unwrap();
+
+        let info = negotiation.await.unwrap().unwrap();
+        assert_eq!((info.width, info.height), (120, 50));
+        assert_eq!(info.term.as_deref(), Some("xterm-direct"));
+        assert_eq!(info.color_mode(), ColorMode::TrueColor);
+    }
+
+    #[tokio::test]
+    async fn oversized_terminal_type_reaches_animation() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["nyancat", "--telnet-read-size", "16"]);
+        let (mut server, mut client) = duplex(64 * 1024);
+        let session = tokio::spawn(async move {
+            let (_tx, frame_delay) = watch::channel(args.frame_delay());
+            let _ = handle_telnet_client(
+                &mut server,
+                &args,
+                ServerContext::from_frame_delay(frame_delay),
+                &test_events(),
+            )
+            .await;
+        });
+
+        let mut handshake = [0; 9];
+        client.read_exact(&mut handshake).await.unwrap();
+        client.write_all(&[IAC, WILL, TTYPE]).await.unwrap();
+        let mut request = [0; 6];
+        client.read_exact(&mut request).await.unwrap();
+
+        // 终端类型远超单次读取的长度，窗口大小紧随其后
+        let mut reply = vec![IAC, SB, TTYPE, TTYPE_IS];
+        reply.extend_from_slice("x".repeat(4000).as_bytes());
+        reply.extend_from_slice(&[IAC, SE, IAC, SB, NAWS, 0, 132, 0, 43, IAC, SE]);
+        client.write_all(&reply).await.unwrap();
+
+        // 握手完成后收到第一帧
+        let mut frame = vec![0; CLEAR_SEQUENCE.len()];
+        timeout(Duration::from_secs(1), client.read_exact(&mut frame))
+            .await
+            .expect("animation did not start")
+            .unwrap();
+        assert_eq!(frame, CLEAR_SEQUENCE.as_bytes());
+        session.abort();
+    }
+
+    #[tokio::test]
+    async fn raw_tcp_streams_without_negotiation() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["nyancat", "-t", "--raw-tcp"]);
+        let (mut server, mut client) = duplex(64 * 1024);
+        let session = tokio::spawn(async move {
+            let (_tx, frame_delay) = watch::channel(args.frame_delay());
+            let _ = handle_telnet_client(
+                &mut server,
+                &args,
+                ServerContext::from_frame_delay(frame_delay),
+                &test_events(),
+            )
+            .await;
         });
+
+        // 客户端什么都不发送，动画直接开始
+        let mut output = vec![0; 4096];
+        timeout(Duration::from_secs(1), client.read_exact(&mut output))
+            .await
+            .expect("animation did not start")
+            .unwrap();
+        assert!(output.starts_with(CLEAR_SEQUENCE.as_bytes()));
+        assert!(
+            !output.contains(&IAC),
+            "telnet commands leaked into the output"
+        );
+        session.abort();
+    }
+
+    #[tokio::test]
+    async fn shutdown_resets_connected_clients() {
+        use clap::Parser;
+        use tokio::net::TcpStream;
+
+        let port = std::net::TcpListener::bind("0.0.0.0:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let port_arg = port.to_string();
+        let args = Args::parse_from(["nyancat", "-t", "--raw-tcp", "--port", &port_arg]);
+        let context = ServerContext::from_frame_delay(watch::channel(args.frame_delay()).1);
+        let server = tokio::spawn({
+            let context = context.clone();
+            async move { run_telnet_server(&args, context).await }
+        });
+
+        let mut client = None;
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                client = Some(stream);
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        let mut client = client.expect("server did not start");
+        let mut frame = [0; 4096];
+        assert!(client.read(&mut frame).await.unwrap() > 0);
+
+        // 关闭后连接发送完当前帧，恢复终端后断开，服务随之返回
+        context.shutdown();
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut output))
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+        assert!(output.ends_with(format!("{RESET_SEQUENCE}{CLEAR_SEQUENCE}").as_bytes()));
+        timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_bytes_closes_connection() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["nyancat", "-t", "--raw-tcp", "--max-bytes", "20000"]);
+        let (server, mut client) = duplex(64 * 1024);
+        let session = tokio::spawn(async move {
+            let (_tx, frame_delay) = watch::channel(args.frame_delay());
+            handle_telnet_client(
+                server,
+                &args,
+                ServerContext::from_frame_delay(frame_delay),
+                &test_events(),
+            )
+            .await
+        });
+
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut output))
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+        session.await.unwrap().unwrap();
+        // 超出的部分不超过一帧，结束时恢复终端
+        assert!((20000..40000).contains(&output.len()), "{}", output.len());
+        assert!(output.ends_with(format!("{RESET_SEQUENCE}{CLEAR_SEQUENCE}").as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn shutdown_skips_banner_wait() {
+        use clap::Parser;
+
+        let args = Args::parse_from([
+            "nyancat",
+            "-t",
+            "--raw-tcp",
+            "--banner",
+            "nyan",
+            "--banner-duration",
+            "3600",
+        ]);
+        let context = ServerContext::from_frame_delay(watch::channel(args.frame_delay()).1);
+        let (server, mut client) = duplex(64 * 1024);
+        let session = tokio::spawn({
+            let context = context.clone();
+            async move { handle_telnet_client(server, &args, context, &test_events()).await }
+        });
+
+        let mut banner = [0; 256];
+        let read = client.read(&mut banner).await.unwrap();
+        assert!(String::from_utf8_lossy(&banner[..read]).contains("nyan"));
+
+        context.shutdown();
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut output))
+            .await
+            .expect("banner wait was not interrupted")
+            .unwrap();
+        session.await.unwrap().unwrap();
+        assert!(output.ends_with(format!("{RESET_SEQUENCE}{CLEAR_SEQUENCE}").as_bytes()));
+    }
+
+    /// 客户端读到第一段数据后发送 RST 断开，返回记录的日志
+    async fn reset_by_client(argv: &[&str]) -> crate::conn::tests::Recorder {
+        use clap::Parser;
+        use tokio::net::{TcpListener, TcpStream};
+
+        use crate::conn::tests::Recorder;
+
+        let recorder = Recorder::default();
+        let _guard = recorder.install();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+
+        let args = Args::parse_from(argv);
+        let (_tx, frame_delay) = watch::channel(args.frame_delay());
+        let server = async {
+            let events = ConnectionEvents::connect(Transport::Telnet, peer);
+            let result = handle_telnet_client(
+                stream,
+                &args,
+                ServerContext::from_frame_delay(frame_delay),
+                &events,
+            )
+            .await;
+            finish(&events, result);
+        };
+        // SO_LINGER 为 0 时关闭连接发送 RST
+        let client = async {
+            let mut buf = [0; 4096];
+            assert!(client.read(&mut buf).await.unwrap() > 0);
+            client.set_linger(Some(Duration::ZERO)).unwrap();
+            drop(client);
+        };
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(server, client)
+        })
+        .await
+        .expect("server did not notice the disconnect");
+        recorder
+    }
+
+    #[tokio::test]
+    async fn abrupt_disconnect_is_not_an_error() {
+        // 动画播放中断开，以及 Telnet 握手期间断开（如端口扫描）
+        let cases: [&[&str]; 2] = [
+            &["nyancat", "-t", "--raw-tcp", "--fps", "50"],
+            &["nyancat", "-t"],
+        ];
+        for argv in cases {
+            let recorder = reset_by_client(argv).await;
+            assert_eq!(
+                recorder.at_least(tracing::Level::ERROR),
+                Vec::<String>::new(),
+                "{argv:?}"
+            );
+            let disconnect = recorder.fields("nyancat::conn").pop().unwrap();
+            assert!(
+                disconnect.contains("reason=\"client_closed\""),
+                "{argv:?}: {disconnect}"
+            );
+        }
     }
 }
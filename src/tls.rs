@@ -0,0 +1,117 @@
+#[cfg(feature = "http")]
+use std::{io, net::SocketAddr};
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+#[cfg(feature = "http")]
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::timeout,
+};
+#[cfg(feature = "http")]
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+#[cfg(feature = "http")]
+use tracing::{error, warn};
+
+use crate::cli::Args;
+
+/// TLS 握手超时时间，Telnet 和 HTTP 共用
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 根据 `--tls-cert` 和 `--tls-key` 创建 TLS acceptor，未设置时返回 `None`
+pub fn acceptor(args: &Args) -> anyhow::Result<Option<TlsAcceptor>> {
+    let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) else {
+        return Ok(None);
+    };
+    load_acceptor(cert, key).map(Some)
+}
+
+/// 读取 PEM 格式的证书链和私钥
+fn load_acceptor(cert: &Path, key: &Path) -> anyhow::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .with_context(|| format!("read TLS certificate {} failed", cert.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("parse TLS certificate {} failed", cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("read TLS private key {} failed", key.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .with_context(|| "invalid TLS certificate or private key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// 在 TCP 之上完成 TLS 握手的 HTTP 监听器
+///
+/// 握手在独立任务中进行，慢速客户端不会阻塞其他连接。
+#[cfg(feature = "http")]
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    rx: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+#[cfg(feature = "http")]
+impl TlsListener {
+    pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            // 监听器被丢弃后停止接受连接
+            while !tx.is_closed() {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Accept error: {e}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => warn!("TLS handshake with {addr} failed: {e}"),
+                        Err(_) => warn!("TLS handshake with {addr} timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(Self { local_addr, rx })
+    }
+}
+
+#[cfg(feature = "http")]
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.rx.recv().await {
+            Some(accepted) => accepted,
+            // 接受连接的任务不会在监听器存在时退出
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}